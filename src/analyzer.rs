@@ -1,6 +1,7 @@
 use crate::{parser::Node, tokenizer::TokenType};
 use std::collections::HashMap;
 use crate::generator::Value;
+use crate::error::NairError;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
@@ -8,75 +9,243 @@ pub enum Type {
     Decimal,    // Float type
     Text,       // String type
     Truth,      // Boolean type
-    Nothing,       // Null type
+    Nothing,    // null, or what a Task without output gives
     Error,      // Error type
     Any,        // Any type (used for variables without type annotation)
     Object,     // Object type
     List(Box<Type>),
     Map { key: Box<Type>, value: Box<Type> },
     Promise(Box<Type>),
+    Record(Vec<(String, Type)>),  // the fields of a record literal, in order
+}
+
+// What the analyzer knows about a name
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub typ: Type,
+    pub inferred: bool,  // declared without a type, so assigning a different kind of value is allowed
+    pub mutable: bool,  // false for `fixed` names
+}
+
+// The parameter and return types of a declared Task, Any where none was given
+#[derive(Debug, Clone)]
+struct TaskSignature {
+    params: Vec<Type>,
+    variadic: bool,  // the last parameter collects the remaining arguments into a List
+    returns: Type,
 }
 
 pub struct Analyzer {
-    pub variables: HashMap<String, Type>,
-    current_scope: Vec<HashMap<String, Type>>,
+    pub variables: HashMap<String, Variable>,
+    current_scope: Vec<HashMap<String, Variable>>,
     current_var_type: Option<Type>,
+    tasks: HashMap<String, TaskSignature>,
+    return_types: Vec<(String, Type)>,  // the Tasks whose bodies are being checked, innermost last
 }
 
 impl Analyzer {
     pub fn new() -> Self {
         Analyzer {
             variables: HashMap::new(),
-            current_scope: vec![HashMap::new()],
+            current_scope: Vec::new(),
             current_var_type: None,
+            tasks: HashMap::new(),
+            return_types: Vec::new(),
         }
     }
 
-    pub fn analyze(&mut self, nodes: &[Node]) -> Result<(), String> {
+    pub fn analyze(&mut self, nodes: &[Node]) -> Result<(), NairError> {
         for node in nodes {
-            self.check_node(node)?;
+            self.check_node(node).map_err(NairError::Type)?;
         }
         Ok(())
     }
 
+    // Block scopes sit on top of `variables`, which holds the top-level names
+    fn enter_scope(&mut self) {
+        self.current_scope.push(HashMap::new());
+    }
+
+    fn exit_scope(&mut self) {
+        self.current_scope.pop();
+    }
+
+    fn declare(&mut self, name: &str, typ: Type) {
+        self.bind(name, Variable { typ, inferred: false, mutable: true });
+    }
+
+    fn bind(&mut self, name: &str, variable: Variable) {
+        match self.current_scope.last_mut() {
+            Some(scope) => scope.insert(name.to_string(), variable),
+            None => self.variables.insert(name.to_string(), variable),
+        };
+    }
+
+    // Assigning to an inferred variable changes its type. The assignment may only happen
+    // on some paths, so a different type leaves it unknown
+    fn rebind(&mut self, name: &str, typ: &Type) {
+        let variable = self.current_scope.iter_mut().rev()
+            .find_map(|scope| scope.get_mut(name))
+            .or_else(|| self.variables.get_mut(name));
+        if let Some(variable) = variable {
+            if &variable.typ != typ {
+                variable.typ = Type::Any;
+            }
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Type> {
+        self.lookup_variable(name).map(|variable| &variable.typ)
+    }
+
+    fn lookup_variable(&self, name: &str) -> Option<&Variable> {
+        self.current_scope.iter().rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.variables.get(name))
+    }
+
     fn check_node(&mut self, node: &Node) -> Result<Type, String> {
         match node {
             Node::VariableDecl { name, type_annotation, initializer } => {
+                let existing = self.lookup_variable(name).cloned();
+                if existing.as_ref().is_some_and(|variable| !variable.mutable) {
+                    return Err(format!("Cannot reassign constant {}", name));
+                }
                 let declared_type = if let Some(type_node) = type_annotation {
                     let typ = self.type_from_annotation(type_node)?;
                     self.current_var_type = Some(typ.clone());
-                    typ
+                    Some(typ)
                 } else {
-                    Type::Any
+                    // Re-binding an existing variable keeps its declared type
+                    existing.as_ref().filter(|variable| !variable.inferred).map(|variable| variable.typ.clone())
                 };
 
-                if let Some(init) = initializer {
-                    let init_type = self.check_node(init)?;
-                    self.check_type_compatibility(&declared_type, &init_type)?;
+                let init_type = match initializer {
+                    Some(init) => Some(self.check_node(init)?),
+                    None => None,
+                };
+                if let (Some(declared), Some(init)) = (&declared_type, &init_type) {
+                    self.check_type_compatibility(declared, init)?;
                 }
-
                 self.current_var_type = None;
-                self.variables.insert(name.clone(), declared_type.clone());
+
+                let Some(declared_type) = declared_type else {
+                    // Without an annotation the variable takes its initializer's type
+                    let typ = init_type.unwrap_or(Type::Any);
+                    if existing.is_some() {
+                        self.rebind(name, &typ);
+                    } else {
+                        self.bind(name, Variable { typ: typ.clone(), inferred: true, mutable: true });
+                    }
+                    return Ok(typ);
+                };
+                // Re-binding updates the existing variable rather than shadowing it
+                if type_annotation.is_some() || existing.is_none() {
+                    self.declare(name, declared_type.clone());
+                }
                 Ok(declared_type)
             },
 
+            Node::MapExpr { var, iterable, transform, filter } => {
+                let element = match self.check_node(iterable)? {
+                    Type::List(element) => *element,
+                    Type::Any => Type::Any,
+                    other => return Err(format!("Only a List can be mapped over, got {:?}", other)),
+                };
+                self.enter_scope();
+                self.declare(var, element);
+                let result = filter.as_deref()
+                    .map_or(Ok(()), |filter| self.check_condition(filter, "Filter"))
+                    .and_then(|_| self.check_node(transform));
+                self.exit_scope();
+                Ok(Type::List(Box::new(result?)))
+            },
+
+            Node::EachLoop { var, index, iterable, body } => {
+                let element = match self.check_node(iterable)? {
+                    Type::List(element) => *element,
+                    Type::Any => Type::Any,
+                    other => return Err(format!("Only a List can be looped over, got {:?}", other)),
+                };
+                self.enter_scope();
+                self.declare(var, element);
+                self.declare(index, Type::Whole);
+                let result = self.check_node(body);
+                self.exit_scope();
+                result?;
+                Ok(Type::Nothing)
+            },
+
+            Node::WithExpr { base, args } => {
+                self.check_node(&Node::Call { callee: base.clone(), args: args.clone() })
+            },
+
+            Node::UsingExpr { base, name, body } => {
+                let typ = self.check_node(base)?;
+                self.enter_scope();
+                self.declare(name, typ);
+                let result = self.check_node(body);
+                self.exit_scope();
+                result?;
+                Ok(Type::Nothing)
+            },
+
+            Node::Block(statements, _) => {
+                self.enter_scope();
+                let result = statements.iter().try_for_each(|stmt| self.check_node(stmt).map(|_| ()));
+                self.exit_scope();
+                result?;
+                Ok(Type::Nothing)
+            },
+
+            Node::WhenStmt { condition, then_branch, else_branch } => {
+                self.check_condition(condition, "when")?;
+                self.check_node(then_branch)?;
+                if let Some(else_branch) = else_branch {
+                    self.check_node(else_branch)?;
+                }
+                Ok(Type::Nothing)
+            },
+
+            Node::LoopStmt { condition, body } => {
+                if let Some(condition) = condition {
+                    self.check_condition(condition, "loop")?;
+                }
+                self.check_node(body)?;
+                Ok(Type::Nothing)
+            },
+
+            Node::AssertStmt { condition, message } => {
+                self.check_condition(condition, "assert")?;
+                if let Some(message) = message {
+                    self.check_node(message)?;
+                }
+                Ok(Type::Nothing)
+            },
+
             Node::Literal(value) => {
                 Ok(match value {
-                    Value::Number(_) => Type::Whole,
+                    Value::Number(n) => if n.fract() == 0.0 { Type::Whole } else { Type::Decimal },
+                    Value::Decimal(_) => Type::Decimal,
                     Value::String(_) => Type::Text,
                     Value::Boolean(_) => Type::Truth,
-                    Value::Null => Type::Nothing,
-                    Value::Object(_) => Type::Object,
+                    Value::Null | Value::Nothing => Type::Nothing,
+                    Value::Instance(_) => Type::Object,
                     Value::Promise(_) => Type::Promise(Box::new(Type::Any)),
                     Value::List(_) => Type::List(Box::new(Type::Any)),
                     Value::Mapping(_) => Type::Map { key: Box::new(Type::Text), value: Box::new(Type::Any) },
                 })
             },
 
+            // Method and constructor bodies aren't analyzed, so any `me` seen here is outside one
+            Node::Me => Err("'me' can only be used inside a method or constructor".to_string()),
+            Node::PropertyAccess { object, .. } if matches!(**object, Node::Me) => {
+                Err("'my' can only be used inside a method or constructor".to_string())
+            },
+
             Node::Variable(name) => {
-                self.variables.get(name)
+                self.lookup(name)
                     .cloned()
-                    .or(Some(Type::Any))
                     .ok_or_else(|| format!("Undefined variable: {}", name))
             },
 
@@ -87,8 +256,10 @@ impl Analyzer {
                 use crate::tokenizer::TokenType;
                 match operator {
                     TokenType::Plus | TokenType::Minus | 
-                    TokenType::Multiply | TokenType::Divide => {
+                    TokenType::Multiply | TokenType::Divide |
+                    TokenType::Modulo | TokenType::Power => {
                         match (&left_type, &right_type) {
+                            (Type::Any, _) | (_, Type::Any) => Ok(Type::Any),
                             (Type::Whole, Type::Whole) => Ok(Type::Whole),
                             (Type::Decimal, _) | (_, Type::Decimal) => Ok(Type::Decimal),
                             (Type::Text, Type::Text) if matches!(operator, TokenType::Plus) => {
@@ -98,21 +269,196 @@ impl Analyzer {
                                            left_type, right_type))
                         }
                     },
+                    TokenType::Is | TokenType::Equals | TokenType::NotEquals => Ok(Type::Truth),
+                    TokenType::GreaterThan | TokenType::GreaterThanOrEqual |
+                    TokenType::LessThan | TokenType::LessThanOrEqual => {
+                        match (&left_type, &right_type) {
+                            (Type::Whole | Type::Decimal | Type::Any, Type::Whole | Type::Decimal | Type::Any) => {
+                                Ok(Type::Truth)
+                            },
+                            _ => Err(format!("Cannot compare {:?} and {:?}", left_type, right_type)),
+                        }
+                    },
+                    TokenType::Contains => {
+                        match (&left_type, &right_type) {
+                            (Type::List(_) | Type::Map { .. } | Type::Record(_) | Type::Any, _) => Ok(Type::Truth),
+                            (Type::Text, Type::Text | Type::Any) => Ok(Type::Truth),
+                            _ => Err(format!("{:?} cannot contain {:?}", left_type, right_type)),
+                        }
+                    },
+                    // A null left side gives way to the fallback
+                    TokenType::Otherwise => {
+                        match (&left_type, &right_type) {
+                            (Type::Nothing, _) => Ok(right_type),
+                            (_, Type::Nothing) => Ok(left_type),
+                            _ if left_type == right_type => Ok(left_type),
+                            _ => Ok(Type::Any),
+                        }
+                    },
+                    TokenType::And | TokenType::Or => {
+                        match (&left_type, &right_type) {
+                            (Type::Truth | Type::Any, Type::Truth | Type::Any) => Ok(Type::Truth),
+                            _ => Err(format!("Logical operands must be Truth, got {:?} and {:?}", 
+                                           left_type, right_type)),
+                        }
+                    },
                     _ => Err("Unsupported operator".to_string()),
                 }
             },
 
-            Node::ShowStmt(expr) => {
+            Node::Unary { operator, operand } => {
+                let operand_type = self.check_node(operand)?;
+                match operator {
+                    TokenType::Minus => match operand_type {
+                        Type::Whole | Type::Decimal | Type::Any => Ok(operand_type),
+                        _ => Err(format!("Cannot negate a value of type {:?}", operand_type)),
+                    },
+                    TokenType::Not => match operand_type {
+                        Type::Truth | Type::Any => Ok(Type::Truth),
+                        _ => Err(format!("'not' needs a Truth, got {:?}", operand_type)),
+                    },
+                    _ => Err("Unsupported unary operator".to_string()),
+                }
+            },
+
+            Node::Cast { value, target } => {
+                self.check_node(value)?;
+                match &**target {
+                    // Number covers both Whole and Decimal, so the result is only known at runtime
+                    Node::TypeAnnotation(type_name) if type_name == "Number" => Ok(Type::Any),
+                    _ => self.type_from_annotation(target),
+                }
+            },
+
+            // Only a record's fields are known; an object's aren't declared anywhere
+            Node::Get { object, name } | Node::PropertyAccess { object, property: name } => match self.check_node(object)? {
+                Type::Record(fields) => fields.into_iter()
+                    .find(|(field, _)| field == name)
+                    .map(|(_, typ)| typ)
+                    .ok_or_else(|| format!("The record has no field {}", name)),
+                _ => Ok(Type::Any),
+            },
+
+            Node::ObjectLiteral { fields } => {
+                let fields = fields.iter()
+                    .map(|(name, value)| Ok((name.clone(), self.check_node(value)?)))
+                    .collect::<Result<Vec<_>, String>>()?;
+                Ok(Type::Record(fields))
+            },
+
+            Node::Index { object, index } => {
+                let Type::List(element) = self.check_node(object)? else {
+                    return self.check_text_position(object, &[index]);
+                };
+                match self.check_node(index)? {
+                    Type::Whole | Type::Any => Ok(*element),
+                    other => Err(format!("List positions must be Whole, got {:?}", other)),
+                }
+            },
+
+            Node::Slice { object, start, end } => {
+                self.check_text_position(object, &[start, end])
+            },
+
+            Node::FixedDecl(decl) => {
+                let Node::VariableDecl { name, .. } = &**decl else {
+                    return Err(format!("Expected a declaration after 'fixed', got {:?}", decl));
+                };
+                match self.lookup_variable(name) {
+                    Some(variable) if !variable.mutable => return Err(format!("Cannot reassign constant {}", name)),
+                    Some(_) => return Err(format!("{} is already declared and cannot be made fixed", name)),
+                    None => {},
+                }
+                let typ = self.check_node(decl)?;
+                self.bind(name, Variable { typ: typ.clone(), inferred: false, mutable: false });
+                Ok(typ)
+            },
+
+            Node::TaskDecl { name, params, variadic, return_type, body, .. } => {
+                // Types the analyzer doesn't model, like Number, go unchecked
+                let declared = |annotation: Option<&Node>| annotation
+                    .and_then(|annotation| self.type_from_annotation(annotation).ok())
+                    .unwrap_or(Type::Any);
+                let mut names = Vec::new();
+                let mut types = Vec::new();
+                for param in params {
+                    if let Node::VariableDecl { name, type_annotation, .. } = param {
+                        names.push(name.clone());
+                        types.push(declared(type_annotation.as_deref()));
+                    }
+                }
+                let returns = declared(return_type.as_deref());
+                // Registered before the body so the Task can call itself
+                self.tasks.insert(name.clone(), TaskSignature { params: types.clone(), variadic: *variadic, returns: returns.clone() });
+
+                self.enter_scope();
+                for (param, typ) in names.iter().zip(types) {
+                    self.declare(param, typ);
+                }
+                self.return_types.push((name.clone(), returns));
+                let result = self.check_node(body);
+                self.return_types.pop();
+                self.exit_scope();
+                result?;
+                Ok(Type::Nothing)
+            },
+
+            Node::ReturnStmt(value) => {
+                let actual = self.check_node(value)?;
+                if let Some((task, expected)) = self.return_types.last() {
+                    self.check_type_compatibility(expected, &actual)
+                        .map_err(|e| format!("{} returns the wrong type: {}", task, e))?;
+                }
+                Ok(Type::Nothing)
+            },
+
+            // Arguments to a known Task are checked against its parameter types;
+            // anything else is left to the runtime
+            Node::Call { callee, args } => {
+                let arg_types = args.iter().map(|arg| self.check_node(arg)).collect::<Result<Vec<_>, String>>()?;
+                let Node::Variable(name) = &**callee else {
+                    return Ok(Type::Any);
+                };
+                if let Some(signature) = self.tasks.get(name) {
+                    let fixed = signature.params.len() - usize::from(signature.variadic);
+                    for (i, actual) in arg_types.iter().enumerate() {
+                        // Each argument collected by `each xs as List of T` must be a T
+                        let expected = match signature.params.get(i.min(fixed)) {
+                            Some(_) if i < fixed => &signature.params[i],
+                            Some(Type::List(element)) => element,
+                            Some(collected) => collected,
+                            None => break,
+                        };
+                        self.check_type_compatibility(expected, actual)
+                            .map_err(|e| format!("Argument {} of {}: {}", i + 1, name, e))?;
+                    }
+                    return Ok(signature.returns.clone());
+                }
+                Ok(Type::Any)
+            },
+
+            Node::Set { object, value, .. } => {
+                self.check_node(object)?;
+                self.check_node(value)?;
+                Ok(Type::Nothing)
+            },
+
+            Node::ShowStmt(values) => {
+                for value in values {
+                    self.check_node(value)?;
+                }
+                Ok(Type::Nothing)
+            },
+
+            Node::EmitStmt(expr) | Node::ExpressionStmt(expr) => {
                 self.check_node(expr)?;
                 Ok(Type::Nothing)
             },
 
             Node::StringInterpolation { parts } => {
+                // Every value has a text form, so any part will do
                 for part in parts {
-                    let part_type = self.check_node(part)?;
-                    if !matches!(part_type, Type::Text) {
-                        return Err("String interpolation parts must be convertible to text".to_string());
-                    }
+                    self.check_node(part)?;
                 }
                 Ok(Type::Text)
             },
@@ -120,18 +466,51 @@ impl Analyzer {
             Node::Assignment { name, value } => {
                 let value_type = self.check_node(value)?;
                 
-                if let Some(var_type) = self.variables.get(name) {
-                    if var_type != &Type::Any && var_type != &value_type {
-                        return Err(format!("Type mismatch: cannot assign {:?} to variable of type {:?}", 
-                                       value_type, var_type));
-                    }
-                } else {
-                    self.variables.insert(name.clone(), Type::Any);
+                match self.lookup_variable(name).cloned() {
+                    Some(variable) if !variable.mutable => return Err(format!("Cannot reassign constant {}", name)),
+                    Some(variable) if variable.inferred => self.rebind(name, &value_type),
+                    Some(variable) => {
+                        if variable.typ != Type::Any && variable.typ != value_type {
+                            return Err(format!("Type mismatch: cannot assign {:?} to variable of type {:?}", 
+                                           value_type, variable.typ));
+                        }
+                    },
+                    // Only `is` declares; updating needs something to update
+                    None => return Err(format!("Undefined variable: {}", name)),
                 }
 
                 Ok(value_type)
             },
 
+            Node::ArrayLiteral { elements, type_annotation } => {
+                // An explicit annotation (or the declared list type) fixes the element type
+                let expected = match type_annotation {
+                    Some(type_node) => match self.type_from_annotation(type_node)? {
+                        Type::List(element) => Some(*element),
+                        element => Some(element),
+                    },
+                    None => match &self.current_var_type {
+                        Some(Type::List(element)) => Some(element.as_ref().clone()),
+                        _ => None,
+                    },
+                };
+
+                let mut element_type: Option<Type> = None;
+                for element in elements {
+                    let actual = self.check_node(element)?;
+                    if let Some(expected) = &expected {
+                        self.check_type_compatibility(expected, &actual)?;
+                    }
+                    element_type = Some(match element_type {
+                        None => actual,
+                        Some(current) => Self::widen(current, actual),
+                    });
+                }
+
+                let element_type = expected.or(element_type).unwrap_or(Type::Any);
+                Ok(Type::List(Box::new(element_type)))
+            },
+
             Node::MappingLiteral { entries } => {
                 if entries.is_empty() {
                     return Ok(Type::Map {
@@ -148,12 +527,12 @@ impl Analyzer {
                 };
                 
                 // Check all entries
-                for (param_name, param_type, value) in entries {
+                for (_param_name, param_type, value) in entries {
                     let value_type = self.check_node(value)?;
                     
                     // If parameter has explicit type, check it
                     if let Some(type_node) = param_type {
-                        let declared_type = self.check_node(&type_node)?;
+                        let declared_type = self.check_node(type_node)?;
                         self.check_type_compatibility(&declared_type, &value_type)?;
                     }
                     
@@ -173,6 +552,37 @@ impl Analyzer {
         }
     }
 
+    // The narrowest type covering both; mixed element types fall back to Any
+    fn widen(a: Type, b: Type) -> Type {
+        match (a, b) {
+            (a, b) if a == b => a,
+            (Type::Whole, Type::Decimal) | (Type::Decimal, Type::Whole) => Type::Decimal,
+            _ => Type::Any,
+        }
+    }
+
+    // Indexing and slicing take whole-number positions into text and give back text
+    fn check_text_position(&mut self, object: &Node, positions: &[&Node]) -> Result<Type, String> {
+        match self.check_node(object)? {
+            Type::Text | Type::Any => {},
+            other => return Err(format!("Only Text can be indexed, got {:?}", other)),
+        }
+        for position in positions {
+            match self.check_node(position)? {
+                Type::Whole | Type::Any => {},
+                other => return Err(format!("Text positions must be Whole, got {:?}", other)),
+            }
+        }
+        Ok(Type::Text)
+    }
+
+    fn check_condition(&mut self, condition: &Node, context: &str) -> Result<(), String> {
+        match self.check_node(condition)? {
+            Type::Truth | Type::Any => Ok(()),
+            other => Err(format!("{} condition must be Truth, got {:?}", context, other)),
+        }
+    }
+
     fn type_from_annotation(&self, node: &Node) -> Result<Type, String> {
         match node {
             Node::TypeAnnotation(type_name) => {
@@ -180,60 +590,207 @@ impl Analyzer {
                     "Whole" => Ok(Type::Whole),
                     "Decimal" => Ok(Type::Decimal),
                     "Text" => Ok(Type::Text),
-                    "Truth" => Ok(Type::Truth),
+                    "Truth" | "Logic" => Ok(Type::Truth),
                     "Nothing" => Ok(Type::Nothing),
                     "Error" => Ok(Type::Error),
                     "Object" => Ok(Type::Object),
+                    "Any" => Ok(Type::Any),
+                    "List" => Ok(Type::List(Box::new(Type::Any))),
                     _ => Err(format!("Unknown type: {}", type_name)),
                 }
             },
+            Node::ListType { element_type } => {
+                Ok(Type::List(Box::new(self.type_from_annotation(element_type)?)))
+            },
+            Node::MappingType { value_type, .. } => Ok(Type::Map {
+                key: Box::new(Type::Text),
+                value: Box::new(self.type_from_annotation(value_type)?),
+            }),
             _ => Err("Invalid type annotation".to_string()),
         }
     }
 
     fn check_type_compatibility(&self, expected: &Type, actual: &Type) -> Result<(), String> {
-        if expected == actual || expected == &Type::Any {
+        // A whole number is also a valid decimal
+        if expected == actual || expected == &Type::Any
+            || (expected == &Type::Decimal && actual == &Type::Whole) {
             Ok(())
+        } else if let (Type::List(expected_element), Type::List(actual_element)) = (expected, actual) {
+            self.check_type_compatibility(expected_element, actual_element)
+        } else if let (Type::Map { value, .. }, Type::Record(fields)) = (expected, actual) {
+            // A record is a Mapping whose keys are its field names
+            fields.iter().try_for_each(|(_, field)| self.check_type_compatibility(value, field))
         } else {
             Err(format!("Type mismatch: expected {:?}, got {:?}", expected, actual))
         }
     }
+}
 
-    fn check_mapping(&mut self, entries: &[(String, Option<Node>, Node)]) -> Result<Type, String> {
-        for (_param_name, param_type, value) in entries {
-            // ... rest of the implementation
-        }
-        Ok(Type::Map {
-            key: Box::new(Type::Text),
-            value: Box::new(Type::Any),
-        })
-    }
-
-    fn token_type_to_type(&self, token_type: &TokenType) -> Type {
-        match token_type {
-            TokenType::TypeWhole => Type::Whole,
-            TokenType::TypeDecimal => Type::Decimal,
-            TokenType::TypeText => Type::Text,
-            TokenType::TypeLogic => Type::Truth,
-            TokenType::TypeNothing => Type::Nothing,
-            TokenType::Number(_) => Type::Decimal,  // Assuming all numbers are whole by default
-            TokenType::String(_) => Type::Text,
-            TokenType::Boolean(_) => Type::Truth,
-            TokenType::Null => Type::Nothing,
-            _ => Type::Any,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::tokenizer::Tokenizer;
+
+    fn analyze(analyzer: &mut Analyzer, source: &str) -> Result<(), String> {
+        let tokens = Tokenizer::new(source).tokenize().map_err(|e| e.to_string())?;
+        let nodes = Parser::new(tokens).parse().map_err(|e| e.to_string())?;
+        analyzer.analyze(&nodes).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn undeclared_variable_is_an_error() {
+        let result = analyze(&mut Analyzer::new(), "show y");
+        assert_eq!(result, Err("Undefined variable: y".to_string()));
+    }
+
+    #[test]
+    fn seeded_variable_is_declared() {
+        let mut analyzer = Analyzer::new();
+        analyzer.variables.insert("y".to_string(), Variable { typ: Type::Any, inferred: true, mutable: true });
+        assert_eq!(analyze(&mut analyzer, "show y"), Ok(()));
+    }
+
+    #[test]
+    fn negation_keeps_the_number_type() {
+        assert_eq!(analyze(&mut Analyzer::new(), "x as Whole is - -3\ny as Decimal is -2.5"), Ok(()));
+        assert!(analyze(&mut Analyzer::new(), "x as Whole is -2.5").is_err());
+        assert!(analyze(&mut Analyzer::new(), "x is -\"a\"").is_err());
+    }
+
+    #[test]
+    fn task_arguments_match_their_parameters() {
+        let task = "Task double requires n as Whole:\n    returns n * 2\n";
+        assert_eq!(analyze(&mut Analyzer::new(), &format!("{}x is double(4)", task)), Ok(()));
+        assert_eq!(
+            analyze(&mut Analyzer::new(), &format!("{}x is double(\"four\")", task)),
+            Err("Argument 1 of double: Type mismatch: expected Whole, got Text".to_string())
+        );
+        // Unknown callees are left to the runtime
+        assert_eq!(analyze(&mut Analyzer::new(), "x is length(\"four\")"), Ok(()));
+    }
+
+    #[test]
+    fn returns_match_the_declared_type() {
+        let source = "Task half requires n as Whole returning Decimal:\n    returns n / 2\nTask name returning Text:\n    output \"Ada\"\nx as Text is name()";
+        assert_eq!(analyze(&mut Analyzer::new(), source), Ok(()));
+        assert_eq!(
+            analyze(&mut Analyzer::new(), "Task count returning Whole:\n    returns \"three\""),
+            Err("count returns the wrong type: Type mismatch: expected Whole, got Text".to_string())
+        );
+        // The declared return type is what a call gives back
+        assert!(analyze(&mut Analyzer::new(), "Task one returning Whole:\n    returns 1\nx as Text is one()").is_err());
+    }
+
+    #[test]
+    fn records_know_their_field_types() {
+        assert_eq!(analyze(&mut Analyzer::new(), "p is {name: \"Ada\", age: 36}\nn as Whole is p.age"), Ok(()));
+        assert_eq!(
+            analyze(&mut Analyzer::new(), "p is {name: \"Ada\"}\nn as Whole is p.name"),
+            Err("Type mismatch: expected Whole, got Text".to_string())
+        );
+        assert_eq!(analyze(&mut Analyzer::new(), "p is {name: \"Ada\"}\nshow p.age"), Err("The record has no field age".to_string()));
+        // a record passes for a Mapping
+        assert_eq!(analyze(&mut Analyzer::new(), "Task size requires m as Mapping:\n    output m\nx is size({a: 1})"), Ok(()));
+    }
+
+    #[test]
+    fn fixed_names_cannot_be_reassigned() {
+        assert_eq!(analyze(&mut Analyzer::new(), "fixed PI as Decimal is 3.14159\narea as Decimal is PI * 2"), Ok(()));
+        for update in ["PI is 3", "PI becomes 3.0", "PI increase by 1", "fixed PI is 3"] {
+            let source = format!("fixed PI as Decimal is 3.14159\n{}", update);
+            assert_eq!(analyze(&mut Analyzer::new(), &source), Err("Cannot reassign constant PI".to_string()), "{}", update);
         }
+        // a name that is already a plain variable cannot become fixed
+        assert_eq!(analyze(&mut Analyzer::new(), "x is 1\nfixed x is 2"), Err("x is already declared and cannot be made fixed".to_string()));
     }
 
-    fn check_assignment(&self, var_name: &str, value_type: &TokenType) -> Result<(), String> {
-        if let Some(var_type) = self.variables.get(var_name) {
-            let converted_type = self.token_type_to_type(value_type);
-            if var_type != &converted_type {
-                return Err(format!(
-                    "Type mismatch: cannot assign {:?} to variable of type {:?}",
-                    converted_type, var_type
-                ));
-            }
+    #[test]
+    fn not_needs_a_truth() {
+        assert_eq!(analyze(&mut Analyzer::new(), "x as Logic is not true
+y is not not x"), Ok(()));
+        assert!(analyze(&mut Analyzer::new(), "x is not 1").is_err());
+    }
+
+    #[test]
+    fn block_variables_stay_in_their_block() {
+        let result = analyze(&mut Analyzer::new(), "when true:\n    inner is 1\nshow inner");
+        assert_eq!(result, Err("Undefined variable: inner".to_string()));
+        assert_eq!(analyze(&mut Analyzer::new(), "outer is 1\nwhen true:\n    show outer"), Ok(()));
+    }
+
+    #[test]
+    fn conditions_must_be_truth() {
+        assert!(analyze(&mut Analyzer::new(), "when \"hello\":\n    show 1").is_err());
+        assert!(analyze(&mut Analyzer::new(), "loop while 1:\n    show 1").is_err());
+        assert_eq!(analyze(&mut Analyzer::new(), "x is 3\nwhen x > 1:\n    show x"), Ok(()));
+        assert_eq!(analyze(&mut Analyzer::new(), "x is 3\nloop while x > 5:\n    show x"), Ok(()));
+    }
+
+    fn number(n: f64) -> Node {
+        Node::Literal(Value::Number(n))
+    }
+
+    fn list(elements: Vec<Node>, annotation: Option<&str>) -> Node {
+        Node::ArrayLiteral {
+            elements,
+            type_annotation: annotation.map(|name| Box::new(Node::TypeAnnotation(name.to_string()))),
         }
-        Ok(())
+    }
+
+    #[test]
+    fn list_element_type_is_inferred() {
+        let wholes = list(vec![number(1.0), number(2.0)], None);
+        assert_eq!(Analyzer::new().check_node(&wholes), Ok(Type::List(Box::new(Type::Whole))));
+        let widened = list(vec![number(1.0), number(2.5)], None);
+        assert_eq!(Analyzer::new().check_node(&widened), Ok(Type::List(Box::new(Type::Decimal))));
+        let mixed = list(vec![number(1.0), Node::Literal(Value::String("a".to_string()))], None);
+        assert_eq!(Analyzer::new().check_node(&mixed), Ok(Type::List(Box::new(Type::Any))));
+    }
+
+    #[test]
+    fn annotated_list_checks_every_element() {
+        let decimals = list(vec![number(1.0), number(2.5)], Some("Decimal"));
+        assert_eq!(Analyzer::new().check_node(&decimals), Ok(Type::List(Box::new(Type::Decimal))));
+        let wholes = list(vec![number(1.0), Node::Literal(Value::String("a".to_string()))], Some("Whole"));
+        assert!(Analyzer::new().check_node(&wholes).is_err());
+    }
+
+    #[test]
+    fn declared_list_type_is_checked_against_an_inferred_one() {
+        let nodes = [
+            Node::VariableDecl {
+                name: "xs".to_string(),
+                type_annotation: None,
+                initializer: Some(Box::new(list(vec![number(1.0)], None))),
+            },
+            Node::VariableDecl {
+                name: "ys".to_string(),
+                type_annotation: Some(Box::new(Node::ListType { element_type: Box::new(Node::TypeAnnotation("Text".to_string())) })),
+                initializer: Some(Box::new(Node::Variable("xs".to_string()))),
+            },
+        ];
+        assert!(Analyzer::new().analyze(&nodes).is_err());
+    }
+
+    #[test]
+    fn unannotated_variable_can_change_type() {
+        assert_eq!(analyze(&mut Analyzer::new(), "x is \"Hello\"\nx is 42"), Ok(()));
+        assert!(analyze(&mut Analyzer::new(), "x is \"a\"\ny as Whole is x").is_err());
+    }
+
+    #[test]
+    fn me_outside_a_method_is_an_error() {
+        let err = analyze(&mut Analyzer::new(), "show me.count").unwrap_err();
+        assert!(err.contains("'me'"), "{}", err);
+        let err = analyze(&mut Analyzer::new(), "show my count").unwrap_err();
+        assert_eq!(err, "'my' can only be used inside a method or constructor");
+    }
+
+    #[test]
+    fn plus_on_text_gives_text() {
+        assert_eq!(analyze(&mut Analyzer::new(), "s as Text is \"a\" + \"b\""), Ok(()));
+        assert!(analyze(&mut Analyzer::new(), "s is \"a\" + 1").is_err());
+        assert!(analyze(&mut Analyzer::new(), "s is \"a\" - \"b\"").is_err());
     }
 }
@@ -0,0 +1,24 @@
+use std::fmt;
+
+// What went wrong, and at which stage. Lexing and parsing know where in the source they were
+#[derive(Debug, Clone, PartialEq)]
+pub enum NairError {
+    Lex { msg: String, line: usize, col: usize },
+    Parse { msg: String, line: usize, col: usize },
+    Type(String),
+    Compile(String),
+    Runtime(String),
+}
+
+impl fmt::Display for NairError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NairError::Lex { msg, line, col } | NairError::Parse { msg, line, col } => {
+                write!(f, "{} (line {}, column {})", msg, line, col)
+            },
+            NairError::Type(msg) | NairError::Compile(msg) | NairError::Runtime(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for NairError {}
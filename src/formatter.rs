@@ -0,0 +1,368 @@
+use crate::generator::Value;
+use crate::parser::Node;
+use crate::tokenizer::TokenType;
+
+const INDENT: &str = "    ";
+
+// How tightly an expression holds together, loosest first. An operand that
+// binds more loosely than its place allows is wrapped in braces, which group
+// like parentheses do elsewhere
+const LOOSE: u8 = 0;  // `as`, and forms such as `f with a, b` that take the rest of the line
+const OTHERWISE: u8 = 1;
+const OR: u8 = 2;
+const AND: u8 = 3;
+const EQUALITY: u8 = 4;
+const COMPARISON: u8 = 5;
+const TERM: u8 = 6;
+const FACTOR: u8 = 7;
+const UNARY: u8 = 8;
+const POWER: u8 = 9;
+const POSTFIX: u8 = 10;
+
+// Writes parsed statements back out as source: one statement per line, single
+// spaces around words and operators, and four spaces for each block level
+pub fn format_program(statements: &[Node]) -> Result<String, String> {
+    let mut out = String::new();
+    for statement in statements {
+        write_statement(&mut out, statement, 0)?;
+    }
+    Ok(out)
+}
+
+fn line(out: &mut String, depth: usize, text: &str) {
+    out.push_str(&INDENT.repeat(depth));
+    out.push_str(text);
+    out.push('\n');
+}
+
+fn write_block(out: &mut String, block: &Node, depth: usize) -> Result<(), String> {
+    match block {
+        Node::Block(statements, _) => statements.iter().try_for_each(|statement| write_statement(out, statement, depth)),
+        other => write_statement(out, other, depth),
+    }
+}
+
+fn write_statement(out: &mut String, node: &Node, depth: usize) -> Result<(), String> {
+    match node {
+        Node::VariableDecl { .. } => line(out, depth, &declaration(node)?),
+        Node::FixedDecl(decl) => line(out, depth, &format!("fixed {}", declaration(decl)?)),
+        Node::Assignment { name, value } => line(out, depth, &format!("{} becomes {}", name, expr(value)?)),
+        Node::Set { object, name, value } => {
+            line(out, depth, &format!("{}.{} is {}", expr_at(object, POSTFIX)?, name, expr(value)?));
+        },
+        Node::ExpressionStmt(value) => {
+            // `x is 5` on its own would read as a declaration, and `a.b is 5` as setting a field
+            let text = expr(value)?;
+            let misread = match &**value {
+                Node::Binary { left, operator: TokenType::Is | TokenType::NotEquals, .. } | Node::Cast { value: left, .. } => {
+                    matches!(**left, Node::Variable(_) | Node::Get { .. })
+                },
+                _ => false,
+            };
+            line(out, depth, &if misread { format!("{{{}}}", text) } else { text });
+        },
+        Node::ShowStmt(values) => line(out, depth, &format!("show {}", list(values)?)),
+        Node::ReturnStmt(value) => line(out, depth, &format!("output {}", expr(value)?)),
+        Node::EmitStmt(value) => line(out, depth, &format!("Emit {}", expr(value)?)),
+        Node::BreakStmt => line(out, depth, "break"),
+        Node::ContinueStmt => line(out, depth, "continue"),
+        Node::BaseBuild { args, .. } if args.is_empty() => line(out, depth, "build base"),
+        Node::BaseBuild { args, .. } => line(out, depth, &format!("build base with {}", list(args)?)),
+        Node::RaiseStmt { message, error_type } => {
+            line(out, depth, &format!("raise {} as {}", expr_at(message, OR)?, type_name(error_type)?));
+        },
+        Node::AssertStmt { condition, message } => {
+            let text = match message {
+                Some(message) => format!("assert {}, {}", expr(condition)?, expr(message)?),
+                None => format!("assert {}", expr(condition)?),
+            };
+            line(out, depth, &text);
+        },
+        Node::UsingFile(path) => line(out, depth, &format!("using \"{}\"", path)),
+        Node::UsingExpr { base, name, body } => {
+            line(out, depth, &format!("using {} as {}:", expr_at(base, OTHERWISE)?, name));
+            write_block(out, body, depth + 1)?;
+        },
+        Node::WhenStmt { condition, then_branch, else_branch } => {
+            line(out, depth, &format!("when {}:", expr(condition)?));
+            write_block(out, then_branch, depth + 1)?;
+            // `or when` chains are kept flat
+            let mut rest = else_branch.as_deref();
+            while let Some(branch) = rest {
+                match branch {
+                    Node::WhenStmt { condition, then_branch, else_branch } => {
+                        line(out, depth, &format!("or when {}:", expr(condition)?));
+                        write_block(out, then_branch, depth + 1)?;
+                        rest = else_branch.as_deref();
+                    },
+                    other => {
+                        line(out, depth, "or:");
+                        write_block(out, other, depth + 1)?;
+                        rest = None;
+                    },
+                }
+            }
+        },
+        Node::LoopStmt { condition, body } => {
+            match condition {
+                Some(condition) => line(out, depth, &format!("loop while {}:", expr(condition)?)),
+                None => line(out, depth, "loop:"),
+            }
+            write_block(out, body, depth + 1)?;
+        },
+        Node::EachLoop { var, index, iterable, body } => {
+            let at = if index == "index" { String::new() } else { format!(" at {}", index) };
+            line(out, depth, &format!("loop each {}{} in {}:", var, at, expr(iterable)?));
+            write_block(out, body, depth + 1)?;
+        },
+        Node::TaskDecl { .. } => write_task(out, node, "Task", depth)?,
+        Node::ObjectDecl { name, base, constructor, methods, doc } => {
+            let inherits = match base {
+                Some(base) => format!(" inherits {}", type_name(base)?),
+                None => String::new(),
+            };
+            line(out, depth, &format!("Object {}{}:", name, inherits));
+            if let Some(doc) = doc {
+                line(out, depth + 1, &format!("about \"{}\"", doc));
+            }
+            if let Some(constructor) = constructor {
+                write_task(out, constructor, "build", depth + 1)?;
+            }
+            for method in methods {
+                write_task(out, method, "Task", depth + 1)?;
+            }
+        },
+        Node::Block(..) => write_block(out, node, depth)?,
+        other => line(out, depth, &expr(other)?),
+    }
+    Ok(())
+}
+
+// `Task name requires a, each rest returns Whole:`, or for a constructor `build defaults a, b:`
+fn write_task(out: &mut String, task: &Node, keyword: &str, depth: usize) -> Result<(), String> {
+    let Node::TaskDecl { name, params, variadic, return_type, body, doc } = task else {
+        return Err(format!("fmt: expected a Task, got {:?}", task));
+    };
+    let mut header = if keyword == "build" { "build".to_string() } else { format!("Task {}", name) };
+    if !params.is_empty() {
+        let mut names = params.iter().map(declaration).collect::<Result<Vec<_>, _>>()?;
+        if *variadic {
+            if let Some(last) = names.last_mut() {
+                last.insert_str(0, "each ");
+            }
+        }
+        header.push_str(if keyword == "build" { " defaults " } else { " requires " });
+        header.push_str(&names.join(", "));
+    }
+    if let Some(return_type) = return_type {
+        header.push_str(&format!(" returns {}", type_name(return_type)?));
+    }
+    line(out, depth, &format!("{}:", header));
+    if let Some(doc) = doc {
+        line(out, depth + 1, &format!("about \"{}\"", doc));
+    }
+    write_block(out, body, depth + 1)
+}
+
+fn declaration(node: &Node) -> Result<String, String> {
+    let Node::VariableDecl { name, type_annotation, initializer } = node else {
+        return Err(format!("fmt: expected a declaration, got {:?}", node));
+    };
+    Ok(match (type_annotation, initializer) {
+        // A typed Mapping lists its entries after `includes`
+        (Some(annotation), Some(entries)) if matches!(**entries, Node::MappingLiteral { .. }) => {
+            format!("{} as {} includes {}", name, type_name(annotation)?, mapping_entries(entries)?)
+        },
+        (Some(annotation), Some(value)) => format!("{} as {} is {}", name, type_name(annotation)?, expr(value)?),
+        (Some(annotation), None) => format!("{} as {}", name, type_name(annotation)?),
+        (None, Some(value)) => format!("{} is {}", name, expr(value)?),
+        (None, None) => name.clone(),
+    })
+}
+
+fn mapping_entries(node: &Node) -> Result<String, String> {
+    let Node::MappingLiteral { entries } = node else {
+        return Err(format!("fmt: expected mapping entries, got {:?}", node));
+    };
+    let entries = entries.iter().map(|(name, annotation, value)| match annotation {
+        Some(annotation) => Ok(format!("{} as {} is {}", name, type_name(annotation)?, expr(value)?)),
+        None => Ok(format!("{} is {}", name, expr(value)?)),
+    }).collect::<Result<Vec<String>, String>>()?;
+    Ok(entries.join(", "))
+}
+
+fn type_name(node: &Node) -> Result<String, String> {
+    Ok(match node {
+        Node::TypeAnnotation(name) => name.clone(),
+        Node::ListType { element_type } => format!("List[{}]", type_name(element_type)?),
+        Node::MappingType { value_type, .. } => match &**value_type {
+            Node::TypeAnnotation(name) if name == "Any" => "Mapping".to_string(),
+            other => format!("Mapping of {}", type_name(other)?),
+        },
+        Node::PromiseType { value_type } => format!("Promise[{}]", type_name(value_type)?),
+        other => return Err(format!("fmt: expected a type, got {:?}", other)),
+    })
+}
+
+fn list(values: &[Node]) -> Result<String, String> {
+    Ok(values.iter().map(expr).collect::<Result<Vec<_>, _>>()?.join(", "))
+}
+
+fn expr(node: &Node) -> Result<String, String> {
+    expr_at(node, LOOSE)
+}
+
+// An expression in a place that needs at least `min` binding strength
+fn expr_at(node: &Node, min: u8) -> Result<String, String> {
+    let (text, strength) = expression(node)?;
+    Ok(if strength < min { format!("{{{}}}", text) } else { text })
+}
+
+fn operator(operator: &TokenType) -> Result<(&'static str, u8), String> {
+    Ok(match operator {
+        TokenType::Otherwise => ("otherwise", OTHERWISE),
+        TokenType::Or => ("or", OR),
+        TokenType::And => ("and", AND),
+        TokenType::Is | TokenType::Equals => ("is", EQUALITY),
+        TokenType::NotEquals => ("is not", EQUALITY),
+        TokenType::GreaterThan => (">", COMPARISON),
+        TokenType::GreaterThanOrEqual => (">=", COMPARISON),
+        TokenType::LessThan => ("<", COMPARISON),
+        TokenType::LessThanOrEqual => ("<=", COMPARISON),
+        TokenType::Contains => ("contains", COMPARISON),
+        TokenType::Plus => ("+", TERM),
+        TokenType::Minus => ("-", TERM),
+        TokenType::Multiply => ("*", FACTOR),
+        TokenType::Divide => ("/", FACTOR),
+        TokenType::Modulo => ("%", FACTOR),
+        other => return Err(format!("fmt: unknown operator {:?}", other)),
+    })
+}
+
+fn literal(value: &Value) -> Result<String, String> {
+    Ok(match value {
+        Value::Number(n) => n.to_string(),
+        // Debug keeps the point of 4.0, which makes it a Decimal
+        Value::Decimal(n) => format!("{:?}", n),
+        Value::String(s) => format!("\"{}\"", s),
+        Value::Boolean(b) => b.to_string(),
+        Value::Null => "null".to_string(),
+        Value::Promise(type_name) => format!("Promise[{}]", type_name),
+        other => return Err(format!("fmt: cannot write the value {}", other)),
+    })
+}
+
+// The text of an expression and how tightly it binds
+fn expression(node: &Node) -> Result<(String, u8), String> {
+    Ok(match node {
+        Node::Literal(value) => (literal(value)?, POSTFIX),
+        Node::Variable(name) => (name.clone(), POSTFIX),
+        Node::Me => ("me".to_string(), POSTFIX),
+        // The one operator that groups to the right, with a unary exponent
+        Node::Binary { left, operator: TokenType::Power, right } => {
+            (format!("{} ** {}", expr_at(left, POSTFIX)?, expr_at(right, UNARY)?), POWER)
+        },
+        Node::Binary { left, operator: op, right } => {
+            let (symbol, strength) = operator(op)?;
+            // Operators group to the left, so an equal one on the right needs braces
+            let text = format!("{} {} {}", expr_at(left, strength)?, symbol, expr_at(right, strength + 1)?);
+            (text, strength)
+        },
+        Node::Unary { operator, operand } => {
+            let symbol = match operator {
+                TokenType::Minus => "-",
+                TokenType::Not => "not ",
+                other => return Err(format!("fmt: unknown operator {:?}", other)),
+            };
+            (format!("{}{}", symbol, expr_at(operand, UNARY)?), UNARY)
+        },
+        Node::Cast { value, target } => (format!("{} as {}", expr_at(value, OTHERWISE)?, type_name(target)?), LOOSE),
+        Node::Call { callee, args } => (format!("{}({})", expr_at(callee, POSTFIX)?, list(args)?), POSTFIX),
+        Node::Get { object, name } => (format!("{}.{}", expr_at(object, POSTFIX)?, name), POSTFIX),
+        Node::PropertyAccess { object, property } if matches!(**object, Node::Me) => (format!("my {}", property), POSTFIX),
+        Node::PropertyAccess { object, property } => (format!("{} of {}", property, expr_at(object, UNARY)?), UNARY),
+        Node::Index { object, index } => (format!("{}[{}]", expr_at(object, POSTFIX)?, expr(index)?), POSTFIX),
+        Node::Slice { object, start, end } => {
+            (format!("{}[{}..{}]", expr_at(object, POSTFIX)?, expr(start)?, expr(end)?), POSTFIX)
+        },
+        Node::StringInterpolation { parts } => {
+            let mut text = String::from("\"");
+            for part in parts {
+                match part {
+                    Node::Literal(Value::String(s)) => text.push_str(s),
+                    other => text.push_str(&format!("{{{}}}", expr(other)?)),
+                }
+            }
+            text.push('"');
+            (text, POSTFIX)
+        },
+        Node::ArrayLiteral { elements, .. } => (format!("[{}]", list(elements)?), POSTFIX),
+        Node::ObjectLiteral { fields } => {
+            let fields = fields.iter()
+                .map(|(name, value)| Ok(format!("{}: {}", name, expr(value)?)))
+                .collect::<Result<Vec<String>, String>>()?;
+            (format!("{{{}}}", fields.join(", ")), POSTFIX)
+        },
+        Node::MappingLiteral { entries } if entries.is_empty() => ("Mapping".to_string(), POSTFIX),
+        Node::New { class_name, args } if args.is_empty() => (format!("new {}", class_name), POSTFIX),
+        // These run to the end of the line, so they only stand alone
+        Node::New { class_name, args } => (format!("new {} with {}", class_name, list(args)?), LOOSE),
+        Node::WithExpr { base, args } => (format!("{} with {}", expr_at(base, POSTFIX)?, list(args)?), LOOSE),
+        Node::AwaitExpr { value } => (format!("await {}", expr(value)?), LOOSE),
+        Node::MapExpr { var, iterable, transform, filter } => {
+            let mut text = format!("each {} in {} becomes {}", var, expr_at(iterable, OTHERWISE)?, expr_at(transform, OTHERWISE)?);
+            if let Some(filter) = filter {
+                text.push_str(&format!(" when {}", expr(filter)?));
+            }
+            (text, LOOSE)
+        },
+        other => return Err(format!("fmt: cannot write {:?}", other)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::tokenizer::Tokenizer;
+
+    fn format(source: &str) -> String {
+        let tokens = Tokenizer::new(source).tokenize().unwrap();
+        format_program(&Parser::new(tokens).parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn messy_source_comes_out_canonical() {
+        let messy = "x   is    1+2*3\nname as Text is   \"Ada\"\nTask   add requires a,b   as Whole returns   Whole:\n  about \"Adds\"\n  output a+b\n\
+                     when x>3:   show x\nor when x is not 2:\n        show  \"two: {x}\"\nor:\n show -x\n\
+                     loop each n at i in [1,2]:\n   total becomes total+i*n\nys is each y in xs becomes y*2 when y>1\n";
+        let canonical = "x is 1 + 2 * 3\nname as Text is \"Ada\"\nTask add requires a, b as Whole returns Whole:\n    about \"Adds\"\n    output a + b\n\
+                         when x > 3:\n    show x\nor when x is not 2:\n    show \"two: {x}\"\nor:\n    show -x\n\
+                         loop each n at i in [1, 2]:\n    total becomes total + i * n\nys is each y in xs becomes y * 2 when y > 1\n";
+        assert_eq!(format(messy), canonical);
+        assert_eq!(format(canonical), canonical);
+    }
+
+    #[test]
+    fn powers_group_to_the_right() {
+        assert_eq!(format("x is 2**3**2\ny is {2 ** 3} ** 2\nz is -2 ** 2\nw is {-2} ** 2\nv is 10%3*2 ** -1"),
+                   "x is 2 ** 3 ** 2\ny is {2 ** 3} ** 2\nz is -2 ** 2\nw is {-2} ** 2\nv is 10 % 3 * 2 ** -1\n");
+    }
+
+    #[test]
+    fn braces_keep_the_grouping() {
+        assert_eq!(format("x is {1 + 2} * 3\ny is 1 - {2 - 3}\nz is -{a + b}"), "x is {1 + 2} * 3\ny is 1 - {2 - 3}\nz is -{a + b}\n");
+        assert_eq!(format("x is {a as Whole} + 1"), "x is {a as Whole} + 1\n");
+        assert_eq!(format("p is {name:\"Ada\",age:{1 + 2} * 3}"), "p is {name: \"Ada\", age: {1 + 2} * 3}\n");
+        assert_eq!(format("c is {city of home of p}.length + -age of p"), "c is {city of home of p}.length + -age of p\n");
+        // A comparison on its own line would otherwise read as a declaration
+        assert_eq!(format("{x is 5}"), "{x is 5}\n");
+    }
+
+    #[test]
+    fn objects_and_constants() {
+        let source = "Object Dog inherits Animal:\n    build defaults name:\n        build base with name, 4\n        me.name is name\n    Task shout returns Text:\n        output my name + \"!\"\n    Task speak returns Text:\n        output \"Woof\"\n\
+                      fixed limit is 10\nconfig as Mapping includes a is 1, b as Whole is 2\nd is new Dog with \"Rex\"\n";
+        assert_eq!(format(source), source);
+    }
+}
@@ -1,16 +1,31 @@
 use crate::parser::Node;
+use crate::error::NairError;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 #[derive(Debug, Clone)]
 pub enum OpCode {
     // Stack Operations
     Push(Value),
+    LoadConst(usize),  // index into the constants pool
     Pop,
     Duplicate,
+    BuildList(usize),  // element count
+    BuildRecord(Vec<String>),  // field names; pops a value for each, the last on top
+
+    // Mapping over a list keeps the list, the result and a counter on the stack
+    NextItem(usize),  // pushes the next item, or jumps to the address once there are none
+    AppendItem,       // pops a value onto the result
+    FinishMap,        // leaves only the result
+    ItemIndex,        // pushes the position of the item NextItem last pushed
     
     // Variables
     LoadVar(String),
-    StoreVar(String),
+    StoreVar {
+        name: String,
+        declared_type: Option<String>,  // set when the store declares the variable's type
+    },
     
     // Arithmetic
     Add,
@@ -19,82 +34,300 @@ pub enum OpCode {
     Divide,
     Modulo,
     Power,
+    FloorDivide,
+    Negate,
+    Not,
+
+    // Comparison
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Contains,  // list membership, mapping key or substring
     
     // Control Flow
     Jump(usize),
     JumpIfFalse(usize),
+    JumpIfNotNull(usize),  // keeps a non-null value and jumps; a null is popped
     Call(String, usize),  // function name, arg count
+    CallMethod(String, usize),  // method name, arg count; the receiver sits below the args
     Return,
+    Raise,  // pops a value and fails with it as the error message
     
     // Objects
-    NewObject(String),    // class name
+    DefineTask(TaskDef),
+    DefineObject(ObjectDef),
+    NewObject(String, usize),  // class name, constructor arg count
+    BuildBase(String, usize),  // base name, arg count; runs its constructor on the object below the args
     GetProperty(String),  // property name
     SetProperty(String),  // property name
+    Bind(String),  // binds a name for the length of a block, setting aside what it held
+    Borrow(String),  // a Bind for `using`, whose object's `release` method runs when it ends
+    Unbind(String),  // ends a Bind or Borrow, bringing back what it set aside
     
     // Types
-    CheckType(String),    // type name
     Cast(String),        // type name
     
     // String Operations
+    Index,               // pops an index, then the text
+    Slice,               // pops the end, the start, then the text
     Concat,
     Interpolate(usize),  // number of parts
-    CheckAssignmentType,
     ConvertToString,
-    Show,
+    Show(usize),  // value count
+    Emit,  // pops a value into the runtime's event buffer
+}
+
+// A compiled Task; objects hold these as their constructor and methods
+#[derive(Debug, Clone)]
+pub struct TaskDef {
+    pub name: String,
+    pub params: Vec<String>,
+    pub variadic: bool,  // the last parameter takes the remaining arguments as a List
+    pub code: Vec<OpCode>,
+    pub lines: Vec<usize>,  // source line of each instruction in `code`, 0 where unknown
+    pub doc: Option<String>,
+}
+
+impl TaskDef {
+    // A variadic Task's last parameter may take no arguments at all
+    pub fn required_args(&self) -> usize {
+        self.params.len() - usize::from(self.variadic)
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        if self.variadic { count >= self.required_args() } else { count == self.params.len() }
+    }
+
+    // How many arguments, for error messages
+    pub fn arity(&self) -> String {
+        if self.variadic {
+            format!("at least {}", self.required_args())
+        } else {
+            self.params.len().to_string()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectDef {
+    pub name: String,
+    pub base: Option<String>,  // the object named after `inherits`
+    pub constructor: Option<TaskDef>,
+    pub methods: Vec<TaskDef>,
+    pub doc: Option<String>,
+}
+
+// A live object; every copy of the value shares the same fields
+#[derive(Debug)]
+pub struct Instance {
+    pub class_name: String,
+    pub fields: RefCell<Vec<(String, Value)>>,  // in the order they were first set
 }
 
 #[derive(Debug, Clone)]
 pub enum Value {
-    Number(f64),
+    Number(f64),   // a Whole
+    Decimal(f64),  // stays a Decimal even without a fraction, like 4.0
     String(String),
     Boolean(bool),
-    Null,
-    Object(String),      // class name
+    Null,     // a missing value, e.g. a variable declared without one
+    Nothing,  // what a Task without output gives, and what a `Nothing` variable holds
+    Instance(Rc<Instance>),
     Promise(String),     // class name
-    List(String),        // class name
-    Mapping(String),     // class name
+    List(Vec<Value>),
+    Mapping(Vec<(String, Value)>),  // entries in insertion order
+}
+
+// Numbers compare as f64 whatever their type, so 4 equals 4.0 and NaN is never
+// equal to itself. Instances are equal only to themselves, while lists and
+// mappings compare element by element, mapping entries in order
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Number(a) | Value::Decimal(a), Value::Number(b) | Value::Decimal(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Null, Value::Null) | (Value::Nothing, Value::Nothing) => true,
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            (Value::Promise(a), Value::Promise(b)) => a == b,
+            (Value::List(a), Value::List(b)) => a == b,
+            (Value::Mapping(a), Value::Mapping(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+// Only numbers with numbers and text with text have an order
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<std::cmp::Ordering> {
+        match (self, other) {
+            (Value::Number(a) | Value::Decimal(a), Value::Number(b) | Value::Decimal(b)) => a.partial_cmp(b),
+            (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+impl Value {
+    // The name a value's type goes by at runtime; instances go by their class
+    pub fn type_name(&self) -> &str {
+        match self {
+            Value::Number(n) => {
+                if n.fract() == 0.0 { "Whole" } else { "Decimal" }
+            },
+            Value::Decimal(_) => "Decimal",
+            Value::String(_) => "Text",
+            Value::Boolean(_) => "Truth",
+            Value::Null | Value::Nothing => "Nothing",
+            Value::Instance(instance) => &instance.class_name,
+            Value::Promise(_) => "Promise",
+            Value::List(_) => "List",
+            Value::Mapping(_) => "Mapping",
+        }
+    }
+
+    // The number held by a Whole or a Decimal
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) | Value::Decimal(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    // Text such as "4" reads as a Whole, while a point or exponent, as in "4.0"
+    // or "1e3", makes a Decimal
+    pub fn parse_number(text: &str) -> Option<Value> {
+        let n = text.parse::<f64>().ok()?;
+        if n.fract() == 0.0 && !text.contains(['.', 'e', 'E']) {
+            Some(Value::Number(n))
+        } else {
+            Some(Value::Decimal(n))
+        }
+    }
+
+    // Like Display, but lists and mappings put each element on its own indented line
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        out
+    }
+
+    fn write_pretty(&self, out: &mut String, depth: usize) {
+        let indent = "    ";
+        let (open, close, entries): (char, char, Vec<(Option<&str>, &Value)>) = match self {
+            Value::List(items) if !items.is_empty() => ('[', ']', items.iter().map(|item| (None, item)).collect()),
+            Value::Mapping(pairs) if !pairs.is_empty() => {
+                ('{', '}', pairs.iter().map(|(key, value)| (Some(key.as_str()), value)).collect())
+            },
+            other => {
+                out.push_str(&other.to_string());
+                return;
+            },
+        };
+        out.push(open);
+        for (i, (key, value)) in entries.iter().enumerate() {
+            out.push('\n');
+            out.push_str(&indent.repeat(depth + 1));
+            if let Some(key) = key {
+                out.push_str(key);
+                out.push_str(": ");
+            }
+            value.write_pretty(out, depth + 1);
+            if i + 1 < entries.len() {
+                out.push(',');
+            }
+        }
+        out.push('\n');
+        out.push_str(&indent.repeat(depth));
+        out.push(close);
+    }
 }
 
 // Add Display implementation for Value
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            // -0 shows as 0, and the special values get words rather than Rust's `inf`
+            Value::Number(n) | Value::Decimal(n) if n.is_nan() => write!(f, "NaN"),
+            Value::Number(n) | Value::Decimal(n) if n.is_infinite() => {
+                write!(f, "{}infinity", if *n < 0.0 { "-" } else { "" })
+            },
+            Value::Number(n) if *n == 0.0 => write!(f, "0"),
             Value::Number(n) => write!(f, "{}", n),
+            // A Decimal keeps its point even when it holds a whole value
+            Value::Decimal(n) if *n == 0.0 => write!(f, "0.0"),
+            Value::Decimal(n) if n.fract() == 0.0 => write!(f, "{}.0", n),
+            Value::Decimal(n) => write!(f, "{}", n),
             Value::String(s) => write!(f, "{}", s),
             Value::Boolean(b) => write!(f, "{}", b),
             Value::Null => write!(f, "null"),
-            Value::Object(name) => write!(f, "[object {}]", name),
+            Value::Nothing => write!(f, "nothing"),
+            Value::Instance(instance) => {
+                let fields: Vec<String> = instance.fields.borrow().iter()
+                    .map(|(name, value)| format!("{}: {}", name, value))
+                    .collect();
+                write!(f, "{} {{{}}}", instance.class_name, fields.join(", "))
+            },
             Value::Promise(name) => write!(f, "[promise {}]", name),
-            Value::List(name) => write!(f, "[list {}]", name),
-            Value::Mapping(name) => write!(f, "[mapping {}]", name),
+            Value::List(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            },
+            Value::Mapping(entries) => {
+                let entries: Vec<String> = entries.iter()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            },
         }
     }
 }
 
 pub struct BytecodeGenerator {
     instructions: Vec<OpCode>,
-    constants: Vec<Value>,
-    variables: HashMap<String, usize>,
-    current_scope: usize,
+    pub lines: Vec<usize>,  // the source line of each instruction, 0 where unknown
+    line: usize,  // the line of the statement being generated
+    pub constants: Vec<Value>,  // text and number literals, each stored once
+    variables: HashMap<String, usize>,  // the slot of each variable in the current scope
+    pub variable_types: HashMap<String, String>,
+    target_type: Option<String>,  // declared type of the variable being initialized
+    current_scope: usize,  // 0 at the top level, one deeper for each Task body being generated
+    scopes: Vec<(HashMap<String, usize>, HashMap<String, String>)>,  // the enclosing scopes' variables and types
     loop_starts: Vec<usize>,
-    loop_ends: Vec<usize>,
+    loop_ends: Vec<Vec<usize>>,  // per loop, the `break` jumps to patch with its end
 }
 
 impl BytecodeGenerator {
     pub fn new() -> Self {
         BytecodeGenerator {
             instructions: Vec::new(),
+            lines: Vec::new(),
+            line: 0,
             constants: Vec::new(),
             variables: HashMap::new(),
+            variable_types: HashMap::new(),
+            target_type: None,
             current_scope: 0,
+            scopes: Vec::new(),
             loop_starts: Vec::new(),
             loop_ends: Vec::new(),
         }
     }
 
-    pub fn generate(&mut self, nodes: Vec<Node>) -> Result<Vec<OpCode>, String> {
-        for node in nodes {
-            self.generate_node(&node)?;
+    // Without line numbers; the runtime compiles through generate_lines
+    #[cfg(test)]
+    pub fn generate(&mut self, nodes: Vec<Node>) -> Result<Vec<OpCode>, NairError> {
+        self.generate_lines(nodes.into_iter().map(|node| (0, node)).collect())
+    }
+
+    // Like generate, with each statement's source line recorded in `lines`
+    pub fn generate_lines(&mut self, statements: Vec<(usize, Node)>) -> Result<Vec<OpCode>, NairError> {
+        for (line, node) in statements {
+            self.line = line;
+            self.generate_node(&node).map_err(NairError::Compile)?;
         }
         Ok(self.instructions.clone())
     }
@@ -102,23 +335,31 @@ impl BytecodeGenerator {
     fn generate_node(&mut self, node: &Node) -> Result<(), String> {
         match node {
             Node::VariableDecl { name, type_annotation, initializer } => {
+                let declared_type = type_annotation.as_deref().and_then(Self::type_name);
+                if let Some(type_name) = &declared_type {
+                    self.variable_types.insert(name.clone(), type_name.clone());
+                }
+
                 if let Some(init) = initializer {
                     // Generate code for initializer
-                    self.generate_node(init)?;
+                    self.target_type = self.variable_types.get(name).cloned();
+                    let result = self.generate_node(init);
+                    self.target_type = None;
+                    result?;
+                } else if declared_type.as_deref() == Some("Nothing") {
+                    // The only value a Nothing variable can hold
+                    self.emit(OpCode::Push(Value::Nothing));
                 } else {
                     // No initializer, push null
                     self.emit(OpCode::Push(Value::Null));
                 }
 
-                // If there's a type annotation, check it
-                if let Some(type_node) = type_annotation {
-                    if let Node::TypeAnnotation(type_name) = &**type_node {
-                        self.emit(OpCode::CheckType(type_name.clone()));
-                    }
-                }
-
-                // Store the variable
-                self.emit(OpCode::StoreVar(name.clone()));
+                // Store the variable along with its declared type, if any
+                self.emit(OpCode::StoreVar {
+                    name: name.clone(),
+                    declared_type,
+                });
+                self.declare(name);
                 Ok(())
             },
 
@@ -126,20 +367,26 @@ impl BytecodeGenerator {
                 // Generate code for the value first
                 self.generate_node(value)?;
 
-                // Only generate LoadVar and CheckAssignmentType if the variable exists
-                if self.variables.contains_key(name) {
-                    self.emit(OpCode::LoadVar(name.to_string()));
-                    self.emit(OpCode::CheckAssignmentType);
-                }
+                // Store the variable; the VM checks it against any declared type
+                self.emit(OpCode::StoreVar {
+                    name: name.clone(),
+                    declared_type: None,
+                });
                 
-                // Store the variable
-                self.emit(OpCode::StoreVar(name.to_string()));
-                
-                // Track the variable if it's new
-                if !self.variables.contains_key(name) {
-                    self.variables.insert(name.clone(), self.variables.len());
+                self.declare(name);
+                Ok(())
+            },
+
+            // The fallback only runs when the left side is null
+            Node::Binary { left, operator: crate::tokenizer::TokenType::Otherwise, right } => {
+                self.generate_node(left)?;
+                let jump_pos = self.instructions.len();
+                self.emit(OpCode::JumpIfNotNull(0));
+                self.generate_node(right)?;
+                let end = self.instructions.len();
+                if let OpCode::JumpIfNotNull(ref mut addr) = self.instructions[jump_pos] {
+                    *addr = end;
                 }
-                
                 Ok(())
             },
 
@@ -151,15 +398,51 @@ impl BytecodeGenerator {
                     crate::tokenizer::TokenType::Plus => OpCode::Add,
                     crate::tokenizer::TokenType::Minus => OpCode::Subtract,
                     crate::tokenizer::TokenType::Multiply => OpCode::Multiply,
+                    // Whole / Whole stays whole unless the result is headed for a Decimal,
+                    // and anything headed for a Whole is floored
+                    crate::tokenizer::TokenType::Divide
+                        if self.target_type.as_deref() == Some("Whole")
+                            || (self.target_type.as_deref() != Some("Decimal")
+                                && self.is_whole(left) && self.is_whole(right)) => OpCode::FloorDivide,
                     crate::tokenizer::TokenType::Divide => OpCode::Divide,
                     crate::tokenizer::TokenType::Modulo => OpCode::Modulo,
+                    crate::tokenizer::TokenType::Power => OpCode::Power,
+                    crate::tokenizer::TokenType::Is | crate::tokenizer::TokenType::Equals => OpCode::Equal,
+                    crate::tokenizer::TokenType::NotEquals => OpCode::NotEqual,
+                    crate::tokenizer::TokenType::GreaterThan => OpCode::Greater,
+                    crate::tokenizer::TokenType::GreaterThanOrEqual => OpCode::GreaterEqual,
+                    crate::tokenizer::TokenType::LessThan => OpCode::Less,
+                    crate::tokenizer::TokenType::LessThanOrEqual => OpCode::LessEqual,
+                    crate::tokenizer::TokenType::Contains => OpCode::Contains,
                     _ => return Err("Unsupported binary operator".to_string()),
                 };
-                self.instructions.push(opcode);
+                self.emit(opcode);
+                Ok(())
+            },
+
+            Node::Unary { operator, operand } => {
+                self.generate_node(operand)?;
+                match operator {
+                    crate::tokenizer::TokenType::Minus => self.emit(OpCode::Negate),
+                    crate::tokenizer::TokenType::Not => self.emit(OpCode::Not),
+                    _ => return Err("Unsupported unary operator".to_string()),
+                }
+                Ok(())
+            },
+
+            Node::Cast { value, target } => {
+                self.generate_node(value)?;
+                let type_name = Self::cast_target(target)?;
+                self.emit(OpCode::Cast(type_name));
                 Ok(())
             },
 
             Node::Call { callee, args } => {
+                // A method's receiver goes below its arguments
+                if let Node::Get { object, .. } = &**callee {
+                    self.generate_node(object)?;
+                }
+
                 // Generate code for arguments first
                 for arg in args {
                     self.generate_node(arg)?;
@@ -171,30 +454,92 @@ impl BytecodeGenerator {
                         self.emit(OpCode::Call(name.clone(), args.len()));
                         Ok(())
                     },
+                    Node::Get { ref name, .. } => {
+                        self.emit(OpCode::CallMethod(name.clone(), args.len()));
+                        Ok(())
+                    },
                     _ => Err("Only direct function calls are supported".to_string()),
                 }
             },
 
-            Node::ShowStmt(expr) => {
-                self.generate_node(expr)?;
-                self.emit(OpCode::Show);
+            Node::WithExpr { base, args } => {
+                let Node::Variable(name) = &**base else {
+                    return Err("Only a named Task can be called with 'with'".to_string());
+                };
+                for arg in args {
+                    self.generate_node(arg)?;
+                }
+                self.emit(OpCode::Call(name.clone(), args.len()));
+                Ok(())
+            },
+
+            Node::UsingExpr { base, name, body } => {
+                self.generate_node(base)?;
+                self.emit(OpCode::Borrow(name.clone()));
+                self.generate_node(body)?;
+                self.emit(OpCode::Unbind(name.clone()));
+                Ok(())
+            },
+
+            Node::ShowStmt(values) => {
+                for value in values {
+                    self.generate_node(value)?;
+                }
+                self.emit(OpCode::Show(values.len()));
                 Ok(())
             },
 
-            Node::Block(statements) => {
-                for stmt in statements {
-                    self.generate_node(stmt)?;
+            // The error type only describes the failure; the message is what is raised
+            Node::RaiseStmt { message, .. } => self.raise(message),
+
+            Node::AssertStmt { condition, message } => {
+                // A true condition jumps over the raise
+                self.generate_node(condition)?;
+                let raise_start = self.instructions.len() + 2;
+                self.emit(OpCode::JumpIfFalse(raise_start));
+                let jump_pos = self.instructions.len();
+                self.emit(OpCode::Jump(0));
+                let default = Node::Literal(Value::String("Assertion failed".to_string()));
+                self.raise(message.as_deref().unwrap_or(&default))?;
+                let after = self.instructions.len();
+                if let OpCode::Jump(ref mut addr) = self.instructions[jump_pos] {
+                    *addr = after;
                 }
                 Ok(())
             },
 
+            Node::EmitStmt(expr) => {
+                self.generate_node(expr)?;
+                self.emit(OpCode::Emit);
+                Ok(())
+            },
+
+            Node::ExpressionStmt(expr) => {
+                // Every expression, calls included, leaves exactly one value
+                // behind; discard it so statements don't grow the stack
+                self.generate_node(expr)?;
+                self.emit(OpCode::Pop);
+                Ok(())
+            },
+
+            Node::Block(statements, lines) => {
+                // Each statement is placed on its own line, and what follows the block on the enclosing one
+                let outer_line = self.line;
+                let result = statements.iter().enumerate().try_for_each(|(i, stmt)| {
+                    self.line = lines.get(i).copied().unwrap_or(outer_line);
+                    self.generate_node(stmt)
+                });
+                self.line = outer_line;
+                result
+            },
+
             Node::WhenStmt { condition, then_branch, else_branch } => {
                 // Generate condition code
                 self.generate_node(condition)?;
                 
                 // Add jump-if-false instruction (we'll patch the jump address later)
                 let jump_if_false_pos = self.instructions.len();
-                self.instructions.push(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::JumpIfFalse(0));
                 
                 // Generate then branch
                 self.generate_node(then_branch)?;
@@ -202,7 +547,7 @@ impl BytecodeGenerator {
                 if let Some(else_branch) = else_branch {
                     // Add jump instruction to skip else branch (we'll patch the address later)
                     let jump_pos = self.instructions.len();
-                    self.instructions.push(OpCode::Jump(0));
+                    self.emit(OpCode::Jump(0));
                     
                     // Patch the jump-if-false address
                     let else_start = self.instructions.len();
@@ -231,38 +576,208 @@ impl BytecodeGenerator {
             Node::LoopStmt { condition, body } => {
                 let loop_start = self.instructions.len();
                 
-                // Generate condition
-                self.generate_node(condition)?;
-                
-                // Add conditional jump to exit loop
-                let jump_if_false_pos = self.instructions.len();
-                self.instructions.push(OpCode::JumpIfFalse(0));
+                // Generate condition and a conditional jump to exit the loop
+                let jump_if_false_pos = match condition {
+                    Some(condition) => {
+                        self.generate_node(condition)?;
+                        self.emit(OpCode::JumpIfFalse(0));
+                        Some(self.instructions.len() - 1)
+                    },
+                    None => None,
+                };
                 
                 // Generate loop body
-                self.generate_node(body)?;
+                self.loop_starts.push(loop_start);
+                self.loop_ends.push(Vec::new());
+                let result = self.generate_node(body);
+                self.loop_starts.pop();
+                let breaks = self.loop_ends.pop().unwrap_or_default();
+                result?;
                 
                 // Add jump back to start
-                self.instructions.push(OpCode::Jump(loop_start));
+                self.emit(OpCode::Jump(loop_start));
                 
-                // Patch the exit jump address
+                // Patch the exit jump address, along with any `break` jumps
                 let after_loop = self.instructions.len();
-                if let OpCode::JumpIfFalse(ref mut addr) = self.instructions[jump_if_false_pos] {
+                if let Some(OpCode::JumpIfFalse(addr)) = jump_if_false_pos.map(|pos| &mut self.instructions[pos]) {
                     *addr = after_loop;
                 }
+                for pos in breaks {
+                    if let OpCode::Jump(ref mut addr) = self.instructions[pos] {
+                        *addr = after_loop;
+                    }
+                }
                 Ok(())
             },
 
-            Node::Get { object, name } => {
+            Node::MapExpr { var, iterable, transform, filter } => {
+                self.generate_node(iterable)?;
+                self.emit(OpCode::BuildList(0));
+                self.emit(OpCode::Push(Value::Number(0.0)));
+                self.emit(OpCode::Push(Value::Null));
+                self.emit(OpCode::Bind(var.clone()));
+
+                let loop_start = self.instructions.len();
+                self.emit(OpCode::NextItem(0));
+                self.emit(OpCode::StoreVar { name: var.clone(), declared_type: None });
+                if let Some(filter) = filter {
+                    self.generate_node(filter)?;
+                    self.emit(OpCode::JumpIfFalse(loop_start));
+                }
+                self.generate_node(transform)?;
+                self.emit(OpCode::AppendItem);
+                self.emit(OpCode::Jump(loop_start));
+
+                let after_loop = self.instructions.len();
+                if let OpCode::NextItem(ref mut addr) = self.instructions[loop_start] {
+                    *addr = after_loop;
+                }
+                self.emit(OpCode::FinishMap);
+                self.emit(OpCode::Unbind(var.clone()));
+                Ok(())
+            },
+
+            // Laid out like a map with an unused result slot, which is popped with the rest.
+            // The item and index names only last as long as the loop
+            Node::EachLoop { var, index, iterable, body } => {
+                self.generate_node(iterable)?;
+                self.emit(OpCode::Push(Value::Null));
+                self.emit(OpCode::Push(Value::Number(0.0)));
+                for name in [var, index] {
+                    self.emit(OpCode::Push(Value::Null));
+                    self.emit(OpCode::Bind(name.clone()));
+                }
+
+                let loop_start = self.instructions.len();
+                self.emit(OpCode::NextItem(0));
+                self.emit(OpCode::StoreVar { name: var.clone(), declared_type: None });
+                self.emit(OpCode::ItemIndex);
+                self.emit(OpCode::StoreVar { name: index.clone(), declared_type: None });
+
+                self.loop_starts.push(loop_start);
+                self.loop_ends.push(Vec::new());
+                let result = self.generate_node(body);
+                self.loop_starts.pop();
+                let breaks = self.loop_ends.pop().unwrap_or_default();
+                result?;
+                self.emit(OpCode::Jump(loop_start));
+
+                let after_loop = self.instructions.len();
+                if let OpCode::NextItem(ref mut addr) = self.instructions[loop_start] {
+                    *addr = after_loop;
+                }
+                for pos in breaks {
+                    if let OpCode::Jump(ref mut addr) = self.instructions[pos] {
+                        *addr = after_loop;
+                    }
+                }
+                for _ in 0..3 {
+                    self.emit(OpCode::Pop);
+                }
+                self.emit(OpCode::Unbind(index.clone()));
+                self.emit(OpCode::Unbind(var.clone()));
+                Ok(())
+            },
+
+            Node::BreakStmt => {
+                // Patched to the end of the loop once it is known
+                let pos = self.instructions.len();
+                self.loop_ends.last_mut()
+                    .ok_or("'break' outside of a loop")?
+                    .push(pos);
+                self.emit(OpCode::Jump(0));
+                Ok(())
+            },
+
+            Node::ContinueStmt => {
+                let loop_start = *self.loop_starts.last().ok_or("'continue' outside of a loop")?;
+                self.emit(OpCode::Jump(loop_start));
+                Ok(())
+            },
+
+            Node::Get { object, name } | Node::PropertyAccess { object, property: name } => {
                 self.generate_node(object)?;
                 self.emit(OpCode::GetProperty(name.clone()));
                 Ok(())
             },
 
+            Node::Index { object, index } => {
+                self.generate_node(object)?;
+                self.generate_node(index)?;
+                self.emit(OpCode::Index);
+                Ok(())
+            },
+
+            Node::Slice { object, start, end } => {
+                self.generate_node(object)?;
+                self.generate_node(start)?;
+                self.generate_node(end)?;
+                self.emit(OpCode::Slice);
+                Ok(())
+            },
+
+            Node::Set { object, name, value } => {
+                self.generate_node(object)?;
+                self.generate_node(value)?;
+                self.emit(OpCode::SetProperty(name.clone()));
+                Ok(())
+            },
+
+            Node::Me => {
+                // `me` is a keyword, so this can't clash with a variable of the same name
+                self.emit(OpCode::LoadVar("me".to_string()));
+                Ok(())
+            },
+
             Node::New { class_name, args } => {
                 for arg in args {
                     self.generate_node(arg)?;
                 }
-                self.emit(OpCode::NewObject(class_name.clone()));
+                self.emit(OpCode::NewObject(class_name.clone(), args.len()));
+                Ok(())
+            },
+
+            Node::BaseBuild { base, args } => {
+                self.emit(OpCode::LoadVar("me".to_string()));
+                for arg in args {
+                    self.generate_node(arg)?;
+                }
+                self.emit(OpCode::BuildBase(base.clone(), args.len()));
+                Ok(())
+            },
+
+            Node::FixedDecl(decl) => self.generate_node(decl),
+
+            Node::TaskDecl { .. } => {
+                let task = self.compile_task(node)?;
+                self.emit(OpCode::DefineTask(task));
+                Ok(())
+            },
+
+            Node::ObjectDecl { name, base, constructor, methods, doc } => {
+                let base = match base.as_deref() {
+                    Some(Node::TypeAnnotation(base)) => Some(base.clone()),
+                    Some(other) => return Err(format!("Unsupported base object: {:?}", other)),
+                    None => None,
+                };
+                let constructor = constructor.as_deref().map(|task| self.compile_task(task)).transpose()?;
+                let methods = methods.iter()
+                    .map(|task| self.compile_task(task))
+                    .collect::<Result<Vec<_>, String>>()?;
+
+                self.emit(OpCode::DefineObject(ObjectDef {
+                    name: name.clone(),
+                    base,
+                    constructor,
+                    methods,
+                    doc: doc.clone(),
+                }));
+                Ok(())
+            },
+
+            Node::ReturnStmt(value) => {
+                self.generate_node(value)?;
+                self.emit(OpCode::Return);
                 Ok(())
             },
 
@@ -274,14 +789,17 @@ impl BytecodeGenerator {
 
             Node::Literal(value) => {
                 match value {
-                    Value::Number(n) => self.emit(OpCode::Push(Value::Number(*n))),
-                    Value::String(s) => self.emit(OpCode::Push(Value::String(s.clone()))),
+                    Value::Number(_) | Value::Decimal(_) | Value::String(_) => {
+                        let index = self.add_constant(value);
+                        self.emit(OpCode::LoadConst(index));
+                    },
                     Value::Boolean(b) => self.emit(OpCode::Push(Value::Boolean(*b))),
                     Value::Null => self.emit(OpCode::Push(Value::Null)),
-                    Value::Object(name) => self.emit(OpCode::Push(Value::Object(name.clone()))),
+                    Value::Nothing => self.emit(OpCode::Push(Value::Nothing)),
+                    Value::Instance(instance) => self.emit(OpCode::Push(Value::Instance(instance.clone()))),
                     Value::Promise(name) => self.emit(OpCode::Push(Value::Promise(name.clone()))),
-                    Value::List(name) => self.emit(OpCode::Push(Value::List(name.clone()))),
-                    Value::Mapping(name) => self.emit(OpCode::Push(Value::Mapping(name.clone()))),
+                    Value::List(items) => self.emit(OpCode::Push(Value::List(items.clone()))),
+                    Value::Mapping(entries) => self.emit(OpCode::Push(Value::Mapping(entries.clone()))),
                 }
                 Ok(())
             },
@@ -291,47 +809,162 @@ impl BytecodeGenerator {
                 Ok(())
             },
 
+            Node::ArrayLiteral { elements, .. } => {
+                for element in elements {
+                    self.generate_node(element)?;
+                }
+                self.emit(OpCode::BuildList(elements.len()));
+                Ok(())
+            },
+
+            // A record is a Mapping keyed by its field names
+            Node::ObjectLiteral { fields } => {
+                for (_, value) in fields {
+                    self.generate_node(value)?;
+                }
+                self.emit(OpCode::BuildRecord(fields.iter().map(|(name, _)| name.clone()).collect()));
+                Ok(())
+            },
+
             // Add more node types as needed...
             _ => Err(format!("Unsupported node type: {:?}", node)),
         }
     }
 
-    fn generate_type_annotation(&mut self, type_node: Node) -> Result<(), String> {
-        match type_node {
-            Node::TypeAnnotation(type_name) => {
-                // For variable declarations with no initializer, we'll push null first
-                self.instructions.push(OpCode::Push(Value::Null));
-                self.instructions.push(OpCode::CheckType(type_name));
-                Ok(())
+    // Whether a node is statically known to produce a whole number
+    fn is_whole(&self, node: &Node) -> bool {
+        use crate::tokenizer::TokenType;
+        match node {
+            Node::Literal(Value::Number(n)) => n.fract() == 0.0,
+            Node::Variable(name) => self.variable_types.get(name).is_some_and(|t| t == "Whole"),
+            Node::Cast { target, .. } => matches!(&**target, Node::TypeAnnotation(t) if t == "Whole"),
+            Node::Unary { operand, .. } => self.is_whole(operand),
+            Node::Binary { left, operator, right } => {
+                matches!(operator, TokenType::Plus | TokenType::Minus | TokenType::Multiply | TokenType::Divide | TokenType::Modulo)
+                    && self.is_whole(left)
+                    && self.is_whole(right)
             },
-            _ => Err("Expected type annotation".to_string()),
+            _ => false,
         }
     }
 
-    fn emit(&mut self, opcode: OpCode) {
-        self.instructions.push(opcode);
+    // Element types are spelled out, e.g. `List[Text]`, for the VM to cast into
+    fn cast_target(target: &Node) -> Result<String, String> {
+        match target {
+            Node::TypeAnnotation(type_name) => Ok(type_name.clone()),
+            Node::ListType { element_type } => Ok(format!("List[{}]", Self::cast_target(element_type)?)),
+            Node::MappingType { value_type, .. } => Ok(format!("Mapping[{}]", Self::cast_target(value_type)?)),
+            _ => Err(format!("Unsupported cast target: {:?}", target)),
+        }
     }
 
-    fn generate_assignment(&mut self, name: &str, value: &Node) -> Result<(), String> {
-        // Generate code for the value first
-        self.generate_node(value)?;
-
-        // For assignments, we only need LoadVar if the variable exists
-        if self.variables.contains_key(name) {
-            self.emit(OpCode::LoadVar(name.to_string()));
-            self.emit(OpCode::CheckAssignmentType);
+    fn type_name(type_node: &Node) -> Option<String> {
+        match type_node {
+            Node::TypeAnnotation(type_name) => Some(type_name.clone()),
+            Node::ListType { .. } => Some("List".to_string()),
+            Node::MappingType { .. } => Some("Mapping".to_string()),
+            Node::PromiseType { .. } => Some("Promise".to_string()),
+            _ => None,
         }
-        
-        // Store the result
-        self.emit(OpCode::StoreVar(name.to_string()));
+    }
+
+    // Stops the program with the message, for `raise` and a failed `assert`
+    fn raise(&mut self, message: &Node) -> Result<(), String> {
+        self.generate_node(message)?;
+        self.emit(OpCode::Raise);
         Ok(())
     }
 
+    // Reuses the slot of an identical literal; numbers compare by their bits so
+    // 0 and -0 keep slots of their own, as do 4 and 4.0
+    fn add_constant(&mut self, value: &Value) -> usize {
+        let existing = self.constants.iter().position(|constant| match (constant, value) {
+            (Value::Number(a), Value::Number(b)) | (Value::Decimal(a), Value::Decimal(b)) => a.to_bits() == b.to_bits(),
+            (Value::String(a), Value::String(b)) => a == b,
+            _ => false,
+        });
+        existing.unwrap_or_else(|| {
+            self.constants.push(value.clone());
+            self.constants.len() - 1
+        })
+    }
+
+    // Gives a new variable the next free slot in the current scope
+    fn declare(&mut self, name: &str) -> usize {
+        let next = self.variables.len();
+        *self.variables.entry(name.to_string()).or_insert(next)
+    }
+
+    // A Task body starts with no variables of its own, numbered from slot zero
+    fn enter_scope(&mut self) {
+        let variables = std::mem::take(&mut self.variables);
+        let types = std::mem::take(&mut self.variable_types);
+        self.scopes.push((variables, types));
+        self.current_scope += 1;
+    }
+
+    // Forgets the body's variables and brings back the enclosing scope's
+    fn exit_scope(&mut self) {
+        if let Some((variables, types)) = self.scopes.pop() {
+            self.variables = variables;
+            self.variable_types = types;
+            self.current_scope -= 1;
+        }
+    }
+
+    // Task bodies get their own instructions, so their jumps start from zero.
+    // They share the constants pool, though
+    fn compile_task(&mut self, task: &Node) -> Result<TaskDef, String> {
+        let Node::TaskDecl { name, params, variadic, body, doc, .. } = task else {
+            return Err(format!("Expected a Task, got {:?}", task));
+        };
+
+        let instructions = std::mem::take(&mut self.instructions);
+        let lines = std::mem::take(&mut self.lines);
+        // `break` and `continue` never leave the Task for a loop around it
+        let loop_starts = std::mem::take(&mut self.loop_starts);
+        let loop_ends = std::mem::take(&mut self.loop_ends);
+        self.enter_scope();
+        let mut param_names = Vec::new();
+        for param in params {
+            if let Node::VariableDecl { name, type_annotation, .. } = param {
+                if let Some(type_name) = type_annotation.as_deref().and_then(Self::type_name) {
+                    self.variable_types.insert(name.clone(), type_name);
+                }
+                self.declare(name);
+                param_names.push(name.clone());
+            }
+        }
+
+        let result = self.generate_node(body);
+        self.exit_scope();
+        let code = std::mem::replace(&mut self.instructions, instructions);
+        let body_lines = std::mem::replace(&mut self.lines, lines);
+        self.loop_starts = loop_starts;
+        self.loop_ends = loop_ends;
+        result?;
+
+        Ok(TaskDef {
+            name: name.clone(),
+            params: param_names,
+            variadic: *variadic,
+            code,
+            lines: body_lines,
+            doc: doc.clone(),
+        })
+    }
+
+    fn emit(&mut self, opcode: OpCode) {
+        self.instructions.push(opcode);
+        self.lines.push(self.line);
+    }
+
     fn generate_string_interpolation(&mut self, parts: &[Node]) -> Result<(), String> {
         for part in parts {
             match part {
-                Node::Literal(Value::String(s)) => {
-                    self.emit(OpCode::Push(Value::String(s.clone())));
+                Node::Literal(value @ Value::String(_)) => {
+                    let index = self.add_constant(value);
+                    self.emit(OpCode::LoadConst(index));
                 },
                 Node::Variable(name) => {
                     self.emit(OpCode::LoadVar(name.clone()));
@@ -339,11 +972,211 @@ impl BytecodeGenerator {
                 },
                 _ => self.generate_node(part)?,
             }
-            
-            if parts.len() > 1 {
-                self.emit(OpCode::Concat);
-            }
         }
         Ok(())
     }
 }
+
+// One instruction per line with its index. Jump targets become labels like `L3`,
+// and a blank line separates basic blocks
+pub fn disassemble(code: &[OpCode]) -> String {
+    let target = |op: &OpCode| match op {
+        OpCode::Jump(t) | OpCode::JumpIfFalse(t) | OpCode::JumpIfNotNull(t) | OpCode::NextItem(t) => Some(*t),
+        _ => None,
+    };
+    let labels: std::collections::BTreeSet<usize> = code.iter().filter_map(target).collect();
+
+    let mut out = String::new();
+    for (i, op) in code.iter().enumerate() {
+        if labels.contains(&i) {
+            if i > 0 && !out.ends_with("\n\n") {
+                out.push('\n');
+            }
+            out.push_str(&format!("L{}:\n", i));
+        }
+        let text = match op {
+            OpCode::Jump(t) => format!("Jump L{}", t),
+            OpCode::JumpIfFalse(t) => format!("JumpIfFalse L{}", t),
+            OpCode::JumpIfNotNull(t) => format!("JumpIfNotNull L{}", t),
+            OpCode::NextItem(t) => format!("NextItem L{}", t),
+            other => format!("{:?}", other),
+        };
+        out.push_str(&format!("{:>4}  {}\n", i, text));
+        // A jump ends its block
+        if target(op).is_some() && i + 1 < code.len() {
+            out.push('\n');
+        }
+    }
+    // A jump past the last instruction lands on the end
+    if labels.contains(&code.len()) {
+        if !out.ends_with("\n\n") && !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&format!("L{}:\n", code.len()));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::tokenizer::Tokenizer;
+
+    fn compile(source: &str) -> Vec<OpCode> {
+        let tokens = Tokenizer::new(source).tokenize().unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        BytecodeGenerator::new().generate(ast).unwrap()
+    }
+
+    fn instance(class_name: &str) -> Value {
+        Value::Instance(Rc::new(Instance { class_name: class_name.to_string(), fields: RefCell::new(Vec::new()) }))
+    }
+
+    #[test]
+    fn values_compare_within_their_variant() {
+        let text = |s: &str| Value::String(s.to_string());
+        let equal = [
+            (Value::Number(1.5), Value::Number(1.5)),
+            (text("a"), text("a")),
+            (Value::Boolean(true), Value::Boolean(true)),
+            (Value::Null, Value::Null),
+            (Value::Nothing, Value::Nothing),
+            (Value::Promise("Whole".to_string()), Value::Promise("Whole".to_string())),
+            (Value::List(vec![Value::Number(1.0), text("a")]), Value::List(vec![Value::Number(1.0), text("a")])),
+            (Value::Mapping(vec![("k".to_string(), Value::Null)]), Value::Mapping(vec![("k".to_string(), Value::Null)])),
+        ];
+        for (a, b) in &equal {
+            assert_eq!(a, b);
+        }
+
+        let unequal = [
+            (Value::Number(1.0), Value::Number(2.0)),
+            (Value::Number(f64::NAN), Value::Number(f64::NAN)),
+            (text("a"), text("b")),
+            (Value::Number(1.0), text("1")),
+            (Value::Boolean(true), Value::Boolean(false)),
+            (Value::Null, Value::Boolean(false)),
+            (Value::Null, Value::Nothing),
+            (Value::Promise("Whole".to_string()), Value::Promise("Text".to_string())),
+            (Value::List(vec![Value::Number(1.0)]), Value::List(vec![Value::Number(1.0), Value::Number(2.0)])),
+            (Value::Mapping(vec![("k".to_string(), Value::Null)]), Value::Mapping(vec![("j".to_string(), Value::Null)])),
+            (instance("Dog"), instance("Dog")),
+        ];
+        for (a, b) in &unequal {
+            assert_ne!(a, b);
+        }
+
+        let dog = instance("Dog");
+        assert_eq!(dog, dog.clone());
+    }
+
+    #[test]
+    fn show_compiles_to_the_show_opcode() {
+        let code = compile("show \"hi\"");
+        assert!(matches!(code.last(), Some(OpCode::Show(1))), "{:?}", code);
+        assert!(!code.iter().any(|op| matches!(op, OpCode::Call(..))), "{:?}", code);
+    }
+
+    #[test]
+    fn expression_statements_pop_their_value() {
+        let code = compile("1 + 2\n\"text\"");
+        assert!(matches!(code.as_slice(), [.., OpCode::Add, OpCode::Pop, OpCode::LoadConst(_), OpCode::Pop]), "{:?}", code);
+    }
+
+    #[test]
+    fn call_statement_compiles_to_call_and_pop() {
+        let code = compile("greet()");
+        assert!(matches!(code.as_slice(), [OpCode::Call(name, 0), OpCode::Pop] if name == "greet"), "{:?}", code);
+    }
+
+    #[test]
+    fn repeated_literals_share_a_constant() {
+        let tokens = Tokenizer::new("a is \"hi\"\nb is \"hi\"\nc is 2").tokenize().unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let mut generator = BytecodeGenerator::new();
+        let code = generator.generate(ast).unwrap();
+
+        let slots: Vec<usize> = code.iter()
+            .filter_map(|op| if let OpCode::LoadConst(index) = op { Some(*index) } else { None })
+            .collect();
+        assert_eq!(slots, [0, 0, 1]);
+        assert_eq!(generator.constants.len(), 2);
+    }
+
+    #[test]
+    fn tasks_keep_their_variables_to_themselves() {
+        let source = "x is 1\nTask first requires n:\n    tmp is n\n    other is tmp\nTask second:\n    tmp as Text is \"b\"\ny is 2";
+        let tokens = Tokenizer::new(source).tokenize().unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let mut generator = BytecodeGenerator::new();
+        let code = generator.generate(ast).unwrap();
+
+        // neither Task's `tmp` took a top-level slot, so `y` follows `x`
+        assert_eq!(generator.variables, HashMap::from([("x".to_string(), 0), ("y".to_string(), 1)]));
+        assert!(generator.variable_types.is_empty(), "{:?}", generator.variable_types);
+        assert!(generator.scopes.is_empty() && generator.current_scope == 0);
+        let tasks: Vec<&TaskDef> = code.iter()
+            .filter_map(|op| if let OpCode::DefineTask(task) = op { Some(task) } else { None })
+            .collect();
+        assert!(matches!(tasks[1].code.as_slice(), [OpCode::LoadConst(_), OpCode::StoreVar { name, declared_type: Some(t) }]
+            if name == "tmp" && t == "Text"), "{:?}", tasks[1].code);
+    }
+
+    #[test]
+    fn each_scope_numbers_its_slots_from_zero() {
+        let mut generator = BytecodeGenerator::new();
+        assert_eq!(generator.declare("x"), 0);
+        generator.enter_scope();
+        assert_eq!(generator.declare("tmp"), 0);
+        assert_eq!(generator.declare("other"), 1);
+        assert_eq!(generator.declare("tmp"), 0);
+        generator.exit_scope();
+        generator.enter_scope();
+        assert_eq!(generator.declare("tmp"), 0);
+        generator.exit_scope();
+        assert_eq!(generator.declare("tmp"), 1);
+    }
+
+    #[test]
+    fn disassembly_labels_jump_targets() {
+        let code = compile("x is true\nwhen x:\n    show 1\nor:\n    show 2\nshow 3");
+        let text = disassemble(&code);
+        assert!(text.contains("   3  JumpIfFalse L7\n\n"), "{}", text);
+        assert!(text.contains("   6  Jump L9\n\nL7:\n   7  LoadConst(1)\n"), "{}", text);
+        assert!(text.contains("\nL9:\n   9  LoadConst(2)\n"), "{}", text);
+    }
+
+    #[test]
+    fn special_numbers_display_plainly() {
+        assert_eq!(Value::Number(-0.0).to_string(), "0");
+        assert_eq!(Value::Number(0.0).to_string(), "0");
+        assert_eq!(Value::Number(f64::NAN).to_string(), "NaN");
+        assert_eq!(Value::Number(f64::INFINITY).to_string(), "infinity");
+        assert_eq!(Value::Number(f64::NEG_INFINITY).to_string(), "-infinity");
+        assert_eq!(Value::Number(-2.5).to_string(), "-2.5");
+    }
+
+    #[test]
+    fn whole_decimals_keep_their_point() {
+        assert_eq!(Value::Decimal(4.0).to_string(), "4.0");
+        assert_eq!(Value::Decimal(-0.0).to_string(), "0.0");
+        assert_eq!(Value::Decimal(-2.5).to_string(), "-2.5");
+        assert_eq!(Value::List(vec![Value::Number(4.0), Value::Decimal(4.0)]).to_string(), "[4, 4.0]");
+    }
+
+    #[test]
+    fn tasks_keep_clear_of_surrounding_loops() {
+        // The parser already refuses this; the generator must not patch the outer loop either
+        let task = Node::TaskDecl {
+            name: "f".to_string(),
+            params: Vec::new(),
+            variadic: false,
+            return_type: None,
+            body: Box::new(Node::Block(vec![Node::BreakStmt], Vec::new())),
+            doc: None,
+        };
+        let ast = vec![Node::LoopStmt { condition: None, body: Box::new(Node::Block(vec![task, Node::BreakStmt], Vec::new())) }];
+        assert_eq!(BytecodeGenerator::new().generate(ast).unwrap_err().to_string(), "'break' outside of a loop");
+    }
+}
@@ -0,0 +1,313 @@
+use crate::generator::Value;
+
+// Objects, instances and promises have no JSON form
+pub fn to_json(value: &Value) -> Result<String, String> {
+    let mut out = String::new();
+    write_value(&mut out, value)?;
+    Ok(out)
+}
+
+fn write_value(out: &mut String, value: &Value) -> Result<(), String> {
+    match value {
+        Value::Number(n) if n.is_finite() => out.push_str(&n.to_string()),
+        // Debug keeps the point, so 4.0 reads back as a Decimal
+        Value::Decimal(n) if n.is_finite() => out.push_str(&format!("{:?}", n)),
+        Value::Number(n) | Value::Decimal(n) => return Err(format!("to_json: {} is not a JSON number", n)),
+        Value::String(s) => write_string(out, s),
+        Value::Boolean(b) => out.push_str(&b.to_string()),
+        // JSON has only the one word for a missing value
+        Value::Null | Value::Nothing => out.push_str("null"),
+        Value::List(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(out, item)?;
+            }
+            out.push(']');
+        },
+        Value::Mapping(entries) => {
+            out.push('{');
+            for (i, (key, item)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(out, key);
+                out.push(':');
+                write_value(out, item)?;
+            }
+            out.push('}');
+        },
+        Value::Instance(_) | Value::Promise(_) => {
+            return Err(format!("to_json: cannot serialize {}", value));
+        },
+    }
+    Ok(())
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// Numbers become Whole or Decimal as written, arrays List and objects Mapping,
+// keys in the order given
+pub fn from_json(text: &str) -> Result<Value, String> {
+    let mut reader = Reader { chars: text.chars().collect(), pos: 0 };
+    let value = reader.value()?;
+    reader.skip_whitespace();
+    if reader.pos < reader.chars.len() {
+        return Err(reader.error("unexpected text after the value"));
+    }
+    Ok(value)
+}
+
+struct Reader {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Reader {
+    fn error(&self, msg: &str) -> String {
+        let before = &self.chars[..self.pos.min(self.chars.len())];
+        let line = before.iter().filter(|&&c| c == '\n').count() + 1;
+        let column = before.iter().rev().take_while(|&&c| c != '\n').count() + 1;
+        format!("from_json: {} at line {}, column {}", msg, line, column)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c)))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Value::String),
+            Some('-' | '0'..='9') => self.number(),
+            Some(c) if c.is_alphabetic() => self.word(),
+            Some(c) => Err(self.error(&format!("unexpected '{}'", c))),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.pos += 1; // Consume '{'
+        let mut entries = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Value::Mapping(entries));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("expected a key"));
+            }
+            let key = self.string()?;
+            self.expect(':')?;
+            entries.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Value::Mapping(entries));
+                },
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.pos += 1; // Consume '['
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Value::List(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(Value::List(items));
+                },
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1; // Consume the opening quote
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(s);
+                },
+                Some('\\') => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let hex: String = self.chars.iter().skip(self.pos + 1).take(4).collect();
+                            let code = u32::from_str_radix(&hex, 16).ok()
+                                .filter(|_| hex.len() == 4)
+                                .ok_or_else(|| self.error("invalid \\u escape"))?;
+                            self.pos += 4;
+                            char::from_u32(code).unwrap_or('\u{fffd}')
+                        },
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    s.push(escaped);
+                    self.pos += 1;
+                },
+                Some(c) => {
+                    s.push(c);
+                    self.pos += 1;
+                },
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while matches!(self.peek(), Some('-' | '+' | '.' | 'e' | 'E' | '0'..='9')) {
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        Value::parse_number(&text).ok_or_else(|| {
+            self.pos = start;
+            self.error(&format!("invalid number {}", text))
+        })
+    }
+
+    fn word(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while self.peek().is_some_and(char::is_alphabetic) {
+            self.pos += 1;
+        }
+        let word: String = self.chars[start..self.pos].iter().collect();
+        match word.as_str() {
+            "true" => Ok(Value::Boolean(true)),
+            "false" => Ok(Value::Boolean(false)),
+            "null" => Ok(Value::Null),
+            _ => {
+                self.pos = start;
+                Err(self.error(&format!("unexpected '{}'", word)))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn nested_list_of_mappings() {
+        let value = Value::List(vec![
+            Value::Mapping(vec![
+                ("name".to_string(), text("Ada")),
+                ("age".to_string(), Value::Number(36.0)),
+            ]),
+            Value::Mapping(vec![
+                ("name".to_string(), text("say \"hi\"\n")),
+                ("scores".to_string(), Value::List(vec![Value::Number(1.5), Value::Null, Value::Boolean(true)])),
+            ]),
+        ]);
+        assert_eq!(
+            to_json(&value).unwrap(),
+            r#"[{"name":"Ada","age":36},{"name":"say \"hi\"\n","scores":[1.5,null,true]}]"#
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let json = r#"[{"name":"Ada","tags":["a","\u00e9\t"]},{"ok":false,"none":null,"n":-2.5e3}]"#;
+        let value = from_json(json).unwrap();
+        assert_eq!(value, Value::List(vec![
+            Value::Mapping(vec![
+                ("name".to_string(), text("Ada")),
+                ("tags".to_string(), Value::List(vec![text("a"), text("\u{e9}\t")])),
+            ]),
+            Value::Mapping(vec![
+                ("ok".to_string(), Value::Boolean(false)),
+                ("none".to_string(), Value::Null),
+                ("n".to_string(), Value::Number(-2500.0)),
+            ]),
+        ]));
+        assert_eq!(from_json(&to_json(&value).unwrap()).unwrap(), value);
+    }
+
+    #[test]
+    fn integral_decimals_keep_their_point() {
+        let value = from_json("[4.0, 4, 1e3]").unwrap();
+        let Value::List(items) = &value else { panic!("{:?}", value) };
+        let types: Vec<&str> = items.iter().map(Value::type_name).collect();
+        assert_eq!(types, ["Decimal", "Whole", "Decimal"]);
+        assert_eq!(to_json(&value).unwrap(), "[4.0,4,1000.0]");
+    }
+
+    #[test]
+    fn malformed_json_reports_where() {
+        assert_eq!(from_json("{\n  \"a\": [1, 2,]\n}").unwrap_err(), "from_json: unexpected ']' at line 2, column 14");
+        assert_eq!(from_json("[1, 2").unwrap_err(), "from_json: expected ',' or ']' at line 1, column 6");
+        assert_eq!(from_json("nope").unwrap_err(), "from_json: unexpected 'nope' at line 1, column 1");
+        assert!(from_json("\"open").is_err());
+        assert!(from_json("1 2").is_err());
+    }
+
+    #[test]
+    fn objects_cannot_be_serialized() {
+        let err = to_json(&Value::List(vec![Value::Promise("Whole".to_string())])).unwrap_err();
+        assert_eq!(err, "to_json: cannot serialize [promise Whole]");
+        assert!(to_json(&Value::Number(f64::INFINITY)).is_err());
+    }
+}
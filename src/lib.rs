@@ -0,0 +1,23 @@
+mod tokenizer;
+mod runtime;
+mod parser;
+mod generator;
+mod analyzer;
+mod error;
+mod json;
+mod formatter;
+mod serializer;
+
+pub use crate::error::NairError;
+pub use crate::generator::Value;
+pub use crate::runtime::{Program, Runtime};
+
+// Checks and compiles source without running it
+pub fn compile(source: &str) -> Result<Program, NairError> {
+    Runtime::new().compile_program(source)
+}
+
+// Runs source on a fresh runtime and gives back every value it showed or emitted, in order
+pub fn eval(source: &str) -> Result<Vec<Value>, NairError> {
+    compile(source)?.run()
+}
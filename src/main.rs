@@ -1,27 +1,78 @@
-mod tokenizer;
-mod runtime;
-mod parser;
-mod generator;
-mod analyzer;
-
-use crate::runtime::Runtime;
+use nair::Runtime;
 use std::env;
 
+const USAGE: &str = "Usage: nair [--tokens] [--ast] [--bytecode] [script]\n       nair fmt <script>\n       nair compile <script> -o <file.nairc>\n       nair run <script or file.nairc>";
+
 fn main() -> Result<(), String> {
-    let args: Vec<String> = env::args().collect();
+    // Inspection flags may come in any order; whatever is left is the script
+    let mut tokens = false;
+    let mut ast = false;
+    let mut bytecode = false;
+    let mut args = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--tokens" => tokens = true,
+            "--ast" => ast = true,
+            "--bytecode" => bytecode = true,
+            flag if flag.starts_with("--") => return Err(format!("Unknown option: {}\n{}", flag, USAGE)),
+            _ => args.push(arg),
+        }
+    }
+
     let mut runtime = Runtime::new();
 
+    if tokens || ast || bytecode {
+        let [file_path] = args.as_slice() else {
+            return Err(USAGE.to_string());
+        };
+        let source = std::fs::read_to_string(file_path)
+            .map_err(|e| format!("Error reading file '{}': {}", file_path, e))?;
+        if tokens {
+            runtime.print_tokens(&source).map_err(|e| e.to_string())?;
+        }
+        if ast {
+            runtime.print_ast(&source).map_err(|e| e.to_string())?;
+        }
+        if bytecode {
+            runtime.print_bytecode(&source).map_err(|e| e.to_string())?;
+        }
+        return Ok(());
+    }
+
+    // `nair fmt file` prints the file in canonical layout
+    if args.first().is_some_and(|arg| arg == "fmt") {
+        let [_, file_path] = args.as_slice() else {
+            return Err(USAGE.to_string());
+        };
+        let source = std::fs::read_to_string(file_path)
+            .map_err(|e| format!("Error reading file '{}': {}", file_path, e))?;
+        print!("{}", runtime.format_source(&source).map_err(|e| e.to_string())?);
+        return Ok(());
+    }
+
+    // `nair compile file -o out.nairc` saves the bytecode for `nair run out.nairc`
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["compile", file_path, "-o", out_path] => {
+            let nairc = runtime.compile_file(file_path).and_then(|program| program.to_nairc())
+                .map_err(|e| e.to_string())?;
+            return std::fs::write(out_path, nairc).map_err(|e| format!("Error writing file '{}': {}", out_path, e));
+        },
+        ["compile", ..] => return Err(USAGE.to_string()),
+        ["run", file_path] if file_path.ends_with(".nairc") => {
+            return runtime.run_compiled_file(file_path).map_err(|e| e.to_string());
+        },
+        ["run", file_path] => return runtime.run_file(file_path).map_err(|e| e.to_string()),
+        _ => {},
+    }
+
     match args.len() {
         // No arguments - run REPL
-        1 => runtime.run_repl(),
-        
+        0 => runtime.run_repl().map_err(|e| e.to_string()),
+
         // File argument provided
-        2 => {
-            let file_path = &args[1];
-            runtime.run_file(file_path)
-        },
-        
+        1 => runtime.run_file(&args[0]).map_err(|e| e.to_string()),
+
         // Invalid number of arguments
-        _ => Err("Usage: nair [script]".to_string()),
+        _ => Err(USAGE.to_string()),
     }
 }
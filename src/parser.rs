@@ -1,7 +1,10 @@
-use crate::{analyzer::Type, tokenizer::{Token, TokenType}};
+use crate::{analyzer::Type, tokenizer::{Token, TokenType, Tokenizer}};
 use crate::generator::Value;
+use crate::error::NairError;
 
+// Some nodes are declared ahead of the parsing that builds them
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub enum Node {
     // Declarations
     VariableDecl {
@@ -9,21 +12,25 @@ pub enum Node {
         type_annotation: Option<Box<Node>>,
         initializer: Option<Box<Node>>,
     },
+    FixedDecl(Box<Node>),  // a VariableDecl whose name can't be given another value
     TaskDecl {
         name: String,
         params: Vec<Node>,
+        variadic: bool,  // the last parameter collects any remaining arguments into a List
         return_type: Option<Box<Node>>,
         body: Box<Node>,
+        doc: Option<String>,  // from an `about "..."` opening the body
     },
     ObjectDecl {
         name: String,
         base: Option<Box<Node>>,
         constructor: Option<Box<Node>>,
         methods: Vec<Node>,
+        doc: Option<String>,
     },
 
     // Statements
-    Block(Vec<Node>),
+    Block(Vec<Node>, Vec<usize>),  // the statements, and the line each starts on
     ExpressionStmt(Box<Node>),
     ReturnStmt(Box<Node>),
     WhenStmt {
@@ -32,14 +39,30 @@ pub enum Node {
         else_branch: Option<Box<Node>>,
     },
     LoopStmt {
-        condition: Box<Node>,
+        condition: Option<Box<Node>>,  // None for a bare `loop:` that only `break` ends
+        body: Box<Node>,
+    },
+    EachLoop {  // `loop each item at i in xs:`
+        var: String,
+        index: String,  // bound to the item's position, `index` unless named with `at`
+        iterable: Box<Node>,
         body: Box<Node>,
     },
-    ShowStmt(Box<Node>),
+    ShowStmt(Vec<Node>),  // printed on one line, separated by spaces
+    BaseBuild {  // `build base with a, b` inside a `build`, running the inherited one on `me`
+        base: String,
+        args: Vec<Node>,
+    },
+    BreakStmt,
+    ContinueStmt,
     RaiseStmt {
         message: Box<Node>,
         error_type: Box<Node>,
     },
+    AssertStmt {
+        condition: Box<Node>,
+        message: Option<Box<Node>>,  // "Assertion failed" when left out
+    },
 
     // Expressions
     Binary {
@@ -47,6 +70,14 @@ pub enum Node {
         operator: TokenType,
         right: Box<Node>,
     },
+    Unary {
+        operator: TokenType,
+        operand: Box<Node>,
+    },
+    Cast {
+        value: Box<Node>,
+        target: Box<Node>,
+    },
     Call {
         callee: Box<Node>,
         args: Vec<Node>,
@@ -55,6 +86,21 @@ pub enum Node {
         object: Box<Node>,
         name: String,
     },
+    Set {
+        object: Box<Node>,
+        name: String,
+        value: Box<Node>,
+    },
+    Index {
+        object: Box<Node>,
+        index: Box<Node>,
+    },
+    Slice {
+        object: Box<Node>,
+        start: Box<Node>,
+        end: Box<Node>,
+    },
+    Me,  // the object a method or constructor was called on
     Literal(Value),
     Variable(String),
     Assignment {
@@ -85,7 +131,7 @@ pub enum Node {
         elements: Vec<Node>,
         type_annotation: Option<Box<Node>>,
     },
-    ObjectLiteral {
+    ObjectLiteral {  // `{name: "Ada", age: 36}`, a record of named fields
         fields: Vec<(String, Node)>,
     },
     MethodCall {
@@ -99,17 +145,25 @@ pub enum Node {
     },
     UsingExpr {
         base: Box<Node>,
-        args: Vec<Node>,
+        name: String,  // bound to the base for the body, then released
+        body: Box<Node>,
     },
     MatchExpr {
         value: Box<Node>,
         cases: Vec<(Node, Node)>,
     },
     EmitStmt(Box<Node>),
+    MapExpr {  // `each x in xs becomes x * 2 when x > 1`
+        var: String,
+        iterable: Box<Node>,
+        transform: Box<Node>,
+        filter: Option<Box<Node>>,
+    },
+    UsingFile(String),  // `using "path"`, replaced by the file's declarations before codegen
     AwaitExpr {
         value: Box<Node>,
     },
-    PropertyAccess {
+    PropertyAccess {  // `name of person`, the same field read as `person.name`
         object: Box<Node>,
         property: String,
     },
@@ -121,6 +175,9 @@ pub enum Node {
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    loop_depth: usize,  // how many loop bodies enclose the current statement
+    base: Option<String>,  // within the `build` of an object that inherits, what it inherits from
+    pub lines: Vec<usize>,  // the line each top-level statement starts on
 }
 
 impl Parser {
@@ -128,18 +185,46 @@ impl Parser {
         Parser {
             tokens,
             current: 0,
+            loop_depth: 0,
+            base: None,
+            lines: Vec::new(),
         }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Node>, String> {
+    pub fn parse(&mut self) -> Result<Vec<Node>, NairError> {
         let mut statements = Vec::new();
+        self.skip_newlines();
         while !self.is_at_end() {
-            statements.push(self.declaration()?);
+            let line = self.peek().line;
+            // Errors are reported at the token the parser stopped on
+            let statement = self.declaration().map_err(|msg| NairError::Parse {
+                msg,
+                line: self.peek().line,
+                col: self.peek().column,
+            })?;
+            statements.push(statement);
+            self.lines.push(line);
+            self.skip_newlines();
         }
         Ok(statements)
     }
 
     fn declaration(&mut self) -> Result<Node, String> {
+        if self.match_token(&[TokenType::Fixed]) {
+            return match self.declaration()? {
+                decl @ Node::VariableDecl { initializer: Some(_), .. } => Ok(Node::FixedDecl(Box::new(decl))),
+                _ => Err("Expected 'name is value' after 'fixed'".to_string()),
+            };
+        }
+
+        if let Some(assignment) = self.update_statement()? {
+            return Ok(assignment);
+        }
+
+        if !matches!(self.peek_next().token_type, TokenType::As | TokenType::Is) {
+            return self.statement();
+        }
+
         if let TokenType::Identifier(name) = &self.peek().token_type {
             let name = name.clone();
             self.advance();
@@ -157,16 +242,17 @@ impl Parser {
                         initializer,
                     })
                 } else {
-                    if self.match_token(&[TokenType::Is]) {
-                        // Regular assignment without type annotation
-                        Ok(Node::VariableDecl {
-                            name,
-                            type_annotation: None,
-                            initializer: Some(Box::new(self.expression()?)),
-                        })
+                    // Typed declaration, optionally initialized
+                    let initializer = if self.match_token(&[TokenType::Is]) {
+                        Some(Box::new(self.expression()?))
                     } else {
-                        Err("Expected 'as' or 'is' after identifier".to_string())
-                    }
+                        None
+                    };
+                    Ok(Node::VariableDecl {
+                        name,
+                        type_annotation: Some(Box::new(type_node)),
+                        initializer,
+                    })
                 }
             } else if self.match_token(&[TokenType::Is]) {
                 // Regular assignment without type annotation
@@ -179,17 +265,18 @@ impl Parser {
                 Err("Expected 'as' or 'is' after identifier".to_string())
             }
         } else {
-            Err("Expected identifier".to_string())
+            self.statement()
         }
     }
 
-    fn Task_declaration(&mut self) -> Result<Node, String> {
+    fn task_declaration(&mut self) -> Result<Node, String> {
         let name = self.consume_identifier("Expected Task name")?;
         
-        let mut params = Vec::new();
-        if self.match_token(&[TokenType::Requires]) {
-            params = self.parameter_list()?;
-        }
+        let (params, variadic) = if self.match_token(&[TokenType::Requires]) {
+            self.parameter_list()?
+        } else {
+            (Vec::new(), false)
+        };
 
         let return_type = if self.match_token(&[TokenType::Returns, TokenType::Returning]) {
             Some(Box::new(self.type_annotation()?))
@@ -198,13 +285,26 @@ impl Parser {
         };
 
         self.consume(&TokenType::Colon, "Expected ':' after Task declaration")?;
-        let body = Box::new(self.block()?);
+        // A loop or `build` around the declaration doesn't reach into the body
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        let base = self.base.take();
+        // With nothing indented under it, the Task does nothing
+        let body = if self.body_is_empty() {
+            Ok((Node::Block(Vec::new(), Vec::new()), None))
+        } else {
+            self.documented_block()
+        };
+        self.loop_depth = loop_depth;
+        self.base = base;
+        let (body, doc) = body?;
 
         Ok(Node::TaskDecl {
             name,
             params,
+            variadic,
             return_type,
-            body,
+            body: Box::new(body),
+            doc,
         })
     }
 
@@ -221,18 +321,30 @@ impl Parser {
 
         let mut methods = Vec::new();
         let mut constructor = None;
+        let mut doc = None;
 
-        while !self.check(&TokenType::EOF) && !self.is_at_end() {
-            if self.match_token(&[TokenType::Build]) {
-                if constructor.is_some() {
-                    return Err("Object can only have one constructor".to_string());
+        self.skip_newlines();
+        if self.match_token(&[TokenType::Indent]) {
+            self.skip_newlines();
+            doc = self.about()?;
+            loop {
+                self.skip_newlines();
+                if self.match_token(&[TokenType::Build]) {
+                    if constructor.is_some() {
+                        return Err("Object can only have one constructor".to_string());
+                    }
+                    let base = match base.as_deref() {
+                        Some(Node::TypeAnnotation(base)) => Some(base.clone()),
+                        _ => None,
+                    };
+                    constructor = Some(Box::new(self.constructor_declaration(base)?));
+                } else if self.match_token(&[TokenType::Task]) {
+                    methods.push(self.task_declaration()?);
+                } else {
+                    break;
                 }
-                constructor = Some(Box::new(self.constructor_declaration()?));
-            } else if self.match_token(&[TokenType::Task]) {
-                methods.push(self.Task_declaration()?);
-            } else {
-                break;
             }
+            self.consume(&TokenType::Dedent, "Expected 'build' or a Task in the object body")?;
         }
 
         Ok(Node::ObjectDecl {
@@ -240,13 +352,16 @@ impl Parser {
             base,
             constructor,
             methods,
+            doc,
         })
     }
 
-    fn parameter_list(&mut self) -> Result<Vec<Node>, String> {
+    // `each` before the last parameter makes the Task variadic
+    fn parameter_list(&mut self) -> Result<(Vec<Node>, bool), String> {
         let mut params = Vec::new();
         
         loop {
+            let variadic = self.match_token(&[TokenType::Each]);
             let name = self.consume_identifier("Expected parameter name")?;
             let type_annotation = if self.match_token(&[TokenType::As]) {
                 Some(Box::new(self.type_annotation()?))
@@ -261,11 +376,12 @@ impl Parser {
             });
 
             if !self.match_token(&[TokenType::Comma]) {
-                break;
+                return Ok((params, variadic));
+            }
+            if variadic {
+                return Err("Only the last parameter can take 'each'".to_string());
             }
         }
-
-        Ok(params)
     }
 
     fn type_annotation(&mut self) -> Result<Node, String> {
@@ -300,9 +416,10 @@ impl Parser {
                 self.advance();
                 Ok(Node::TypeAnnotation("Decimal".to_string()))
             },
+            // `Logic` is an older name for Truth
             TokenType::TypeLogic => {
                 self.advance();
-                Ok(Node::TypeAnnotation("Logic".to_string()))
+                Ok(Node::TypeAnnotation("Truth".to_string()))
             },
             TokenType::TypeNothing => {
                 self.advance();
@@ -344,137 +461,101 @@ impl Parser {
         }
     }
 
+    // The lines after a ':' that are indented further than it, or a single
+    // statement on the same line as in `when x: show 1`
     fn block(&mut self) -> Result<Node, String> {
+        match self.documented_block()? {
+            (_, Some(_)) => Err("Only a Task or Object can have an 'about'".to_string()),
+            (block, None) => Ok(block),
+        }
+    }
+
+    // A block that may open with `about "..."` describing what it belongs to
+    fn documented_block(&mut self) -> Result<(Node, Option<String>), String> {
+        if !self.check(&TokenType::NewLine) {
+            if self.is_at_end() {
+                return Err("Expected a block after ':'".to_string());
+            }
+            let line = self.peek().line;
+            return Ok((Node::Block(vec![self.declaration()?], vec![line]), None));
+        }
+
+        self.skip_newlines();
+        self.consume(&TokenType::Indent, "Expected an indented block after ':'")?;
+
         let mut statements = Vec::new();
-        
-        while !self.is_at_end() && !self.check(&TokenType::EOF) {
+        let mut lines = Vec::new();
+        self.skip_newlines();
+        let doc = self.about()?;
+        self.skip_newlines();
+        while !self.is_at_end() && !self.check(&TokenType::Dedent) {
+            lines.push(self.peek().line);
             statements.push(self.declaration()?);
+            self.skip_newlines();
         }
+        self.consume(&TokenType::Dedent, "Expected the block to end")?;
         
-        Ok(Node::Block(statements))
+        Ok((Node::Block(statements, lines), doc))
+    }
+
+    // The ':' ends its line and no indented line follows
+    fn body_is_empty(&self) -> bool {
+        let mut ahead = self.current;
+        while self.tokens[ahead].token_type == TokenType::NewLine {
+            ahead += 1;
+        }
+        (ahead > self.current && self.tokens[ahead].token_type != TokenType::Indent)
+            || self.tokens[ahead].token_type == TokenType::EOF
     }
 
-    fn constructor_declaration(&mut self) -> Result<Node, String> {
-        self.consume(&TokenType::Defaults, "Expected 'defaults' after 'build'")?;
-        let params = self.parameter_list()?;
+    fn about(&mut self) -> Result<Option<String>, String> {
+        if !self.match_token(&[TokenType::About]) {
+            return Ok(None);
+        }
+        match self.advance().token_type.clone() {
+            TokenType::String(text) => Ok(Some(text)),
+            _ => Err("Expected text after 'about'".to_string()),
+        }
+    }
+
+    fn constructor_declaration(&mut self, base: Option<String>) -> Result<Node, String> {
+        // A bare `build:` takes no arguments
+        let (params, variadic) = if self.match_token(&[TokenType::Defaults]) {
+            self.parameter_list()?
+        } else {
+            (Vec::new(), false)
+        };
         self.consume(&TokenType::Colon, "Expected ':' after constructor parameters")?;
-        let body = Box::new(self.block()?);
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        let outer_base = std::mem::replace(&mut self.base, base);
+        let body = self.block();
+        self.loop_depth = loop_depth;
+        self.base = outer_base;
+        let body = Box::new(body?);
         
         Ok(Node::TaskDecl {
             name: "build".to_string(),
             params,
+            variadic,
             return_type: None,
             body,
+            doc: None,
         })
     }
 
     fn expression(&mut self) -> Result<Node, String> {
-        match self.peek().token_type {
-            TokenType::Identifier(_) => {
-                let name = self.consume_identifier("Expected identifier")?;
-                Ok(Node::Variable(name))
-            },
-            TokenType::String(_) => self.string_literal(),
-            TokenType::Number(_) => {
-                if let TokenType::Number(n) = self.peek().token_type {
-                    self.advance();
-                    Ok(Node::Literal(Value::Number(n)))
-                } else {
-                    Err("Expected number".to_string())
-                }
-            },
-            TokenType::Boolean(_) => {
-                if let TokenType::Boolean(b) = self.peek().token_type {
-                    self.advance();
-                    Ok(Node::Literal(Value::Boolean(b)))
-                } else {
-                    Err("Expected boolean".to_string())
-                }
-            },
-            TokenType::Null => {
-                self.advance();
-                Ok(Node::Literal(Value::Null))
-            },
-            TokenType::New => {
-                self.new_expression()
-            },
-            TokenType::Await => {
-                Ok(Node::AwaitExpr {
-                    value: Box::new(self.expression()?),
-                })
-            },
-            TokenType::Quote => {
-                let mut parts = Vec::new();
-                while !self.check(&TokenType::Quote) && !self.is_at_end() {
-                    if self.match_token(&[TokenType::LeftBrace]) {
-                        let expr = self.expression()?;
-                        self.consume(&TokenType::RightBrace, "Expected '}' after expression")?;
-                        parts.push(expr);
-                    } else {
-                        let text = self.consume_string_part()?;
-                        parts.push(Node::Literal(Value::String(text)));
-                    }
-                }
-                self.consume(&TokenType::Quote, "Expected '\"' after string")?;
-                Ok(Node::StringInterpolation { parts })
-            },
-            TokenType::TypeMapping => {
-                let mut entries = Vec::new();
-                loop {
-                    let param_name = self.consume_identifier("Expected parameter name")?;
-                    let (param_type, value) = if self.match_token(&[TokenType::As]) {
-                        let param_type = self.type_annotation()?;
-                        self.consume(&TokenType::Is, "Expected 'is' after type")?;
-                        let value = self.expression()?;
-                        (Some(param_type), value)
-                    } else if self.match_token(&[TokenType::Is]) {
-                        let value = self.expression()?;
-                        (None, value)
-                    } else {
-                        return Err("Expected 'as' or 'is' after parameter name".to_string());
-                    };
-                    entries.push((param_name, param_type, value));
-                    if !self.match_token(&[TokenType::Comma]) {
-                        break;
-                    }
-                    while self.peek().token_type == TokenType::NewLine {
-                        self.advance();
-                    }
-                }
-                Ok(Node::MappingLiteral { entries })
-            },
-            TokenType::TypeList => {
-                self.advance();
-                let element_type = Box::new(self.type_annotation()?);
-                self.consume(&TokenType::CloseBracket, "Expected ']' after type parameter")?;
-                Ok(Node::ListType { element_type })
-            },
-            TokenType::TypePromise => {
-                self.advance();
-                let value_type = Box::new(self.type_annotation()?);
-                self.consume(&TokenType::CloseBracket, "Expected ']' after type parameter")?;
-                Ok(Node::PromiseType { value_type })
-            },
-            // TokenType::TypeAnnotation => {
-            //     let type_name = self.consume_identifier("Expected type name")?;
-            //     match type_name.as_str() {
-            //         "Mapping" => Ok(Node::MappingType {
-            //             key_type: Box::new(Node::TypeAnnotation("Text".to_string())),
-            //             value_type: Box::new(Node::TypeAnnotation("Any".to_string())),
-            //         }),
-            //         "Whole" => Ok(Node::TypeAnnotation("Whole".to_string())),
-            //         "Decimal" => Ok(Node::TypeAnnotation("Decimal".to_string())),
-            //         "Text" => Ok(Node::TypeAnnotation("Text".to_string())),
-            //         "Truth" => Ok(Node::TypeAnnotation("Logic".to_string())),
-            //         "Nothing" => Ok(Node::TypeAnnotation("Nothing".to_string())),
-            //         "Any" => Ok(Node::TypeAnnotation("Any".to_string())),
-            //         "Number" => Ok(Node::TypeAnnotation("Number".to_string())),
-            //         "Error" => Ok(Node::TypeAnnotation("Error".to_string())),
-            //         _ => Err(format!("Unknown type: {}", type_name)),
-            //     }
-            // },
-            _ => Err("Expected expression".to_string()),
+        let expr = self.otherwise()?;
+
+        // In value position, `as` converts the value rather than declaring a type
+        if self.match_token(&[TokenType::As]) {
+            let target = Box::new(self.type_annotation()?);
+            return Ok(Node::Cast {
+                value: Box::new(expr),
+                target,
+            });
         }
+
+        Ok(expr)
     }
 
     fn new_expression(&mut self) -> Result<Node, String> {
@@ -491,6 +572,45 @@ impl Parser {
         })
     }
 
+    // `count becomes count + 1`, or the shorthands `count increase by 1` and
+    // `count decrease by 1`. The words `increase`, `decrease` and `by` are only
+    // special here, so they still work as names elsewhere
+    fn update_statement(&mut self) -> Result<Option<Node>, String> {
+        let TokenType::Identifier(name) = &self.peek().token_type else {
+            return Ok(None);
+        };
+        let name = name.clone();
+        let word = |token: &Token, word: &str| matches!(&token.token_type, TokenType::Identifier(w) if w == word);
+
+        let operator = match &self.peek_next().token_type {
+            TokenType::Becomes => None,
+            _ if self.current + 2 < self.tokens.len() && word(&self.tokens[self.current + 2], "by") => {
+                match &self.peek_next().token_type {
+                    TokenType::Identifier(w) if w == "increase" => Some(TokenType::Plus),
+                    TokenType::Identifier(w) if w == "decrease" => Some(TokenType::Minus),
+                    _ => return Ok(None),
+                }
+            },
+            _ => return Ok(None),
+        };
+
+        self.advance(); // Consume the name
+        self.advance(); // Consume 'becomes', or 'increase'/'decrease'
+        let value = match operator {
+            None => self.expression()?,
+            Some(operator) => {
+                self.advance(); // Consume 'by'
+                Node::Binary {
+                    left: Box::new(Node::Variable(name.clone())),
+                    operator,
+                    right: Box::new(self.expression()?),
+                }
+            },
+        };
+        Ok(Some(Node::Assignment { name, value: Box::new(value) }))
+    }
+
+    #[allow(dead_code)]
     fn assignment(&mut self) -> Result<Node, String> {
         let name = match &self.tokens[self.current - 1] {
             Token { token_type: TokenType::Identifier(id), .. } => id.clone(),
@@ -519,6 +639,22 @@ impl Parser {
         }
     }
 
+    // `name otherwise "anonymous"` falls back when the left side is null
+    fn otherwise(&mut self) -> Result<Node, String> {
+        let mut expr = self.or()?;
+
+        while self.match_token(&[TokenType::Otherwise]) {
+            let right = Box::new(self.or()?);
+            expr = Node::Binary {
+                left: Box::new(expr),
+                operator: TokenType::Otherwise,
+                right,
+            };
+        }
+
+        Ok(expr)
+    }
+
     fn or(&mut self) -> Result<Node, String> {
         let mut expr = self.and()?;
 
@@ -543,7 +679,7 @@ impl Parser {
             let right = Box::new(self.equality()?);
             expr = Node::Binary {
                 left: Box::new(expr),
-                operator: operator,
+                operator,
                 right,
             };
         }
@@ -554,12 +690,20 @@ impl Parser {
     fn equality(&mut self) -> Result<Node, String> {
         let mut expr = self.comparison()?;
 
-        while self.match_token(&[TokenType::Is]) {
-            let operator = self.previous().token_type.clone();
+        while self.match_token(&[TokenType::Is, TokenType::Equals, TokenType::NotEquals]) {
+            let mut operator = self.previous().token_type.clone();
+            // `is not`, `is greater than` and `is less than` read as their symbols
+            if operator == TokenType::Is {
+                if self.match_token(&[TokenType::Not]) {
+                    operator = TokenType::NotEquals;
+                } else if let Some(symbol) = self.comparison_words() {
+                    operator = symbol;
+                }
+            }
             let right = Box::new(self.comparison()?);
             expr = Node::Binary {
                 left: Box::new(expr),
-                operator: operator,
+                operator,
                 right,
             };
         }
@@ -567,15 +711,30 @@ impl Parser {
         Ok(expr)
     }
 
+    fn comparison_words(&mut self) -> Option<TokenType> {
+        let symbol = match (&self.peek().token_type, &self.peek_next().token_type) {
+            (TokenType::Identifier(word), TokenType::Identifier(than)) if than == "than" => match word.as_str() {
+                "greater" => TokenType::GreaterThan,
+                "less" => TokenType::LessThan,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        self.advance();
+        self.advance();
+        Some(symbol)
+    }
+
     fn comparison(&mut self) -> Result<Node, String> {
         let mut expr = self.term()?;
 
-        while self.match_token(&[TokenType::GreaterThan]) {
+        while self.match_token(&[TokenType::GreaterThan, TokenType::GreaterThanOrEqual,
+                                 TokenType::LessThan, TokenType::LessThanOrEqual, TokenType::Contains]) {
             let operator = self.previous().token_type.clone();
             let right = Box::new(self.term()?);
             expr = Node::Binary {
                 left: Box::new(expr),
-                operator: operator,
+                operator,
                 right,
             };
         }
@@ -591,7 +750,7 @@ impl Parser {
             let right = Box::new(self.factor()?);
             expr = Node::Binary {
                 left: Box::new(expr),
-                operator: operator,
+                operator,
                 right,
             };
         }
@@ -602,12 +761,12 @@ impl Parser {
     fn factor(&mut self) -> Result<Node, String> {
         let mut expr = self.unary()?;
 
-        while self.match_token(&[TokenType::Multiply, TokenType::Divide]) {
+        while self.match_token(&[TokenType::Multiply, TokenType::Divide, TokenType::Modulo]) {
             let operator = self.previous().token_type.clone();
             let right = Box::new(self.unary()?);
             expr = Node::Binary {
                 left: Box::new(expr),
-                operator: operator,
+                operator,
                 right,
             };
         }
@@ -616,17 +775,28 @@ impl Parser {
     }
 
     fn unary(&mut self) -> Result<Node, String> {
-        if self.match_token(&[TokenType::Minus]) {
+        if self.match_token(&[TokenType::Minus, TokenType::Not]) {
             let operator = self.previous_token_type();
-            let right = Box::new(self.unary()?);
-            Ok(Node::Binary {
-                left: Box::new(Node::Literal(Value::Number(0.0))),
-                operator,
-                right,
-            })
+            let operand = Box::new(self.unary()?);
+            Ok(Node::Unary { operator, operand })
         } else {
-            self.call()
+            self.power()
+        }
+    }
+
+    // `**` binds tighter than a leading minus and groups to the right, so
+    // -2 ** 2 is -4 and 2 ** 3 ** 2 is 2 ** 9
+    fn power(&mut self) -> Result<Node, String> {
+        let base = self.call()?;
+        if self.match_token(&[TokenType::Power]) {
+            let exponent = Box::new(self.unary()?);
+            return Ok(Node::Binary {
+                left: Box::new(base),
+                operator: TokenType::Power,
+                right: exponent,
+            });
         }
+        Ok(base)
     }
 
     fn call(&mut self) -> Result<Node, String> {
@@ -641,6 +811,37 @@ impl Parser {
                     object: Box::new(expr),
                     name,
                 };
+            } else if self.match_token(&[TokenType::OpenBracket]) {
+                let object = Box::new(expr);
+                let start = Box::new(self.expression()?);
+                expr = if self.match_token(&[TokenType::DotDot]) {
+                    let end = Box::new(self.expression()?);
+                    Node::Slice { object, start, end }
+                } else {
+                    Node::Index { object, index: start }
+                };
+                self.consume(&TokenType::CloseBracket, "Expected ']' after index")?;
+            } else if self.match_token(&[TokenType::At]) {
+                // `xs at 0`, or `item at 0 of xs` where the leading word only names the element
+                let index = Box::new(self.unary()?);
+                let object = if self.match_token(&[TokenType::Of]) {
+                    if !matches!(expr, Node::Variable(_)) {
+                        return Err("Expected a word before 'at ... of'".to_string());
+                    }
+                    self.unary()?
+                } else {
+                    expr
+                };
+                expr = Node::Index { object: Box::new(object), index };
+            } else if let (Node::Variable(property), TokenType::Of) = (&expr, &self.peek().token_type) {
+                // `name of person` reads a field, with the field named first
+                let property = property.clone();
+                self.advance();
+                expr = Node::PropertyAccess { object: Box::new(self.unary()?), property };
+            } else if matches!(expr, Node::Variable(_)) && self.match_token(&[TokenType::With]) {
+                // `greet with "Ada"` is another way to write `greet("Ada")`
+                let args = self.argument_list()?;
+                expr = Node::WithExpr { base: Box::new(expr), args };
             } else {
                 break;
             }
@@ -676,42 +877,49 @@ impl Parser {
                 self.advance();
                 Ok(Node::Variable(name))
             },
-            TokenType::String(value) => {
+            TokenType::Me => {
                 self.advance();
-                Ok(Node::Literal(Value::String(value)))
+                Ok(Node::Me)
             },
-            TokenType::LeftBrace => {
+            // `my count` is `me.count`
+            TokenType::My => {
                 self.advance();
-                let expr = self.expression()?;
-                self.consume(&TokenType::RightBrace, "Expected '}' after expression")?;
-                Ok(expr)
+                let property = self.consume_identifier("Expected a field name after 'my'")?;
+                Ok(Node::PropertyAccess { object: Box::new(Node::Me), property })
             },
-            TokenType::Quote => {
+            TokenType::Each => {
                 self.advance();
-                let mut parts = Vec::new();
-                
-                while !self.check(&TokenType::Quote) && !self.is_at_end() {
-                    if self.match_token(&[TokenType::LeftBrace]) {
-                        let expr = self.expression()?;
-                        self.consume(&TokenType::RightBrace, "Expected '}' after expression")?;
-                        parts.push(expr);
-                    } else {
-                        let text = self.consume_string_part()?;
-                        parts.push(Node::Literal(Value::String(text)));
-                    }
-                }
-                
-                self.consume(&TokenType::Quote, "Expected '\"' after string")?;
-                Ok(Node::StringInterpolation { parts })
+                self.map_expression()
             },
-            TokenType::Number(value) => {
+            TokenType::String(_) => self.string_literal(),
+            TokenType::New => {
                 self.advance();
-                Ok(Node::Literal(Value::Number(value)))
+                self.new_expression()
             },
-            TokenType::Boolean(value) => {
+            TokenType::Await => {
                 self.advance();
-                Ok(Node::Literal(Value::Boolean(value)))
+                Ok(Node::AwaitExpr {
+                    value: Box::new(self.expression()?),
+                })
             },
+            TokenType::LeftBrace => {
+                self.advance();
+                // `{name: ...` starts a record, anything else is just grouped
+                if matches!(self.peek().token_type, TokenType::Identifier(_)) && self.peek_next().token_type == TokenType::Colon {
+                    return self.record_literal();
+                }
+                let expr = self.expression()?;
+                self.consume(&TokenType::RightBrace, "Expected '}' after expression")?;
+                Ok(expr)
+            },
+            TokenType::Number(value) => {
+                // Written with a point, 4.0 is a Decimal even though it has no fraction
+                let decimal = self.advance().literal.contains('.') || value.fract() != 0.0;
+                Ok(Node::Literal(if decimal { Value::Decimal(value) } else { Value::Number(value) }))
+            },
+            TokenType::Boolean(_) => self.boolean_literal(),
+            TokenType::OpenBracket => self.list_literal(),
+            TokenType::TypePromise => self.promise_literal(),
             TokenType::Null => {
                 self.advance();
                 Ok(Node::Literal(Value::Null))
@@ -720,28 +928,112 @@ impl Parser {
                 self.advance();
                 Ok(Node::MappingLiteral { entries: Vec::new() })
             },
+            // `config as Mapping includes ...` consumes its own
+            TokenType::Includes => Err("'includes' is only valid after a Mapping type".to_string()),
             _ => Err("Expected expression".to_string()),
         }
     }
 
-    fn consume_string_part(&mut self) -> Result<String, String> {
-        if let TokenType::StringPart(text) = &self.peek().token_type {
-            let text = text.clone();
-            self.advance();
-            Ok(text)
-        } else {
-            Err("Expected string part".to_string())
+    fn boolean_literal(&mut self) -> Result<Node, String> {
+        match self.peek().token_type {
+            TokenType::Boolean(value) => {
+                self.advance();
+                Ok(Node::Literal(Value::Boolean(value)))
+            },
+            _ => Err("Expected 'true' or 'false'".to_string()),
+        }
+    }
+
+    // `[1, 2, 3]`; the element type is left for the analyzer to infer
+    fn list_literal(&mut self) -> Result<Node, String> {
+        self.consume(&TokenType::OpenBracket, "Expected '[' to start a list")?;
+        let mut elements = Vec::new();
+
+        if !self.check(&TokenType::CloseBracket) {
+            loop {
+                elements.push(self.expression()?);
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
         }
+
+        self.consume(&TokenType::CloseBracket, "Expected ']' after list elements")?;
+        Ok(Node::ArrayLiteral {
+            elements,
+            type_annotation: None,
+        })
+    }
+
+    // The fields of `{name: "Ada", age: 36}`, after the opening brace
+    fn record_literal(&mut self) -> Result<Node, String> {
+        let mut fields: Vec<(String, Node)> = Vec::new();
+        loop {
+            let name = self.consume_identifier("Expected a field name")?;
+            if fields.iter().any(|(field, _)| *field == name) {
+                return Err(format!("Field {} is given twice", name));
+            }
+            self.consume(&TokenType::Colon, "Expected ':' after field name")?;
+            fields.push((name, self.expression()?));
+            if !self.match_token(&[TokenType::Comma]) {
+                break;
+            }
+        }
+        self.consume(&TokenType::RightBrace, "Expected '}' after record fields")?;
+        Ok(Node::ObjectLiteral { fields })
+    }
+
+    // `Promise[Whole]`, written the same way as the type it belongs to
+    fn promise_literal(&mut self) -> Result<Node, String> {
+        self.consume(&TokenType::TypePromise, "Expected 'Promise'")?;
+        self.consume(&TokenType::OpenBracket, "Expected '[' after 'Promise'")?;
+        let value_type = match self.type_annotation()? {
+            Node::TypeAnnotation(type_name) => type_name,
+            other => return Err(format!("Expected a type name in Promise[...], got {:?}", other)),
+        };
+        self.consume(&TokenType::CloseBracket, "Expected ']' after promise type")?;
+        Ok(Node::Literal(Value::Promise(value_type)))
+    }
+
+    fn map_expression(&mut self) -> Result<Node, String> {
+        let var = self.consume_identifier("Expected a name after 'each'")?;
+        if !matches!(&self.peek().token_type, TokenType::Identifier(word) if word == "in") {
+            return Err("Expected 'in' after the name in 'each'".to_string());
+        }
+        self.advance();
+        let iterable = Box::new(self.expression()?);
+        self.consume(&TokenType::Becomes, "Expected 'becomes' after the list in 'each'")?;
+        let transform = Box::new(self.expression()?);
+        let filter = if self.match_token(&[TokenType::When]) {
+            Some(Box::new(self.expression()?))
+        } else {
+            None
+        };
+        Ok(Node::MapExpr { var, iterable, transform, filter })
     }
 
     fn when_statement(&mut self) -> Result<Node, String> {
         let condition = Box::new(self.expression()?);
         self.consume(&TokenType::Colon, "Expected ':' after when condition")?;
         let then_branch = Box::new(self.block()?);
+
+        // `or:` starts a line of its own after the block
+        let mut ahead = self.current;
+        while self.tokens[ahead].token_type == TokenType::NewLine {
+            ahead += 1;
+        }
+        if self.tokens[ahead].token_type == TokenType::Or {
+            self.current = ahead;
+        }
         
+        // `or when` chains another condition, which takes any later branches with it
         let else_branch = if self.match_token(&[TokenType::Or]) {
-            self.consume(&TokenType::Colon, "Expected ':' after 'or'")?;
-            Some(Box::new(self.block()?))
+            if self.match_token(&[TokenType::When]) {
+                Some(Box::new(self.when_statement()?))
+            } else {
+                self.consume(&TokenType::Colon, "Expected ':' after 'or'")?;
+                Some(Box::new(self.block()?))
+            }
         } else {
             None
         };
@@ -753,23 +1045,70 @@ impl Parser {
         })
     }
 
-    fn loop_statement(&mut self) -> Result<Node, String> {
-        self.consume(&TokenType::While, "Expected 'while' after 'loop'")?;
-        let condition = Box::new(self.expression()?);
-        self.consume(&TokenType::Colon, "Expected ':' after loop condition")?;
+    fn each_loop(&mut self) -> Result<Node, String> {
+        let var = self.consume_identifier("Expected a name after 'loop each'")?;
+        let index = if self.match_token(&[TokenType::At]) {
+            self.consume_identifier("Expected a name for the position after 'at'")?
+        } else {
+            "index".to_string()
+        };
+        if !matches!(&self.peek().token_type, TokenType::Identifier(word) if word == "in") {
+            return Err("Expected 'in' after the name in 'loop each'".to_string());
+        }
+        self.advance();
+        let iterable = Box::new(self.expression()?);
+        self.consume(&TokenType::Colon, "Expected ':' after the list in 'loop each'")?;
+
+        self.loop_depth += 1;
+        let body = self.block();
+        self.loop_depth -= 1;
+
+        Ok(Node::EachLoop { var, index, iterable, body: Box::new(body?) })
+    }
+
+    // `using open_log() as log:` binds the value for the block and releases it after
+    fn using_block(&mut self) -> Result<Node, String> {
+        // Parsed below `expression` so the `as` isn't read as a conversion
+        let base = Box::new(self.otherwise()?);
+        self.consume(&TokenType::As, "Expected 'as' after the value in 'using'")?;
+        let name = self.consume_identifier("Expected a name after 'as'")?;
+        self.consume(&TokenType::Colon, "Expected ':' after the 'using' name")?;
         let body = Box::new(self.block()?);
+        Ok(Node::UsingExpr { base, name, body })
+    }
 
-        Ok(Node::LoopStmt { condition, body })
+    fn loop_statement(&mut self) -> Result<Node, String> {
+        if self.match_token(&[TokenType::Each]) {
+            return self.each_loop();
+        }
+        let condition = if self.match_token(&[TokenType::Colon]) {
+            None
+        } else {
+            self.consume(&TokenType::While, "Expected 'while' or ':' after 'loop'")?;
+            let condition = Box::new(self.expression()?);
+            self.consume(&TokenType::Colon, "Expected ':' after loop condition")?;
+            Some(condition)
+        };
+
+        self.loop_depth += 1;
+        let body = self.block();
+        self.loop_depth -= 1;
+
+        Ok(Node::LoopStmt { condition, body: Box::new(body?) })
     }
 
     fn show_statement(&mut self) -> Result<Node, String> {
         self.advance(); // Consume 'show'
-        let expr = self.expression()?;
-        Ok(Node::ShowStmt(Box::new(expr)))
+        let mut values = vec![self.expression()?];
+        while self.match_token(&[TokenType::Comma]) {
+            values.push(self.expression()?);
+        }
+        Ok(Node::ShowStmt(values))
     }
 
     fn raise_statement(&mut self) -> Result<Node, String> {
-        let message = Box::new(self.expression()?);
+        // Parse below `expression` so the `as` introducing the error type isn't read as a cast
+        let message = Box::new(self.or()?);
         self.consume(&TokenType::As, "Expected 'as' after raise message")?;
         let error_type = Box::new(self.type_annotation()?);
         
@@ -785,6 +1124,17 @@ impl Parser {
     }
 
     fn expression_statement(&mut self) -> Result<Node, String> {
+        // `obj.field is value`, `field of obj is value` and `my field is value` set
+        // the field rather than comparing it
+        let start = self.current;
+        if let Ok(Node::Get { object, name } | Node::PropertyAccess { object, property: name }) = self.call() {
+            if self.match_token(&[TokenType::Is]) {
+                let value = Box::new(self.expression()?);
+                return Ok(Node::Set { object, name, value });
+            }
+        }
+        self.current = start;
+
         let expr = self.expression()?;
         Ok(Node::ExpressionStmt(Box::new(expr)))
     }
@@ -807,25 +1157,36 @@ impl Parser {
             let mut chars = string_content.chars().peekable();
             
             while let Some(c) = chars.next() {
-                if c == '{' {
+                // `{}` is left as written, for format()
+                if c == '{' && chars.peek() == Some(&'}') {
+                    chars.next();
+                    current_text.push_str("{}");
+                } else if c == '{' {
                     // Add accumulated text if any
                     if !current_text.is_empty() {
                         parts.push(Node::Literal(Value::String(current_text.clone())));
                         current_text.clear();
                     }
                     
-                    // Collect variable name
-                    let mut var_name = String::new();
-                    while let Some(&next_char) = chars.peek() {
-                        if next_char == '}' {
-                            chars.next(); // consume the '}'
+                    // Collect up to the matching '}', so braces inside are kept
+                    let mut inner = String::new();
+                    let mut depth = 1;
+                    for next_char in chars.by_ref() {
+                        match next_char {
+                            '{' => depth += 1,
+                            '}' => depth -= 1,
+                            _ => {},
+                        }
+                        if depth == 0 {
                             break;
                         }
-                        var_name.push(chars.next().unwrap());
+                        inner.push(next_char);
                     }
-                    
-                    // Add variable reference
-                    parts.push(Node::Variable(var_name));
+                    if depth > 0 {
+                        return Err(format!("Unclosed '{{' in text: \"{}\"", string_content));
+                    }
+
+                    parts.push(Self::interpolated_expression(&inner)?);
                 } else {
                     current_text.push(c);
                 }
@@ -842,6 +1203,18 @@ impl Parser {
         }
     }
 
+    // What sits between the braces is parsed as an expression of its own
+    fn interpolated_expression(source: &str) -> Result<Node, String> {
+        let tokens = Tokenizer::new(source).tokenize().map_err(|e| e.to_string())?;
+        let mut parser = Parser::new(tokens);
+        let expr = parser.expression()
+            .map_err(|msg| format!("Invalid interpolation {{{}}}: {}", source, msg))?;
+        if !parser.is_at_end() {
+            return Err(format!("Invalid interpolation {{{}}}: unexpected {}", source, parser.peek().literal));
+        }
+        Ok(expr)
+    }
+
     fn argument_list(&mut self) -> Result<Vec<Node>, String> {
         let mut args = Vec::new();
 
@@ -861,6 +1234,15 @@ impl Parser {
         &self.tokens[self.current]
     }
 
+    fn peek_next(&self) -> &Token {
+        &self.tokens[(self.current + 1).min(self.tokens.len() - 1)]
+    }
+
+    // Newlines separate statements, but any number of them may sit between two
+    fn skip_newlines(&mut self) {
+        while self.match_token(&[TokenType::NewLine]) {}
+    }
+
     fn is_at_end(&self) -> bool {
         matches!(self.peek().token_type, TokenType::EOF)
     }
@@ -913,47 +1295,40 @@ impl Parser {
 
     fn statement(&mut self) -> Result<Node, String> {
         match self.peek().token_type {
-            TokenType::Show => {
-                self.advance(); // Consume 'show'
-                match &self.peek().token_type {
-                    TokenType::Identifier(_) => {
-                        let name = self.consume_identifier("Expected variable name after 'show'")?;
-                        Ok(Node::ShowStmt(Box::new(Node::Variable(name))))
-                    },
-                    TokenType::String(_) => {
-                        let expr = self.string_literal()?;
-                        Ok(Node::ShowStmt(Box::new(expr)))
-                    },
-                    TokenType::Number(_) => {
-                        if let TokenType::Number(n) = self.advance().token_type {
-                            Ok(Node::ShowStmt(Box::new(Node::Literal(Value::Number(n)))))
-                        } else {
-                            Err("Expected number".to_string())
-                        }
-                    },
-                    TokenType::Boolean(_) => {
-                        let expr = self.boolean_literal()?;
-                        Ok(Node::ShowStmt(Box::new(expr)))
-                    },
-                    TokenType::Null | TokenType::TypeMapping => {
-                        Ok(Node::ShowStmt(Box::new(Node::Literal(Value::Null))))
-                    },
-                    TokenType::TypePromise => {
-                        let expr = self.promise_literal()?;
-                        Ok(Node::ShowStmt(Box::new(expr)))
-                    },
-                    TokenType::TypeList => {
-                        let expr = self.list_literal()?;
-                        Ok(Node::ShowStmt(Box::new(expr)))
-                    },
-                    _ => Err("Expected variable name, string, or number after 'show'".to_string()),
+            TokenType::Show => self.show_statement(),
+            TokenType::Build => {
+                self.advance(); // Consume 'build'
+                if !matches!(&self.advance().token_type, TokenType::Identifier(word) if word == "base") {
+                    return Err("Expected 'base' after 'build'".to_string());
                 }
+                let base = self.base.clone()
+                    .ok_or("'build base' can only be used in the build of an object that inherits")?;
+                let args = if self.match_token(&[TokenType::With]) {
+                    self.argument_list()?
+                } else {
+                    Vec::new()
+                };
+                Ok(Node::BaseBuild { base, args })
+            },
+            TokenType::When => {
+                self.advance(); // Consume 'when'
+                self.when_statement()
             },
             TokenType::Raise => {
                 self.advance();
                 self.raise_statement()
             },
-            TokenType::Returns => {
+            TokenType::Assert => {
+                self.advance();
+                let condition = Box::new(self.expression()?);
+                let message = if self.match_token(&[TokenType::Comma]) {
+                    Some(Box::new(self.expression()?))
+                } else {
+                    None
+                };
+                Ok(Node::AssertStmt { condition, message })
+            },
+            TokenType::Returns | TokenType::Output => {
                 self.advance();
                 self.return_statement()
             },
@@ -967,11 +1342,16 @@ impl Parser {
             },
             TokenType::Emit => {
                 self.advance(); // Consume 'emit'
-                self.declaration()
+                Ok(Node::EmitStmt(Box::new(self.expression()?)))
             },
             TokenType::Using => {
                 self.advance(); // Consume 'using'
-                self.declaration()
+                if let TokenType::String(path) = &self.peek().token_type {
+                    let path = path.clone();
+                    self.advance();
+                    return Ok(Node::UsingFile(path));
+                }
+                self.using_block()
             },
             TokenType::With => {
                 self.advance(); // Consume 'with'
@@ -1009,17 +1389,14 @@ impl Parser {
                 self.advance(); // Consume 'becomes'
                 self.declaration()
             },
-            TokenType::My => {
-                self.advance(); // Consume 'my'
-                self.declaration()
-            },
-            TokenType::About => {
-                self.advance(); // Consume 'about'
-                self.declaration()
+            TokenType::About => Err("'about' must open the body of a Task or Object".to_string()),
+            TokenType::Object => {
+                self.advance(); // Consume 'Object'
+                self.object_declaration()
             },
-            TokenType::Me => {
-                self.advance(); // Consume 'me'
-                self.declaration()
+            TokenType::Task => {
+                self.advance(); // Consume 'Task'
+                self.task_declaration()
             },
             TokenType::Loop => {
                 self.advance(); // Consume 'loop'
@@ -1029,18 +1406,17 @@ impl Parser {
                 self.advance(); // Consume 'while'
                 self.loop_statement()
             },
-            TokenType::Emit => {
-                self.advance(); // Consume 'Emit'
-                self.declaration()
+            TokenType::Break | TokenType::Continue => {
+                let keyword = self.advance().token_type.clone();
+                if self.loop_depth == 0 {
+                    return Err(format!("'{}' outside of a loop", self.previous().literal));
+                }
+                Ok(if keyword == TokenType::Break { Node::BreakStmt } else { Node::ContinueStmt })
             },
             TokenType::Match => {
                 self.advance(); // Consume 'match'
                 self.declaration()
             },
-            TokenType::Output => {
-                self.advance(); // Consume 'output'
-                self.declaration()
-            },
             _ => self.expression_statement(),
         }
     }
@@ -1077,15 +1453,14 @@ impl Parser {
                 break;
             }
             
-            // Skip any newlines after comma
-            while self.peek().token_type == TokenType::NewLine {
-                self.advance();
-            }
+            // Entries may continue on the next line after a comma
+            self.skip_newlines();
         }
         
         Ok(Node::MappingLiteral { entries })
     }
 
+    #[allow(dead_code)]
     fn type_from_annotation(&mut self, type_node: &Node) -> Result<Type, String> {
         match type_node {
             Node::MappingType { key_type, value_type } => {
@@ -1114,3 +1489,307 @@ impl Parser {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::Tokenizer;
+
+    fn parse(source: &str) -> Vec<Node> {
+        let tokens = Tokenizer::new(source).tokenize().unwrap();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    fn parse_error(source: &str) -> String {
+        let tokens = Tokenizer::new(source).tokenize().unwrap();
+        match Parser::new(tokens).parse() {
+            Err(NairError::Parse { msg, .. }) => msg,
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn braces_with_a_field_name_make_a_record() {
+        let statements = parse("p is {name: \"Ada\", age: 36}\nq is {age}");
+        assert!(matches!(&statements[..], [
+            Node::VariableDecl { initializer: Some(record), .. },
+            Node::VariableDecl { initializer: Some(grouped), .. },
+        ] if matches!(&**record, Node::ObjectLiteral { fields } if fields.len() == 2 && fields[1].0 == "age")
+            && matches!(&**grouped, Node::Variable(name) if name == "age")), "{:?}", statements);
+        assert_eq!(parse_error("p is {a: 1, a: 2}"), "Field a is given twice");
+    }
+
+    #[test]
+    fn field_of_object_reads_a_field() {
+        let statements = parse("c is city of home of p + 1");
+        let [Node::VariableDecl { initializer: Some(init), .. }] = &statements[..] else { panic!("{:?}", statements) };
+        let Node::Binary { left, .. } = &**init else { panic!("{:?}", init) };
+        assert!(matches!(&**left, Node::PropertyAccess { object, property }
+            if property == "city" && matches!(&**object, Node::PropertyAccess { property, .. } if property == "home")), "{:?}", left);
+    }
+
+    #[test]
+    fn declarations_take_type_keywords() {
+        assert!(matches!(&parse("x as Whole is 1")[..], [Node::VariableDecl { name, type_annotation: Some(t), initializer: Some(_) }]
+            if name == "x" && matches!(&**t, Node::TypeAnnotation(t) if t == "Whole")));
+        for (source, expected) in [("f as Truth", "Truth"), ("f as Logic", "Truth"), ("e as Error", "Error"), ("a as Any", "Any")] {
+            assert!(matches!(&parse(source)[..], [Node::VariableDecl { type_annotation: Some(t), .. }]
+                if matches!(&**t, Node::TypeAnnotation(t) if t == expected)), "{}", source);
+        }
+    }
+
+    #[test]
+    fn stray_includes_is_explained() {
+        let expected = "'includes' is only valid after a Mapping type";
+        assert_eq!(parse_error("includes a is 1"), expected);
+        assert_eq!(parse_error("x is 5 includes 3"), expected);
+        assert_eq!(parse_error("show includes"), expected);
+        assert!(matches!(&parse("config as Mapping includes a is 1, b is 2")[..], [Node::VariableDecl { .. }]));
+    }
+
+    #[test]
+    fn only_the_last_parameter_is_variadic() {
+        assert!(matches!(parse("Task sum requires first, each rest as List:\n    returns first").as_slice(),
+            [Node::TaskDecl { params, variadic: true, .. }] if params.len() == 2));
+        assert_eq!(parse_error("Task sum requires each rest, last:\n    returns last"),
+                   "Only the last parameter can take 'each'");
+    }
+
+    #[test]
+    fn increase_by_is_an_assignment() {
+        let statements = parse("count increase by 2");
+        assert!(matches!(&statements[..], [Node::Assignment { name, value }]
+            if name == "count" && matches!(&**value, Node::Binary { operator: TokenType::Plus, left, .. }
+                if matches!(&**left, Node::Variable(v) if v == "count"))), "{:?}", statements);
+        assert!(matches!(&parse("count becomes 3")[..], [Node::Assignment { .. }]));
+        // Without `by` these are ordinary names
+        assert!(matches!(&parse("increase is 1")[..], [Node::VariableDecl { .. }]));
+    }
+
+    #[test]
+    fn each_in_becomes_is_a_map() {
+        let statements = parse("doubled is each x in nums becomes x * 2 when x > 1");
+        let [Node::VariableDecl { initializer: Some(value), .. }] = &statements[..] else {
+            panic!("expected a declaration, got {:?}", statements);
+        };
+        assert!(matches!(&**value, Node::MapExpr { var, filter: Some(_), .. } if var == "x"), "{:?}", value);
+        assert_eq!(parse_error("ys is each x nums becomes x"), "Expected 'in' after the name in 'each'");
+    }
+
+    #[test]
+    fn output_is_another_way_to_return() {
+        for source in ["output 1", "returns 1"] {
+            assert!(matches!(parse(source).as_slice(), [Node::ReturnStmt(value)]
+                if matches!(**value, Node::Literal(Value::Number(n)) if n == 1.0)), "{}", source);
+        }
+    }
+
+    #[test]
+    fn as_in_value_position_is_a_cast() {
+        let statements = parse("x is 3.7 as Whole");
+        let [Node::VariableDecl { initializer: Some(value), .. }] = statements.as_slice() else {
+            panic!("expected a declaration, got {:?}", statements);
+        };
+        assert!(matches!(&**value, Node::Cast { value, target }
+            if matches!(**value, Node::Literal(Value::Decimal(_)))
+                && matches!(&**target, Node::TypeAnnotation(name) if name == "Whole")), "{:?}", value);
+    }
+
+    #[test]
+    fn show_takes_any_expression() {
+        let statements = parse("show x + 1\nshow double(21)");
+        let [Node::ShowStmt(sum), Node::ShowStmt(call)] = statements.as_slice() else {
+            panic!("expected two show statements, got {:?}", statements);
+        };
+        assert!(matches!(&sum[..], [Node::Binary { operator: TokenType::Plus, .. }]), "{:?}", sum);
+        assert!(matches!(&call[..], [Node::Call { .. }]), "{:?}", call);
+    }
+
+    #[test]
+    fn show_takes_several_values() {
+        let statements = parse("show \"x =\", x, \"y =\", y");
+        assert!(matches!(&statements[..], [Node::ShowStmt(values)] if values.len() == 4), "{:?}", statements);
+    }
+
+    #[test]
+    fn interpolation_takes_an_expression() {
+        let statements = parse("show \"Hi {user.name}, {max(1, 2) + 1}\"");
+        let [Node::ShowStmt(values)] = statements.as_slice() else {
+            panic!("expected a show statement, got {:?}", statements);
+        };
+        assert!(matches!(&values[..], [Node::StringInterpolation { parts }]
+            if matches!(&parts[..], [Node::Literal(_), Node::Get { name, .. }, Node::Literal(_), Node::Binary { .. }] if name == "name")),
+            "{:?}", values);
+        assert!(parse_error("show \"{1 +}\"").starts_with("Invalid interpolation {1 +}"));
+        assert!(parse_error("show \"{a b}\"").starts_with("Invalid interpolation {a b}"));
+    }
+
+    #[test]
+    fn minus_parses_as_negation() {
+        let statements = parse("show - -3");
+        let [Node::ShowStmt(values)] = statements.as_slice() else {
+            panic!("expected a show statement, got {:?}", statements);
+        };
+        let [Node::Unary { operator: TokenType::Minus, operand }] = &values[..] else {
+            panic!("expected a negation, got {:?}", values);
+        };
+        assert!(matches!(&**operand, Node::Unary { operator: TokenType::Minus, operand }
+            if matches!(**operand, Node::Literal(Value::Number(n)) if n == 3.0)));
+    }
+
+    #[test]
+    fn break_and_continue_need_a_loop() {
+        assert!(parse_error("break").contains("break"));
+        assert!(parse_error("continue").contains("continue"));
+    }
+
+    #[test]
+    fn build_base_needs_an_inheriting_build() {
+        let statements = parse("Object Dog inherits Animal:\n    build:\n        build base with 1, 2");
+        let [Node::ObjectDecl { constructor: Some(constructor), .. }] = &statements[..] else {
+            panic!("expected an object, got {:?}", statements);
+        };
+        assert!(matches!(&**constructor, Node::TaskDecl { body, .. }
+            if matches!(&**body, Node::Block(body, _) if matches!(&body[..], [Node::BaseBuild { base, args }]
+                if base == "Animal" && args.len() == 2))), "{:?}", constructor);
+        let message = "'build base' can only be used in the build of an object that inherits";
+        assert_eq!(parse_error("build base"), message);
+        assert_eq!(parse_error("Object Dog:\n    build:\n        build base"), message);
+        assert_eq!(parse_error("Object Dog inherits Animal:\n    Task bark:\n        build base"), message);
+        assert_eq!(parse_error("Object Dog inherits Animal:\n    build:\n        build it"), "Expected 'base' after 'build'");
+    }
+
+    #[test]
+    fn tasks_inside_loops_are_outside_them() {
+        let source = "loop each x in [1, 2]:\n    Task f:\n        break\n    f()";
+        assert_eq!(parse_error(source), "'break' outside of a loop");
+        let source = "loop:\n    Object Counter:\n        build:\n            continue\n    break";
+        assert_eq!(parse_error(source), "'continue' outside of a loop");
+    }
+
+    #[test]
+    fn object_records_its_base() {
+        let statements = parse("Object Dog inherits Animal:");
+        assert!(matches!(&statements[..], [Node::ObjectDecl { name, base: Some(base), .. }]
+            if name == "Dog" && matches!(&**base, Node::TypeAnnotation(b) if b == "Animal")));
+    }
+
+    #[test]
+    fn field_is_value_sets_the_field() {
+        let statements = parse("me.count is me.count + 1");
+        assert!(matches!(&statements[..], [Node::Set { object, name, value }]
+            if matches!(**object, Node::Me) && name == "count" && matches!(**value, Node::Binary { .. })));
+    }
+
+    #[test]
+    fn show_true() {
+        let statements = parse("show true");
+        assert!(matches!(&statements[..], [Node::ShowStmt(values)]
+            if matches!(&values[..], [Node::Literal(Value::Boolean(true))])));
+    }
+
+    #[test]
+    fn show_a_list() {
+        let statements = parse("show [1, 2]");
+        assert!(matches!(&statements[..], [Node::ShowStmt(values)]
+            if matches!(&values[..], [Node::ArrayLiteral { elements, type_annotation: None }] if elements.len() == 2)));
+        assert!(parse_error("show [1, 2").contains("']'"));
+    }
+
+    #[test]
+    fn show_a_promise() {
+        let statements = parse("show Promise[Whole]");
+        assert!(matches!(&statements[..], [Node::ShowStmt(values)]
+            if matches!(&values[..], [Node::Literal(Value::Promise(t))] if t == "Whole")));
+        assert!(parse_error("show Promise Whole").contains("'['"));
+    }
+
+    #[test]
+    fn brackets_index_or_slice() {
+        assert!(matches!(&parse("show text[2]")[..], [Node::ShowStmt(values)] if matches!(values[..], [Node::Index { .. }])));
+        assert!(matches!(&parse("show text[1..3]")[..], [Node::ShowStmt(values)] if matches!(values[..], [Node::Slice { .. }])));
+    }
+
+    #[test]
+    fn tasks_and_objects_may_be_empty() {
+        let statements = parse("Task noop:\nObject Empty:\nObject Quiet:\n    Task hush:\n    Task rest:\nTask last:");
+        let empty = |body: &Node| matches!(body, Node::Block(statements, _) if statements.is_empty());
+        assert!(matches!(&statements[..], [
+            Node::TaskDecl { body: noop, .. },
+            Node::ObjectDecl { methods: empty_methods, .. },
+            Node::ObjectDecl { methods, .. },
+            Node::TaskDecl { body: last, .. },
+        ] if empty(noop) && empty_methods.is_empty() && methods.len() == 2 && empty(last)), "{:?}", statements);
+    }
+
+    #[test]
+    fn about_describes_a_task_or_object() {
+        let statements = parse("Task greet:\n    about \"Says hello\"\n    show 1\nObject Dog:\n    about \"A dog\"\n    build:\n        show 2");
+        assert!(matches!(&statements[..], [
+            Node::TaskDecl { doc: Some(task_doc), body, .. },
+            Node::ObjectDecl { doc: Some(object_doc), constructor: Some(_), .. },
+        ] if task_doc == "Says hello" && object_doc == "A dog" && matches!(&**body, Node::Block(b, _) if b.len() == 1)));
+        assert!(parse_error("when true:\n    about \"no\"\n    show 1").contains("Only a Task or Object"));
+    }
+
+    #[test]
+    fn comparisons_can_be_spelled_out() {
+        for (source, expected) in [
+            ("show x is not 5", TokenType::NotEquals),
+            ("show x is greater than 3", TokenType::GreaterThan),
+            ("show x is less than 3", TokenType::LessThan),
+            ("show x is 5", TokenType::Is),
+        ] {
+            let statements = parse(source);
+            assert!(matches!(&statements[..], [Node::ShowStmt(values)]
+                if matches!(&values[..], [Node::Binary { operator, .. }] if *operator == expected)), "{:?}", statements);
+        }
+    }
+
+    #[test]
+    fn at_indexes_like_brackets() {
+        for source in ["show mylist at 2", "show item at 2 of mylist"] {
+            let statements = parse(source);
+            let [Node::ShowStmt(values)] = &statements[..] else {
+                panic!("expected a show, got {:?}", statements);
+            };
+            assert!(matches!(&values[..], [Node::Index { object, index }]
+                if matches!(&**object, Node::Variable(name) if name == "mylist")
+                    && matches!(&**index, Node::Literal(Value::Number(n)) if *n == 2.0)), "{:?}", values);
+        }
+    }
+
+    #[test]
+    fn newline_ends_a_statement() {
+        // Without the line break this would be `x is 1 - x`
+        let statements = parse("x is 1\n-x\n");
+        assert!(matches!(&statements[..], [Node::VariableDecl { .. }, Node::ExpressionStmt(value)]
+            if matches!(**value, Node::Unary { .. })));
+    }
+
+    #[test]
+    fn statement_after_a_when_block_is_outside_it() {
+        for source in ["when x:\n    show 1\nshow 2", "when x: show 1\nshow 2"] {
+            let statements = parse(source);
+            assert!(matches!(&statements[..], [Node::WhenStmt { then_branch, .. }, Node::ShowStmt(_)]
+                if matches!(&**then_branch, Node::Block(body, _) if body.len() == 1)), "{:?}", statements);
+        }
+    }
+
+    #[test]
+    fn or_when_nests_in_the_else_branch() {
+        let statements = parse("when a:\n    show 1\nor when b:\n    show 2\nor:\n    show 3");
+        let [Node::WhenStmt { else_branch: Some(chained), .. }] = &statements[..] else {
+            panic!("expected one when, got {:?}", statements);
+        };
+        assert!(matches!(&**chained, Node::WhenStmt { else_branch: Some(last), .. }
+            if matches!(&**last, Node::Block(..))), "{:?}", chained);
+    }
+
+    #[test]
+    fn nested_blocks_close_together() {
+        let statements = parse("loop while x:\n    when y:\n        show 1\n    show 2\nshow 3");
+        assert!(matches!(&statements[..], [Node::LoopStmt { body, .. }, Node::ShowStmt(_)]
+            if matches!(&**body, Node::Block(body, _) if body.len() == 2)), "{:?}", statements);
+    }
+}
@@ -1,33 +1,194 @@
-use std::io::{self, Write};
-use crate::tokenizer::Tokenizer;
-use crate::parser::Parser;
-use crate::generator::{BytecodeGenerator, OpCode, Value};
-use std::collections::HashMap;
-use crate::analyzer::{Analyzer, Type};
+use std::io::{self, BufRead, Write};
+use crate::tokenizer::{Token, Tokenizer};
+use crate::parser::{Node, Parser};
+use crate::generator::{disassemble, BytecodeGenerator, Instance, ObjectDef, OpCode, TaskDef, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::rc::Rc;
+use crate::analyzer::{Analyzer, Type, Variable};
+use crate::error::NairError;
+use crate::json;
+use crate::serializer;
+use crate::formatter::format_program;
+
+// Compiled code along with the constants it refers to, ready to run on its own
+pub struct Program {
+    pub(crate) code: Vec<OpCode>,
+    pub(crate) lines: Vec<usize>,
+    pub(crate) constants: Vec<Value>,
+}
+
+impl Program {
+    // Runs on a fresh runtime and gives back everything shown or emitted, in order
+    pub fn run(&self) -> Result<Vec<Value>, NairError> {
+        self.run_on(Runtime::new())
+    }
+
+    // Like run, on a runtime set up beforehand, e.g. with budgets
+    pub fn run_on(&self, mut runtime: Runtime) -> Result<Vec<Value>, NairError> {
+        runtime.constants = self.constants.clone();
+        runtime.lines = self.lines.clone();
+        runtime.captured = Some(Vec::new());
+        runtime.execute_bytecode(self.code.clone())?;
+        Ok(runtime.captured.unwrap_or_default())
+    }
+
+    // The text of a `.nairc` file, which runs later without going back to the source
+    pub fn to_nairc(&self) -> Result<String, NairError> {
+        serializer::encode(self)
+            .map_err(|e| NairError::Compile(format!("Cannot save the program: {}", e)))
+    }
+
+    pub fn from_nairc(text: &str) -> Result<Program, NairError> {
+        serializer::decode(text).map_err(|e| NairError::Runtime(format!("Not a compiled nair program: {}", e)))
+    }
+}
+
+// Where a run has got to: the code, its stack and the next instruction
+struct VmState {
+    code: Vec<OpCode>,
+    stack: Vec<Value>,
+    ip: usize,
+}
+
+impl VmState {
+    fn new(code: Vec<OpCode>) -> Self {
+        VmState { code, stack: Vec::new(), ip: 0 }
+    }
+}
+
+// A name bound for the length of a block, and what it covered until then
+struct Binding {
+    name: String,
+    covered: Option<Value>,
+    covered_type: Option<String>,
+    releases: bool,  // a `using` name, whose object is released when the block ends
+}
 
 pub struct Runtime {
     tokenizer: Tokenizer,
     variables: HashMap<String, Value>,
-    variable_types: HashMap<String, Type>,
-    stack: Vec<Value>,
+    variable_types: HashMap<String, String>,
+    fixed: HashSet<String>,  // names declared `fixed`, which later input may not reassign
+    objects: HashMap<String, ObjectDef>,
+    tasks: HashMap<String, TaskDef>,
+    constants: Vec<Value>,  // the pool LoadConst indexes, shared by everything compiled so far
+    frames: Vec<HashMap<String, Value>>,  // locals of the Tasks being run, innermost last
+    bindings: Vec<Binding>,  // loop and `using` names in force, innermost last
+    rng_state: u64,  // xorshift state behind random() and random_int()
+    input: Option<Box<dyn BufRead>>,  // REPL lines and read_line() both come from here; stdin when None
+    output: Option<Box<dyn Write>>,  // where `show` writes; stdout when None
+    history: Vec<String>,     // code entered at the REPL, meta-commands excluded
+    events: Vec<Value>,  // values passed to `Emit`, oldest first
+    importing: Vec<PathBuf>,  // files being run or imported, innermost last; relative imports start from here
+    imported: HashSet<PathBuf>,  // files whose declarations are already registered
+    lines: Vec<usize>,  // source line of each instruction last compiled, 0 where unknown
+    failed_line: Option<usize>,  // source line of the innermost failing instruction, once one is known
+    captured: Option<Vec<Value>>,  // when embedded, shown and emitted values are kept here instead of printed
+    instruction_budget: Option<usize>,  // how many instructions may run over the runtime's life; no limit when None
+    output_budget: Option<usize>,  // how many bytes `show` may produce, likewise
+    executed: usize,  // instructions run so far
+    shown: usize,  // bytes shown so far
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Runtime {
     pub fn new() -> Self {
+        // Seed from the clock; scripts call seed(n) for reproducible runs
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+
         Runtime {
             tokenizer: Tokenizer::new(""),
             variables: HashMap::new(),
             variable_types: HashMap::new(),
-            stack: Vec::new(),
+            fixed: HashSet::new(),
+            objects: HashMap::new(),
+            tasks: HashMap::new(),
+            constants: Vec::new(),
+            frames: Vec::new(),
+            bindings: Vec::new(),
+            rng_state: Self::mix_seed(seed),
+            input: None,
+            output: None,
+            history: Vec::new(),
+            events: Vec::new(),
+            importing: Vec::new(),
+            imported: HashSet::new(),
+            lines: Vec::new(),
+            failed_line: None,
+            captured: None,
+            instruction_budget: None,
+            output_budget: None,
+            executed: 0,
+            shown: 0,
+        }
+    }
+
+    // Reads from a different source than stdin, e.g. scripted input
+    pub fn with_input(mut self, input: Box<dyn BufRead>) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    // Sends what `show` prints somewhere other than stdout, e.g. a buffer to inspect
+    pub fn with_output(mut self, output: Box<dyn Write>) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    // Stops scripts that would otherwise run forever, for hosts running untrusted code
+    pub fn with_instruction_budget(mut self, instructions: usize) -> Self {
+        self.instruction_budget = Some(instructions);
+        self
+    }
+
+    // Stops scripts that would flood the output; each shown line counts its bytes
+    pub fn with_output_budget(mut self, bytes: usize) -> Self {
+        self.output_budget = Some(bytes);
+        self
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        let result = match &mut self.output {
+            Some(output) => writeln!(output, "{}", line),
+            None => writeln!(io::stdout().lock(), "{}", line),
+        };
+        result.map_err(|e| format!("Cannot write output: {}", e))
+    }
+
+    // Everything emitted so far, for embedders to collect
+    pub fn events(&self) -> &[Value] {
+        &self.events
+    }
+
+    // Stdin is only locked for the one read, so several runtimes can live side by side
+    fn read_input(&mut self, buf: &mut String) -> io::Result<usize> {
+        match &mut self.input {
+            Some(input) => input.read_line(buf),
+            None => io::stdin().lock().read_line(buf),
         }
     }
 
-    pub fn run_repl(&mut self) -> Result<(), String> {
+    pub fn run_repl(&mut self) -> Result<(), NairError> {
         println!("Vernacular Runtime v0.1.0");
-        println!("'.exit' is quit, '.load' is load, or enter code directly.");
+        println!("'.exit' is quit, '.load' is load, '.dump' shows bytecode, '.vars' lists variables,");
+        println!("'.reset' clears them, '.history' lists past input and '!N' re-runs entry N,");
+        println!("'.time' runs code and reports how long it took, '.doc' describes a Task or Object,");
+        println!("'.step' runs code one instruction at a time,");
+        println!("or enter code directly.");
 
         let mut input = String::new();
         let mut is_continuation = false;
+        let mut in_block = false;  // a line ended with ':'; read until a blank line
 
         loop {
             if is_continuation {
@@ -38,7 +199,7 @@ impl Runtime {
             io::stdout().flush().unwrap();
 
             let mut line = String::new();
-            io::stdin().read_line(&mut line).expect("Failed to read line");
+            self.read_input(&mut line).expect("Failed to read line");
             let line = line.trim_end();
 
             match line {
@@ -49,25 +210,99 @@ impl Runtime {
                 ".load" if !is_continuation => {
                     println!("Enter file path:");
                     let mut file_path = String::new();
-                    io::stdin().read_line(&mut file_path).expect("Failed to read line");
+                    self.read_input(&mut file_path).expect("Failed to read line");
                     let file_path = file_path.trim();
                     
                     self.run_file(file_path)?;
                     input.clear();
                     is_continuation = false;
                 }
+                ".reset" if !is_continuation => {
+                    self.variables.clear();
+                    self.variable_types.clear();
+                    self.fixed.clear();
+                    self.objects.clear();
+                    self.tasks.clear();
+                    self.imported.clear();
+                    println!("All variables cleared.");
+                }
+                ".vars" if !is_continuation => {
+                    let mut names: Vec<&String> = self.variables.keys().collect();
+                    names.sort();
+                    for name in names {
+                        let value = &self.variables[name];
+                        // Prefer the declared type over what the value happens to be
+                        let type_name = self.variable_types.get(name)
+                            .map(String::as_str)
+                            .unwrap_or_else(|| value.type_name());
+                        println!("  {} as {} is {}", name, type_name, value);
+                    }
+                }
+                ".history" if !is_continuation => {
+                    for (i, entry) in self.history.iter().enumerate() {
+                        println!("{:>4}  {}", i + 1, entry.replace('\n', "\n      "));
+                    }
+                }
+                _ if !is_continuation && line.starts_with('!') => {
+                    let entry = line[1..].trim().parse::<usize>().ok()
+                        .and_then(|n| n.checked_sub(1))
+                        .and_then(|i| self.history.get(i))
+                        .cloned();
+                    match entry {
+                        Some(entry) => {
+                            println!("{}", entry);
+                            self.history.push(entry.clone());
+                            self.process_input(&entry)?;
+                        }
+                        None => println!("No history entry {}", &line[1..]),
+                    }
+                }
+                _ if !is_continuation && line.starts_with(".doc") => {
+                    let name = line.trim_start_matches(".doc").trim();
+                    match self.doc(name) {
+                        Ok(Some(doc)) => println!("{}", doc),
+                        Ok(None) => println!("{} has no description", name),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                _ if !is_continuation && line.starts_with(".dump") => {
+                    let code = line.trim_start_matches(".dump").trim();
+                    match self.compile(code) {
+                        Ok(bytecode) => print!("{}", disassemble(&bytecode)),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                _ if !is_continuation && line.starts_with(".step") => {
+                    let code = line.trim_start_matches(".step").trim();
+                    // A mistake is reported and the session carries on
+                    if let Err(e) = self.compile(code).and_then(|bytecode| self.step_through(bytecode)) {
+                        println!("{}", e);
+                    }
+                }
+                _ if !is_continuation && line.split_whitespace().next() == Some(".time") => {
+                    let code = line.trim_start_matches(".time").trim();
+                    if let Err(e) = self.time(code) {
+                        println!("{}", e);
+                    }
+                }
                 _ => {
                     input.push_str(line);
                     input.push('\n');  // Add newline to maintain line structure
                     
-                    if line.trim_end().ends_with('\\') {
+                    if line.trim_end().ends_with(':') {
+                        in_block = true;
+                    }
+
+                    if line.trim_end().ends_with('\\') || (in_block && !line.trim().is_empty()) {
                         is_continuation = true;
                     } else {
                         if !input.trim().is_empty() {
+                            self.history.push(input.trim_end().to_string());
                             self.process_input(&input)?;
                         }
                         input.clear();
                         is_continuation = false;
+                        in_block = false;
                     }
                 }
             }
@@ -76,32 +311,196 @@ impl Runtime {
         Ok(())
     }
 
-    pub fn run_file(&mut self, file_path: &str) -> Result<(), String> {
+    // Runs code for `.time`, showing a bare expression's value alongside how long it took
+    fn time(&mut self, code: &str) -> Result<(), NairError> {
+        let mut bytecode = self.compile(code)?;
+        if matches!(bytecode.last(), Some(OpCode::Pop)) {
+            bytecode.pop();
+        }
+        let start = std::time::Instant::now();
+        let leftover = self.execute_bytecode(bytecode)?;
+        let elapsed = start.elapsed();
+        if let Some(value) = leftover.last() {
+            println!("{}", value);
+        }
+        println!("Took {:.3} ms", elapsed.as_secs_f64() * 1000.0);
+        Ok(())
+    }
+
+    // Shows each instruction as it runs, then the stack and any variables it changed,
+    // waiting for Enter before the next
+    fn step_through(&mut self, bytecode: Vec<OpCode>) -> Result<(), NairError> {
+        let mut state = VmState::new(bytecode);
+        while state.ip < state.code.len() {
+            println!("{:>4}  {:?}", state.ip, state.code[state.ip]);
+            let before = self.variables.clone();
+            let more = self.step(&mut state).map_err(NairError::Runtime)?;
+
+            let stack: Vec<String> = state.stack.iter().map(Value::to_string).collect();
+            println!("      stack: [{}]", stack.join(", "));
+            let mut changed: Vec<(&String, &Value)> = self.variables.iter()
+                .filter(|(name, value)| before.get(*name) != Some(*value))
+                .collect();
+            changed.sort_by_key(|(name, _)| *name);
+            for (name, value) in changed {
+                println!("      {} is {}", name, value);
+            }
+
+            if !more {
+                break;
+            }
+            self.read_input(&mut String::new()).expect("Failed to read line");
+        }
+        Ok(())
+    }
+
+    pub fn run_file(&mut self, file_path: &str) -> Result<(), NairError> {
         match std::fs::read_to_string(file_path) {
             Ok(content) => {
                 println!("Running file: {}", file_path);
-                self.process_input(&content)
+                let path = PathBuf::from(file_path);
+                self.importing.push(path.canonicalize().unwrap_or(path));
+                let result = self.process_input(&content);
+                self.importing.pop();
+                result
             }
-            Err(e) => Err(format!("Error reading file '{}': {}", file_path, e)),
+            Err(e) => Err(NairError::Runtime(format!("Error reading file '{}': {}", file_path, e))),
+        }
+    }
+
+    // Runs a `.nairc` file written by `nair compile`, skipping every stage before the VM
+    pub fn run_compiled_file(&mut self, file_path: &str) -> Result<(), NairError> {
+        let content = std::fs::read_to_string(file_path)
+            .map_err(|e| NairError::Runtime(format!("Error reading file '{}': {}", file_path, e)))?;
+        let program = Program::from_nairc(&content)?;
+        println!("Running file: {}", file_path);
+        self.constants = program.constants;
+        self.lines = program.lines;
+        let path = PathBuf::from(file_path);
+        self.importing.push(path.canonicalize().unwrap_or(path));
+        let result = self.execute_bytecode(program.code).map(|_| ());
+        self.importing.pop();
+        result
+    }
+
+    pub fn compile_program(&mut self, input: &str) -> Result<Program, NairError> {
+        let code = self.compile(input)?;
+        Ok(Program { code, lines: self.lines.clone(), constants: self.constants.clone() })
+    }
+
+    // Like run_file, but stops before running; `using` paths are relative to the file
+    pub fn compile_file(&mut self, file_path: &str) -> Result<Program, NairError> {
+        let content = std::fs::read_to_string(file_path)
+            .map_err(|e| NairError::Runtime(format!("Error reading file '{}': {}", file_path, e)))?;
+        let path = PathBuf::from(file_path);
+        self.importing.push(path.canonicalize().unwrap_or(path));
+        let program = self.compile_program(&content);
+        self.importing.pop();
+        program
+    }
+
+    fn process_input(&mut self, input: &str) -> Result<(), NairError> {
+        let bytecode = self.compile(input)?;
+        self.execute_bytecode(bytecode)?;
+        Ok(())
+    }
+
+    // The inspection modes stop after their own stage and run nothing
+    pub fn print_tokens(&mut self, input: &str) -> Result<(), NairError> {
+        println!("Tokens:");
+        for token in self.lex(input)? {
+            println!("  {}", token);
+        }
+        Ok(())
+    }
+
+    pub fn print_ast(&mut self, input: &str) -> Result<(), NairError> {
+        println!("AST:");
+        for node in self.parse(input)? {
+            println!("  {:?}", node);
         }
+        Ok(())
+    }
+
+    pub fn print_bytecode(&mut self, input: &str) -> Result<(), NairError> {
+        println!("Bytecode:");
+        print!("{}", disassemble(&self.compile(input)?));
+        Ok(())
+    }
+
+    // The program rewritten in canonical layout, for `nair fmt`
+    pub fn format_source(&mut self, input: &str) -> Result<String, NairError> {
+        format_program(&self.parse(input)?).map_err(NairError::Compile)
     }
 
-    fn process_input(&mut self, input: &str) -> Result<(), String> {
+    fn lex(&mut self, input: &str) -> Result<Vec<Token>, NairError> {
         // First, preprocess the input to handle line continuations
-        let processed_input = self.preprocess_input(input)?;
-        
+        let processed_input = self.preprocess_input(input);
         self.tokenizer = Tokenizer::new(&processed_input);
-        let tokens = self.tokenizer.tokenize()?;
-        
-        // Create and run parser
-        let mut parser = Parser::new(tokens.clone());
+        self.tokenizer.tokenize()
+    }
+
+    fn parse(&mut self, input: &str) -> Result<Vec<Node>, NairError> {
+        Ok(self.parse_lines(input)?.into_iter().map(|(_, node)| node).collect())
+    }
+
+    // Each top-level statement along with the line it starts on
+    fn parse_lines(&mut self, input: &str) -> Result<Vec<(usize, Node)>, NairError> {
+        let tokens = self.lex(input)?;
+        let mut parser = Parser::new(tokens);
         let ast = parser.parse()?;
+        Ok(parser.lines.into_iter().zip(ast).collect())
+    }
+
+    // Hoists the Tasks and Objects of every `using "file"` above the rest of the program.
+    // Their lines belong to another file, so they are recorded as unknown
+    fn resolve_imports(&mut self, ast: Vec<(usize, Node)>) -> Result<Vec<(usize, Node)>, NairError> {
+        let mut declarations = Vec::new();
+        let mut program = Vec::new();
+        for (line, node) in ast {
+            match node {
+                Node::UsingFile(path) => declarations.extend(self.import(&path)?.into_iter().map(|node| (0, node))),
+                other => program.push((line, other)),
+            }
+        }
+        declarations.extend(program);
+        Ok(declarations)
+    }
+
+    fn import(&mut self, path: &str) -> Result<Vec<Node>, NairError> {
+        let base = self.importing.last().and_then(|file| file.parent()).map(PathBuf::from).unwrap_or_default();
+        let path = base.join(path);
+        let canonical = path.canonicalize()
+            .map_err(|e| NairError::Runtime(format!("Cannot import '{}': {}", path.display(), e)))?;
+        if self.importing.contains(&canonical) {
+            return Err(NairError::Runtime(format!("Circular import of '{}'", path.display())));
+        }
+        if !self.imported.insert(canonical.clone()) {
+            return Ok(Vec::new());
+        }
+
+        let content = std::fs::read_to_string(&canonical)
+            .map_err(|e| NairError::Runtime(format!("Cannot import '{}': {}", path.display(), e)))?;
+        self.importing.push(canonical);
+        let ast = self.parse_lines(&content).and_then(|ast| self.resolve_imports(ast));
+        self.importing.pop();
+
+        Ok(ast?.into_iter()
+            .map(|(_, node)| node)
+            .filter(|node| matches!(node, Node::TaskDecl { .. } | Node::ObjectDecl { .. }))
+            .collect())
+    }
+
+    // Runs every stage up to code generation without executing anything
+    fn compile(&mut self, input: &str) -> Result<Vec<OpCode>, NairError> {
+        let ast = self.parse_lines(input)?;
+        let ast = self.resolve_imports(ast)?;
         
         // Run type checker with existing variables
         let mut analyzer = Analyzer::new();
         
         // Only copy variables that have explicit types
-        for (name, _value) in &self.variables {
+        for name in self.variables.keys() {
             let var_type = if let Some(declared_type) = self.variable_types.get(name) {
                 match declared_type.as_str() {
                     "Whole" => Type::Whole,
@@ -114,43 +513,36 @@ impl Runtime {
             } else {
                 Type::Any
             };
-            analyzer.variables.insert(name.clone(), var_type);
+            let inferred = !self.variable_types.contains_key(name);
+            let mutable = !self.fixed.contains(name);
+            analyzer.variables.insert(name.clone(), Variable { typ: var_type, inferred, mutable });
         }
         
+        let (lines, ast): (Vec<usize>, Vec<Node>) = ast.into_iter().unzip();
         analyzer.analyze(&ast)?;
+        self.fixed.extend(analyzer.variables.iter()
+            .filter(|(_, variable)| !variable.mutable)
+            .map(|(name, _)| name.clone()));
         
-        // Generate and run bytecode
+        // Generate bytecode, adding to the constants pool of earlier inputs
         let mut generator = BytecodeGenerator::new();
-        let bytecode = generator.generate(ast.clone())?;
-        
-        // Debug output
-        println!("Tokens:");
-        for token in tokens {
-            println!("  {}", token);
-        }
-        
-        println!("\nAST:");
-        for node in &ast {
-            println!("  {:?}", node);
-        }
-        
-        println!("\nBytecode:");
-        for op in &bytecode {
-            println!("  {:?}", op);
-        }
-
-        self.execute_bytecode(bytecode)
+        generator.variable_types = self.variable_types.clone();
+        generator.constants = std::mem::take(&mut self.constants);
+        let bytecode = generator.generate_lines(lines.into_iter().zip(ast).collect());
+        self.constants = generator.constants;
+        self.lines = generator.lines;
+        bytecode
     }
 
-    fn preprocess_input(&self, input: &str) -> Result<String, String> {
+    fn preprocess_input(&self, input: &str) -> String {
         let mut processed = String::new();
         let mut lines = input.lines().peekable();
         
         while let Some(line) = lines.next() {
             let trimmed = line.trim_end();
-            if trimmed.ends_with('\\') {
+            if let Some(continued) = trimmed.strip_suffix('\\') {
                 // Remove the \ and add a space
-                processed.push_str(&trimmed[..trimmed.len()-1]);
+                processed.push_str(continued);
                 processed.push(' ');
             } else {
                 // Add the line as-is
@@ -162,330 +554,1926 @@ impl Runtime {
             }
         }
         
-        Ok(processed)
+        processed
     }
 
-    fn execute_bytecode(&mut self, bytecode: Vec<OpCode>) -> Result<(), String> {
-        let mut stack: Vec<Value> = Vec::new();
-        let mut ip = 0;
+    // Gives back whatever is left on the stack, which should be nothing
+    // Errors in a file name the line of the statement that failed
+    fn execute_bytecode(&mut self, bytecode: Vec<OpCode>) -> Result<Vec<Value>, NairError> {
+        let lines = self.lines.clone();
+        self.failed_line = None;
+        self.run(bytecode, &lines).map_err(|msg| match self.failed_line.take() {
+            Some(line) if !self.importing.is_empty() => NairError::Runtime(format!("{} (line {})", msg, line)),
+            _ => NairError::Runtime(msg),
+        })
+    }
 
-        while ip < bytecode.len() {
-            match &bytecode[ip] {
-                OpCode::StoreVar(name) => {
-                    let value = stack.pop().ok_or("Stack underflow")?;
-                    
-                    if let Some(declared_type) = self.variable_types.get(name) {
-                        // Skip type checking if we're storing null during declaration
-                        if !matches!(value, Value::Null) {
-                            let value_type = match &value {
-                                Value::Number(n) => {
-                                    if n.fract() == 0.0 { "Whole" } else { "Decimal" }
-                                },
-                                Value::String(_) => "Text",
-                                Value::Boolean(_) => "Truth",
-                                Value::Null => "Nothing",
-                                Value::Object(ref class_name) => class_name,
-                            };
-                            
-                            if declared_type != value_type {
-                                return Err(format!("Type mismatch: cannot assign {} to variable of type {}", 
-                                              value_type, declared_type));
-                            }
-                        }
+    // Runs code whose instructions came from the given source lines. The line
+    // of the innermost instruction that fails is kept for the error message
+    fn run(&mut self, bytecode: Vec<OpCode>, lines: &[usize]) -> Result<Vec<Value>, String> {
+        let mut state = VmState::new(bytecode);
+        let bound = self.bindings.len();
+        let mut result = loop {
+            match self.step(&mut state) {
+                Ok(true) => {},
+                Ok(false) => break Ok(()),
+                Err(msg) => {
+                    if self.failed_line.is_none() {
+                        self.failed_line = lines.get(state.ip).copied().filter(|&line| line > 0);
                     }
-                    
-                    self.variables.insert(name.clone(), value);
-                    Ok(())
+                    break Err(msg);
                 },
-                OpCode::LoadVar(name) => {
-                    // Only try to load if the variable exists
-                    if let Some(value) = self.variables.get(name) {
-                        stack.push(value.clone());
-                        Ok(())
-                    } else {
-                        Err(format!("Undefined variable: {}", name))
+            }
+        };
+        // Blocks left early, by an error or an `output`, still give their names back
+        // and release what they borrowed. The first error is the one reported
+        while self.bindings.len() > bound {
+            if let Some(binding) = self.bindings.pop() {
+                let ended = self.end_binding(binding);
+                result = result.and(ended);
+            }
+        }
+        result?;
+        Ok(state.stack)
+    }
+
+    // Binds a name in the current Task, or globally outside one, setting aside
+    // whatever it held and its declared type until the block ends
+    fn bind(&mut self, name: &str, value: Value, releases: bool) {
+        let covered_type = self.variable_types.remove(name);
+        let covered = match self.frames.last_mut() {
+            Some(frame) => frame.insert(name.to_string(), value),
+            None => self.variables.insert(name.to_string(), value),
+        };
+        self.bindings.push(Binding { name: name.to_string(), covered, covered_type, releases });
+    }
+
+    // Unbinds, then calls the `release` method of a borrowed object if it has one
+    fn end_binding(&mut self, binding: Binding) -> Result<(), String> {
+        let releases = binding.releases;
+        let value = self.unbind(binding);
+        if let (true, Some(receiver @ Value::Instance(instance))) = (releases, &value) {
+            let release = self.find_in_chain(&instance.class_name, |object| {
+                object.methods.iter().find(|method| method.name == "release")
+            })?;
+            if let Some(release) = release {
+                self.call_task(&release, Some(receiver.clone()), Vec::new())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Brings back what a binding covered, and gives back the value it held
+    fn unbind(&mut self, binding: Binding) -> Option<Value> {
+        let Binding { name, covered, covered_type, .. } = binding;
+        if let Some(type_name) = covered_type {
+            self.variable_types.insert(name.clone(), type_name);
+        }
+        let scope = match self.frames.last_mut() {
+            Some(frame) => frame,
+            None => &mut self.variables,
+        };
+        match covered {
+            Some(value) => scope.insert(name, value),
+            None => scope.remove(&name),
+        }
+    }
+
+    // Runs the instruction at `ip` and says whether there is more to run
+    fn step(&mut self, state: &mut VmState) -> Result<bool, String> {
+        let VmState { code: bytecode, stack, ip } = state;
+        if *ip >= bytecode.len() {
+            return Ok(false);
+        }
+        self.executed += 1;
+        if self.instruction_budget.is_some_and(|budget| self.executed > budget) {
+            return Err("Instruction budget exceeded".to_string());
+        }
+        match &bytecode[*ip] {
+            OpCode::StoreVar { name, declared_type } => {
+                let value = stack.pop().ok_or("Stack underflow")?;
+
+                // A declaration records the type so later stores, even from
+                // later REPL lines, are checked against it
+                if let Some(type_name) = declared_type {
+                    self.variable_types.insert(name.clone(), type_name.clone());
+                }
+                
+                let mut value = value;
+                if let Some(declared_type) = self.variable_types.get(name) {
+                    // Skip type checking if we're storing null during declaration
+                    if !matches!(value, Value::Null) && declared_type != "Any" {
+                        let value_type = value.type_name();
+                        let widens = declared_type == "Decimal" && value_type == "Whole";
+                        if declared_type != value_type && !widens {
+                            return Err(format!("Type mismatch: cannot assign {} to variable of type {}", 
+                                          value_type, declared_type));
+                        }
+                        // A Whole stored as a Decimal becomes one
+                        if let (true, Value::Number(n)) = (widens, &value) {
+                            value = Value::Decimal(*n);
+                        }
                     }
-                },
-                OpCode::Push(value) => {
+                }
+                
+                // Inside a Task, new names and its own parameters are local to it
+                match self.frames.last_mut() {
+                    Some(frame) if frame.contains_key(name) || !self.variables.contains_key(name) => {
+                        frame.insert(name.clone(), value)
+                    },
+                    _ => self.variables.insert(name.clone(), value),
+                };
+                Ok(())
+            },
+            OpCode::Bind(name) | OpCode::Borrow(name) => {
+                let value = stack.pop().ok_or("Stack underflow")?;
+                let releases = matches!(bytecode[*ip], OpCode::Borrow(_));
+                self.bind(name, value, releases);
+                Ok(())
+            },
+            OpCode::Unbind(name) => {
+                let position = self.bindings.iter().rposition(|binding| &binding.name == name)
+                    .ok_or_else(|| format!("{} is not bound", name))?;
+                let binding = self.bindings.remove(position);
+                self.end_binding(binding)
+            },
+            OpCode::LoadVar(name) => {
+                // Only try to load if the variable exists
+                let local = self.frames.last().and_then(|frame| frame.get(name));
+                if let Some(value) = local.or_else(|| self.variables.get(name)) {
                     stack.push(value.clone());
                     Ok(())
-                },
-                OpCode::Pop => {
-                    stack.pop();
-                    Ok(())
-                },
-                OpCode::Duplicate => {
-                    if let Some(value) = stack.last() {
-                        stack.push(value.clone());
-                    }
-                    Ok(())
-                },
-                OpCode::Add => {
-                    let b = stack.pop().ok_or("Stack underflow")?;
-                    let a = stack.pop().ok_or("Stack underflow")?;
-                    stack.push(self.binary_op(a, b, |x, y| x + y)?);
-                    Ok(())
-                },
-                OpCode::Subtract => {
-                    let b = stack.pop().ok_or("Stack underflow")?;
-                    let a = stack.pop().ok_or("Stack underflow")?;
-                    stack.push(self.binary_op(a, b, |x, y| x - y)?);
-                    Ok(())
-                },
-                OpCode::Multiply => {
-                    let b = stack.pop().ok_or("Stack underflow")?;
-                    let a = stack.pop().ok_or("Stack underflow")?;
-                    stack.push(self.binary_op(a, b, |x, y| x * y)?);
-                    Ok(())
-                },
-                OpCode::Divide => {
-                    let b = stack.pop().ok_or("Stack underflow")?;
-                    let a = stack.pop().ok_or("Stack underflow")?;
-                    stack.push(self.binary_op(a, b, |x, y| x / y)?);
-                    Ok(())
-                },
-                OpCode::Jump(target) => {
-                    ip = *target;
-                    Ok(())
-                },
-                OpCode::JumpIfFalse(target) => {
-                    if let Some(Value::Boolean(false)) = stack.last() {
-                        ip = *target;
+                } else {
+                    Err(format!("Undefined variable: {}", name))
+                }
+            },
+            OpCode::LoadConst(index) => {
+                let value = self.constants.get(*index).ok_or("Constant index out of range")?;
+                stack.push(value.clone());
+                Ok(())
+            },
+            OpCode::Push(value) => {
+                stack.push(value.clone());
+                Ok(())
+            },
+            OpCode::Pop => {
+                stack.pop().ok_or("Stack underflow")?;
+                Ok(())
+            },
+            OpCode::BuildList(count) => {
+                if stack.len() < *count {
+                    return Err("Stack underflow".to_string());
+                }
+                let items = stack.split_off(stack.len() - count);
+                stack.push(Value::List(items));
+                Ok(())
+            },
+            OpCode::BuildRecord(names) => {
+                if stack.len() < names.len() {
+                    return Err("Stack underflow".to_string());
+                }
+                let values = stack.split_off(stack.len() - names.len());
+                stack.push(Value::Mapping(names.iter().cloned().zip(values).collect()));
+                Ok(())
+            },
+            OpCode::NextItem(target) => {
+                let [.., list, _, counter] = &mut stack[..] else {
+                    return Err("Stack underflow".to_string());
+                };
+                let Value::List(items) = list else {
+                    return Err(format!("Only a List can be mapped over, got {}", list.type_name()));
+                };
+                let Value::Number(i) = counter else {
+                    return Err("Map counter is not a number".to_string());
+                };
+                match items.get(*i as usize).cloned() {
+                    Some(item) => {
+                        *i += 1.0;
+                        stack.push(item);
+                    },
+                    None => {
+                        *ip = *target;
+                        return Ok(*ip < bytecode.len());
+                    },
+                }
+                Ok(())
+            },
+            OpCode::ItemIndex => {
+                match stack.last() {
+                    Some(Value::Number(i)) => {
+                        let index = Value::Number(i - 1.0);
+                        stack.push(index);
                         Ok(())
-                    } else {
+                    },
+                    _ => Err("Map counter is not a number".to_string()),
+                }
+            },
+            OpCode::AppendItem => {
+                let value = stack.pop().ok_or("Stack underflow")?;
+                let [.., Value::List(result), _] = &mut stack[..] else {
+                    return Err("Stack underflow".to_string());
+                };
+                result.push(value);
+                Ok(())
+            },
+            OpCode::FinishMap => {
+                stack.pop();
+                let result = stack.pop().ok_or("Stack underflow")?;
+                stack.pop().ok_or("Stack underflow")?;
+                stack.push(result);
+                Ok(())
+            },
+            OpCode::Duplicate => {
+                if let Some(value) = stack.last() {
+                    stack.push(value.clone());
+                }
+                Ok(())
+            },
+            OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide
+            | OpCode::Modulo | OpCode::Power | OpCode::FloorDivide => {
+                let b = stack.pop().ok_or("Stack underflow")?;
+                let a = stack.pop().ok_or("Stack underflow")?;
+                let op = &bytecode[*ip];
+                // Text only adds to text; "a" + 1 is an error rather than "a1"
+                let result = match (op, &a, &b) {
+                    (OpCode::Add, Value::String(_), Value::String(_)) => self.concat_values(a, b)?,
+                    (OpCode::Add, Value::String(_), _) | (OpCode::Add, _, Value::String(_)) => {
+                        return Err(format!("Cannot add {} and {}", a.type_name(), b.type_name()));
+                    },
+                    // Rather than a silent inf or NaN
+                    (OpCode::Divide | OpCode::Modulo | OpCode::FloorDivide, _, Value::Number(y) | Value::Decimal(y))
+                        if *y == 0.0 && a.as_number().is_some() => return Err("Division by zero".to_string()),
+                    _ => self.binary_op(a, b, Self::arithmetic(op))?,
+                };
+                stack.push(result);
+                Ok(())
+            },
+            OpCode::Negate => {
+                match stack.pop().ok_or("Stack underflow")? {
+                    Value::Number(n) => stack.push(Value::Number(-n)),
+                    Value::Decimal(n) => stack.push(Value::Decimal(-n)),
+                    other => return Err(format!("Cannot negate {}", other)),
+                }
+                Ok(())
+            },
+            OpCode::Not => {
+                match stack.pop().ok_or("Stack underflow")? {
+                    Value::Boolean(b) => stack.push(Value::Boolean(!b)),
+                    other => return Err(format!("'not' needs a Truth, got {}", other.type_name())),
+                }
+                Ok(())
+            },
+            OpCode::Equal | OpCode::NotEqual => {
+                let b = stack.pop().ok_or("Stack underflow")?;
+                let a = stack.pop().ok_or("Stack underflow")?;
+                let equal = a == b;
+                stack.push(Value::Boolean(equal == matches!(bytecode[*ip], OpCode::Equal)));
+                Ok(())
+            },
+            OpCode::Greater | OpCode::GreaterEqual | OpCode::Less | OpCode::LessEqual => {
+                let b = stack.pop().ok_or("Stack underflow")?;
+                let a = stack.pop().ok_or("Stack underflow")?;
+                let ordering = a.partial_cmp(&b).ok_or_else(|| format!("Cannot compare {} and {}", a, b))?;
+                let result = match bytecode[*ip] {
+                    OpCode::Greater => ordering.is_gt(),
+                    OpCode::GreaterEqual => ordering.is_ge(),
+                    OpCode::Less => ordering.is_lt(),
+                    _ => ordering.is_le(),
+                };
+                stack.push(Value::Boolean(result));
+                Ok(())
+            },
+            OpCode::Contains => {
+                let item = stack.pop().ok_or("Stack underflow")?;
+                let container = stack.pop().ok_or("Stack underflow")?;
+                let found = match (&container, &item) {
+                    (Value::List(items), _) => items.contains(&item),
+                    (Value::Mapping(entries), Value::String(key)) => entries.iter().any(|(k, _)| k == key),
+                    (Value::Mapping(_), _) => false,
+                    (Value::String(text), Value::String(part)) => text.contains(part.as_str()),
+                    _ => return Err(format!("{} cannot contain {}", container.type_name(), item.type_name())),
+                };
+                stack.push(Value::Boolean(found));
+                Ok(())
+            },
+            OpCode::Jump(target) => {
+                // Land exactly on the target rather than the instruction after it
+                *ip = *target;
+                return Ok(*ip < bytecode.len());
+            },
+            OpCode::JumpIfFalse(target) => {
+                // The condition is consumed either way so loops don't pile them up
+                match stack.pop().ok_or("Stack underflow")? {
+                    Value::Boolean(false) => {
+                        *ip = *target;
+                        return Ok(*ip < bytecode.len());
+                    },
+                    Value::Boolean(true) => Ok(()),
+                    other => Err(format!("Condition must be Truth, got {}", other.type_name())),
+                }
+            },
+            OpCode::JumpIfNotNull(target) => {
+                match stack.last().ok_or("Stack underflow")? {
+                    Value::Null | Value::Nothing => {
+                        stack.pop();
                         Ok(())
-                    }
-                },
-                OpCode::ConvertToString => {
-                    let value = stack.pop().ok_or("Stack underflow")?;
-                    stack.push(Value::String(value.to_string()));
-                    Ok(())
-                },
-                OpCode::Call(name, arg_count) => {
-                    let mut args = Vec::new();
-                    // Pop arguments in reverse order
-                    for _ in 0..*arg_count {
-                        if let Some(arg) = stack.pop() {
-                            args.insert(0, arg);
-                        }
-                    }
+                    },
+                    _ => {
+                        *ip = *target;
+                        return Ok(*ip < bytecode.len());
+                    },
+                }
+            },
+            OpCode::ConvertToString => {
+                let value = stack.pop().ok_or("Stack underflow")?;
+                stack.push(Value::String(value.to_string()));
+                Ok(())
+            },
+            OpCode::Call(name, arg_count) => {
+                if stack.len() < *arg_count {
+                    return Err("Stack underflow".to_string());
+                }
+                let args = stack.split_off(stack.len() - arg_count);
 
-                    match name.as_str() {
-                        "show" => {
-                            // Built-in show function
-                            if let Some(value) = args.get(0) {
-                                println!("{}", value);
-                            }
-                            stack.push(Value::Null); // show returns null
-                        },
-                        _ => {
-                            return Err(format!("Unknown function: {}", name));
-                        }
-                    }
-                    Ok(())
-                },
-                OpCode::Return => {
-                    // TODO: Implement return
-                    break;
-                },
-                OpCode::NewObject(_class_name) => {
-                    // TODO: Implement object creation
-                    return Err("Object creation not implemented yet".to_string());
-                },
-                OpCode::GetProperty(_name) => {
-                    // TODO: Implement property access
-                    return Err("Property access not implemented yet".to_string());
-                },
-                OpCode::SetProperty(_name) => {
-                    // TODO: Implement property setting
-                    return Err("Property setting not implemented yet".to_string());
-                },
-                OpCode::CheckType(type_name) => {
-                    if let Some(var_name) = self.get_next_var_name(&bytecode[ip+1..]) {
-                        self.variable_types.insert(var_name.clone(), type_name.clone());
-                    }
-                    Ok(())
-                },
-                OpCode::Cast(type_name) => {
-                    if let Some(value) = stack.pop() {
-                        let new_value = match (value.clone(), type_name.as_str()) {
-                            (Value::Number(n), "Whole") => {
-                                Value::Number(n.floor())
-                            },
-                            (Value::Number(n), "Decimal") => {
-                                Value::Number(n)
-                            },
-                            (Value::String(s), "Text") => {
-                                Value::String(s)
-                            },
-                            (Value::Boolean(b), "Truth") => {
-                                Value::Boolean(b)
-                            },
-                            _ => return Err(format!("Cannot cast {:?} to {}", value, type_name)),
-                        };
-                        stack.push(new_value);
-                    }
-                    Ok(())
-                },
-                OpCode::Concat => {
-                    let b = stack.pop().ok_or("Stack underflow")?;
-                    let a = stack.pop().ok_or("Stack underflow")?;
-                    stack.push(self.concat_values(a, b)?);
-                    Ok(())
-                },
-                OpCode::Interpolate(part_count) => {
-                    let mut result = String::new();
-                    for _ in 0..*part_count {
-                        if let Some(value) = stack.pop() {
-                            result = value.to_string() + &result;
-                        }
-                    }
-                    stack.push(Value::String(result));
-                    Ok(())
-                },
-                OpCode::CheckAssignmentType => {
-                    let _var_value = stack.pop().ok_or("Stack underflow")?;
-                    let new_value = stack.last().ok_or("Stack underflow")?;
-                    
-                    if let Some(var_name) = self.get_next_var_name(&bytecode[ip+1..]) {
-                        // Only check type if the variable has an explicit type declaration
-                        if let Some(declared_type) = self.variable_types.get(&var_name) {
-                            let new_type = match new_value {
-                                Value::Number(n) => {
-                                    if n.fract() == 0.0 { Type::Whole } else { Type::Decimal }
-                                },
-                                Value::String(_) => Type::Text,
-                                Value::Boolean(_) => Type::Truth,
-                                Value::Null => Type::Nothing,
-                                Value::Object(ref class_name) => Type::Object,
-                                Value::Promise(ref class_name) => Type::Promise,
-                                Value::List(ref class_name) => Type::List,
-                                Value::Mapping(ref class_name) => Type::Mapping,
-                            };
-
-                            if declared_type != new_type {
-                                return Err(format!("Type mismatch: cannot assign {} to variable of type {}", 
-                                              new_type, declared_type));
-                            }
-                        }
-                        // If variable doesn't have a declared type, allow any assignment
+                let result = match self.tasks.get(name).cloned() {
+                    Some(task) => self.call_task(&task, None, args)?,
+                    None => self.call_builtin(name, args)?,
+                };
+                stack.push(result);
+                Ok(())
+            },
+            OpCode::CallMethod(name, arg_count) => {
+                if stack.len() < arg_count + 1 {
+                    return Err("Stack underflow".to_string());
+                }
+                let args = stack.split_off(stack.len() - arg_count);
+                let receiver = stack.pop().ok_or("Stack underflow")?;
+
+                let result = match &receiver {
+                    Value::Instance(instance) => {
+                        let class_name = &instance.class_name;
+                        let method = self.find_in_chain(class_name, |object| {
+                            object.methods.iter().find(|method| &method.name == name)
+                        })?.ok_or_else(|| format!("{} has no method {}", class_name, name))?;
+                        self.call_task(&method, Some(receiver.clone()), args)?
+                    },
+                    // Anything else borrows a built-in, so `xs.length()` is `length(xs)`
+                    _ => self.call_builtin(name, std::iter::once(receiver).chain(args).collect())?,
+                };
+                stack.push(result);
+                Ok(())
+            },
+            OpCode::Return => {
+                // The returned value is left on top of the stack for the caller
+                return Ok(false);
+            },
+            OpCode::DefineTask(task) => {
+                self.tasks.insert(task.name.clone(), task.clone());
+                Ok(())
+            },
+            OpCode::DefineObject(object) => {
+                if let Some(base) = &object.base {
+                    if !self.objects.contains_key(base) {
+                        return Err(format!("{} inherits from unknown object {}", object.name, base));
                     }
-                    Ok(())
-                },
-                OpCode::Show => {
+                }
+                self.objects.insert(object.name.clone(), object.clone());
+                Ok(())
+            },
+            OpCode::NewObject(class_name, arg_count) => {
+                if stack.len() < *arg_count {
+                    return Err("Stack underflow".to_string());
+                }
+                let args = stack.split_off(stack.len() - arg_count);
+
+                if !self.objects.contains_key(class_name) {
+                    return Err(format!("Unknown object: {}", class_name));
+                }
+                let instance = Value::Instance(Rc::new(Instance {
+                    class_name: class_name.clone(),
+                    fields: RefCell::new(Vec::new()),
+                }));
+                self.construct(class_name, instance.clone(), args)?;
+
+                stack.push(instance);
+                Ok(())
+            },
+            OpCode::BuildBase(base, arg_count) => {
+                if stack.len() <= *arg_count {
+                    return Err("Stack underflow".to_string());
+                }
+                let args = stack.split_off(stack.len() - arg_count);
+                let me = stack.pop().ok_or("Stack underflow")?;
+                self.construct(base, me, args)
+            },
+            OpCode::GetProperty(name) => {
+                let object = stack.pop().ok_or("Stack underflow")?;
+                let value = match &object {
+                    Value::Instance(instance) => instance.fields.borrow().iter()
+                        .find(|(field, _)| field == name)
+                        .map(|(_, value)| value.clone())
+                        .ok_or_else(|| format!("{} has no field {}", instance.class_name, name))?,
+                    // a record's fields are its keys
+                    Value::Mapping(entries) => entries.iter()
+                        .find(|(key, _)| key == name)
+                        .map(|(_, value)| value.clone())
+                        .ok_or_else(|| format!("{} has no field {}", object, name))?,
+                    _ => return Err(format!("Cannot read field {} of {}", name, object)),
+                };
+                stack.push(value);
+                Ok(())
+            },
+            OpCode::SetProperty(name) => {
+                let value = stack.pop().ok_or("Stack underflow")?;
+                let object = stack.pop().ok_or("Stack underflow")?;
+                let Value::Instance(instance) = &object else {
+                    return Err(format!("Cannot set field {} of {}", name, object));
+                };
+                let mut fields = instance.fields.borrow_mut();
+                match fields.iter_mut().find(|(field, _)| field == name) {
+                    Some((_, slot)) => *slot = value,
+                    None => fields.push((name.clone(), value)),
+                }
+                Ok(())
+            },
+            OpCode::Cast(type_name) => {
+                if let Some(value) = stack.pop() {
+                    stack.push(Self::cast(value, type_name)?);
+                }
+                Ok(())
+            },
+            // Positions count characters from 0. Ranges leave out their end, and
+            // anything past the end of the text is an error rather than clamped
+            OpCode::Index => {
+                let index = stack.pop().ok_or("Stack underflow")?;
+                let text = stack.pop().ok_or("Stack underflow")?;
+                if let Value::List(items) = &text {
+                    let item = match index {
+                        Value::Number(n) if n >= 0.0 && n.fract() == 0.0 => items.get(n as usize),
+                        _ => None,
+                    }.ok_or_else(|| format!("Index {} is out of range for a list of length {}", index, items.len()))?;
+                    stack.push(item.clone());
+                } else {
+                    let chars = Self::text_chars(&text)?;
+                    let i = Self::text_position(&index, chars.len())?;
+                    let c = chars.get(i)
+                        .ok_or_else(|| format!("Index {} is out of range for text of length {}", i, chars.len()))?;
+                    stack.push(Value::String(c.to_string()));
+                }
+                Ok(())
+            },
+            OpCode::Slice => {
+                let end = stack.pop().ok_or("Stack underflow")?;
+                let start = stack.pop().ok_or("Stack underflow")?;
+                let text = stack.pop().ok_or("Stack underflow")?;
+                let chars = Self::text_chars(&text)?;
+                let (start, end) = (Self::text_position(&start, chars.len())?, Self::text_position(&end, chars.len())?);
+                if start > end || end > chars.len() {
+                    return Err(format!("Range {}..{} is out of range for text of length {}", start, end, chars.len()));
+                }
+                stack.push(Value::String(chars[start..end].iter().collect()));
+                Ok(())
+            },
+            OpCode::Concat => {
+                let b = stack.pop().ok_or("Stack underflow")?;
+                let a = stack.pop().ok_or("Stack underflow")?;
+                stack.push(self.concat_values(a, b)?);
+                Ok(())
+            },
+            OpCode::Interpolate(part_count) => {
+                let mut result = String::new();
+                for _ in 0..*part_count {
                     if let Some(value) = stack.pop() {
-                        println!("{}", value);
-                    } else {
-                        return Err("Stack underflow".to_string());
+                        result = value.to_string() + &result;
                     }
-                    Ok(())
-                },
-            }?;
-            ip += 1;
-        }
-        Ok(())
+                }
+                stack.push(Value::String(result));
+                Ok(())
+            },
+            OpCode::Show(count) => {
+                if stack.len() < *count {
+                    return Err("Stack underflow".to_string());
+                }
+                let values = stack.split_off(stack.len() - count);
+                let line = values.iter().map(Value::to_string).collect::<Vec<_>>().join(" ");
+                self.shown += line.len() + 1;
+                if self.output_budget.is_some_and(|budget| self.shown > budget) {
+                    return Err("Output budget exceeded".to_string());
+                }
+                match &mut self.captured {
+                    Some(captured) => captured.extend(values),
+                    None => self.write_line(&line)?,
+                }
+                Ok(())
+            },
+            OpCode::Raise => {
+                let message = stack.pop().ok_or("Stack underflow")?;
+                Err(message.to_string())
+            },
+            OpCode::Emit => {
+                let value = stack.pop().ok_or("Stack underflow")?;
+                if let Some(captured) = &mut self.captured {
+                    captured.push(value.clone());
+                }
+                self.events.push(value);
+                Ok(())
+            },
+        }?;
+        *ip += 1;
+        Ok(*ip < bytecode.len())
     }
 
-    fn get_next_var_name(&self, upcoming_ops: &[OpCode]) -> Option<String> {
-        for op in upcoming_ops {
-            if let OpCode::StoreVar(name) = op {
-                return Some(name.clone());
-            }
+    // Helper methods for the Runtime impl
+
+    // `List[T]` and `Mapping[T]` cast every element to T
+    fn cast(value: Value, type_name: &str) -> Result<Value, String> {
+        let element_type = |prefix: &str| type_name.strip_prefix(prefix).and_then(|rest| rest.strip_suffix(']'));
+        Ok(match (value, type_name) {
+            (Value::List(items), _) if element_type("List[").is_some() => {
+                let element = element_type("List[").unwrap_or_default();
+                Value::List(items.into_iter().map(|item| Self::cast(item, element)).collect::<Result<_, _>>()?)
+            },
+            (Value::Mapping(entries), _) if element_type("Mapping[").is_some() => {
+                let element = element_type("Mapping[").unwrap_or_default();
+                Value::Mapping(entries.into_iter()
+                    .map(|(key, item)| Ok((key, Self::cast(item, element)?)))
+                    .collect::<Result<_, String>>()?)
+            },
+            (value, "Any") | (value @ Value::List(_), "List") | (value @ Value::Mapping(_), "Mapping") => value,
+            (Value::Number(n) | Value::Decimal(n), "Whole") => {
+                Value::Number(n.floor())
+            },
+            (Value::Number(n) | Value::Decimal(n), "Decimal") => {
+                Value::Decimal(n)
+            },
+            (value @ (Value::Number(_) | Value::Decimal(_)), "Number") => value,
+            (Value::Number(n) | Value::Decimal(n), "Text") => {
+                Value::String(n.to_string())
+            },
+            (Value::String(s), "Text") => {
+                Value::String(s)
+            },
+            (Value::String(s), "Whole" | "Decimal" | "Number") => {
+                let n = Value::parse_number(s.trim())
+                    .ok_or_else(|| format!("Cannot cast \"{}\" to {}", s, type_name))?;
+                if type_name == "Number" { n } else { Self::cast(n, type_name)? }
+            },
+            (Value::Boolean(b), "Truth" | "Logic") => {
+                Value::Boolean(b)
+            },
+            (value, _) => return Err(format!("Cannot cast {:?} to {}", value, type_name)),
+        })
+    }
+
+    fn text_chars(value: &Value) -> Result<Vec<char>, String> {
+        match value {
+            Value::String(s) => Ok(s.chars().collect()),
+            other => Err(format!("Only Text can be indexed, got {}", other)),
         }
-        None
     }
 
-    // Helper methods for the Runtime impl
-    fn binary_op<F>(&self, a: Value, b: Value, op: F) -> Result<Value, String>
-    where
-        F: Fn(f64, f64) -> f64,
-    {
-        match (a, b) {
-            (Value::Number(x), Value::Number(y)) => Ok(Value::Number(op(x, y))),
-            _ => Err("Invalid operands for arithmetic operation".to_string()),
+    fn text_position(value: &Value, length: usize) -> Result<usize, String> {
+        match value {
+            Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Ok(*n as usize),
+            other => Err(format!("Invalid position {} for text of length {}", other, length)),
         }
     }
 
-    fn concat_values(&self, a: Value, b: Value) -> Result<Value, String> {
-        match (a, b) {
-            (Value::String(s1), Value::String(s2)) => Ok(Value::String(s1 + &s2)),
-            _ => Err("Can only concatenate strings".to_string()),
+    // Looks for something on an object, then on each object it inherits from
+    fn find_in_chain<'a>(&'a self, class_name: &str, find: impl Fn(&'a ObjectDef) -> Option<&'a TaskDef>)
+        -> Result<Option<TaskDef>, String> {
+        let mut current = Some(class_name);
+        // Redefining an object can close a loop in the chain, so stop after visiting each once
+        for _ in 0..=self.objects.len() {
+            let Some(name) = current else {
+                return Ok(None);
+            };
+            let object = self.objects.get(name).ok_or_else(|| format!("Unknown object: {}", name))?;
+            if let Some(task) = find(object) {
+                return Ok(Some(task.clone()));
+            }
+            current = object.base.as_deref();
         }
+        Err(format!("{} inherits from itself", class_name))
     }
 
-    fn execute(&mut self, instructions: &[OpCode]) -> Result<(), String> {
-        for instruction in instructions {
-            match instruction {
-                OpCode::Show => {
-                    if let Some(value) = self.stack.pop() {
-                        println!("{}", value);
-                    }
-                },
-                OpCode::Push(value) => {
-                    self.stack.push(value.clone());
-                },
-                OpCode::LoadVar(name) => {
-                    if let Some(value) = self.variables.get(name) {
-                        self.stack.push(value.clone());
-                    } else {
-                        return Err(format!("Undefined variable: {}", name));
-                    }
-                },
-                OpCode::StoreVar(name) => {
-                    let value = self.stack.pop().ok_or("Stack underflow")?;
-                    
-                    // Check type if variable has a declared type
-                    if let Some(declared_type) = self.variable_types.get(name) {
-                        let value_type = match &value {
-                            Value::Number(n) => {
-                                if n.fract() == 0.0 { Type::Whole } else { Type::Decimal }
-                            },
-                            Value::String(_) => Type::Text,
-                            Value::Boolean(_) => Type::Truth,
-                            Value::Null => Type::Nothing,
-                            Value::Object(_) => Type::Object,
-                            Value::Promise(_) => Type::Promise(Box::new(Type::Any)),
-                            Value::List(_) => Type::List(Box::new(Type::Any)),
-                            Value::Mapping(_) => Type::Map { key: Box::new(Type::Text), value: Box::new(Type::Any) },
-                        };
-                        
-                        if declared_type != &value_type {
-                            return Err(format!("Type mismatch: cannot assign {:?} to variable of type {:?}", 
-                                value_type, declared_type));
-                        }
-                    }
-                    
-                    self.variables.insert(name.clone(), value);
-                },
-                OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide => {
-                    let b = self.stack.pop().ok_or("Stack underflow")?;
-                    let a = self.stack.pop().ok_or("Stack underflow")?;
-                    let result = match instruction {
-                        OpCode::Add => self.binary_op(a, b, |x, y| x + y)?,
-                        OpCode::Subtract => self.binary_op(a, b, |x, y| x - y)?,
-                        OpCode::Multiply => self.binary_op(a, b, |x, y| x * y)?,
-                        OpCode::Divide => self.binary_op(a, b, |x, y| x / y)?,
-                        _ => unreachable!(),
-                    };
-                    self.stack.push(result);
-                },
-                OpCode::Pop => {
-                    self.stack.pop();
-                },
-                OpCode::Duplicate => {
-                    if let Some(value) = self.stack.last() {
-                        self.stack.push(value.clone());
-                    }
-                },
-                _ => return Err(format!("Unhandled opcode: {:?}", instruction)),
-            }
+    // Runs the `build` that makes a `class_name` on an instance. An object without
+    // its own `build` uses its nearest ancestor's
+    fn construct(&mut self, class_name: &str, instance: Value, args: Vec<Value>) -> Result<(), String> {
+        let constructor = self.find_in_chain(class_name, |object| object.constructor.as_ref())?;
+        if !constructor.as_ref().map_or(args.is_empty(), |constructor| constructor.accepts(args.len())) {
+            let expected = constructor.as_ref().map_or("0".to_string(), TaskDef::arity);
+            return Err(format!("Constructor for {} expects {} arguments, got {}",
+                               class_name, expected, args.len()));
+        }
+        if let Some(constructor) = constructor {
+            self.call_task(&constructor, Some(instance), args)?;
         }
         Ok(())
     }
+
+    // Runs a Task body with its parameters bound as locals, along with `me` for
+    // methods and constructors, and gives back its result
+    fn call_task(&mut self, task: &TaskDef, me: Option<Value>, mut args: Vec<Value>) -> Result<Value, String> {
+        if !task.accepts(args.len()) {
+            return Err(format!("{} expects {} argument(s), got {}", task.name, task.arity(), args.len()));
+        }
+
+        if task.variadic {
+            let rest = args.split_off(task.required_args());
+            args.push(Value::List(rest));
+        }
+        let mut frame: HashMap<String, Value> = task.params.iter().cloned().zip(args).collect();
+        if let Some(me) = me {
+            frame.insert("me".to_string(), me);
+        }
+        self.frames.push(frame);
+        let result = self.run(task.code.clone(), &task.lines);
+        self.frames.pop();
+
+        Ok(result?.pop().unwrap_or(Value::Nothing))
+    }
+
+    // The `about` text of a Task or Object, if it was given one
+    fn doc(&self, name: &str) -> Result<Option<&str>, String> {
+        if let Some(task) = self.tasks.get(name) {
+            Ok(task.doc.as_deref())
+        } else if let Some(object) = self.objects.get(name) {
+            Ok(object.doc.as_deref())
+        } else {
+            Err(format!("doc: no Task or Object named {}", name))
+        }
+    }
+
+    // `show` is a statement compiled to OpCode::Show, not a callable task
+    fn call_builtin(&mut self, name: &str, args: Vec<Value>) -> Result<Value, String> {
+        let expect_args = |count: usize| {
+            if args.len() == count {
+                Ok(())
+            } else {
+                Err(format!("{} expects {} argument(s), got {}", name, count, args.len()))
+            }
+        };
+        let number = |value: &Value| value.as_number()
+            .ok_or_else(|| format!("{} expects a number, got {}", name, value));
+
+        match name {
+            "length" => {
+                expect_args(1)?;
+                match &args[0] {
+                    Value::String(s) => Ok(Value::Number(s.chars().count() as f64)),
+                    Value::List(items) => Ok(Value::Number(items.len() as f64)),
+                    Value::Mapping(entries) => Ok(Value::Number(entries.len() as f64)),
+                    other => Err(format!("length: cannot take the length of {}", other)),
+                }
+            },
+            "sqrt" | "abs" | "floor" | "ceil" | "round" => {
+                expect_args(1)?;
+                let n = number(&args[0])?;
+                // Rounding gives a Whole, abs keeps the type it was given and sqrt is a Decimal
+                match name {
+                    "sqrt" if n < 0.0 => Err(format!("sqrt of a negative number: {}", n)),
+                    "sqrt" => Ok(Value::Decimal(n.sqrt())),
+                    "abs" if matches!(args[0], Value::Decimal(_)) => Ok(Value::Decimal(n.abs())),
+                    "abs" => Ok(Value::Number(n.abs())),
+                    "floor" => Ok(Value::Number(n.floor())),
+                    "ceil" => Ok(Value::Number(n.ceil())),
+                    _ => Ok(Value::Number(n.round())),
+                }
+            },
+            "min" | "max" => {
+                if args.is_empty() {
+                    return Err(format!("{} expects at least 1 argument", name));
+                }
+                // The value picked keeps its own type
+                let mut picked = &args[0];
+                for arg in &args {
+                    let (n, best) = (number(arg)?, number(picked)?);
+                    if (name == "min" && n < best) || (name == "max" && n > best) || best.is_nan() {
+                        picked = arg;
+                    }
+                }
+                Ok(picked.clone())
+            },
+            "read_line" | "read_number" => {
+                expect_args(0)?;
+                let mut line = String::new();
+                self.read_input(&mut line).map_err(|e| format!("{}: {}", name, e))?;
+                let line = line.trim_end_matches(['\n', '\r']);
+                if name == "read_line" {
+                    Ok(Value::String(line.to_string()))
+                } else {
+                    Value::parse_number(line.trim())
+                        .ok_or_else(|| format!("read_number: \"{}\" is not a number", line))
+                }
+            },
+            "read_file" => {
+                expect_args(1)?;
+                let path = args[0].to_string();
+                std::fs::read_to_string(&path)
+                    .map(Value::String)
+                    .map_err(|e| format!("Error reading file '{}': {}", path, e))
+            },
+            "write_file" => {
+                expect_args(2)?;
+                let path = args[0].to_string();
+                std::fs::write(&path, args[1].to_string())
+                    .map(|_| Value::Nothing)
+                    .map_err(|e| format!("Error writing file '{}': {}", path, e))
+            },
+            "range" => {
+                // Half-open, like text slices: range(1, 4) is [1, 2, 3]
+                if !(2..=3).contains(&args.len()) {
+                    return Err(format!("range expects 2 or 3 arguments, got {}", args.len()));
+                }
+                let (start, end) = (number(&args[0])?, number(&args[1])?);
+                let step = args.get(2).map_or(Ok(1.0), number)?;
+                if step == 0.0 {
+                    return Err("range: step cannot be 0".to_string());
+                }
+                if ![start, end, step].iter().all(|n| n.is_finite()) {
+                    return Err("range: bounds and step must be finite".to_string());
+                }
+                // Counted up front, as a step too small to move a large bound would never arrive
+                const MAX_RANGE: f64 = 10_000_000.0;
+                let count = ((end - start) / step).ceil().max(0.0);
+                if count > MAX_RANGE {
+                    return Err(format!("range: {} to {} by {} gives too many items", start, end, step));
+                }
+                // Whole bounds and step count in Wholes, anything else in Decimals
+                let whole = args.iter().all(|arg| matches!(arg, Value::Number(_)));
+                let items = (0..count as usize)
+                    .map(|i| start + i as f64 * step)
+                    .map(|n| if whole { Value::Number(n) } else { Value::Decimal(n) })
+                    .collect();
+                Ok(Value::List(items))
+            },
+            "sort" => {
+                // A second argument of true sorts descending
+                if !(1..=2).contains(&args.len()) {
+                    return Err(format!("sort expects 1 or 2 arguments, got {}", args.len()));
+                }
+                let Value::List(items) = &args[0] else {
+                    return Err(format!("sort expects a List, got {}", args[0].type_name()));
+                };
+                let descending = match args.get(1) {
+                    None | Some(Value::Boolean(false)) => false,
+                    Some(Value::Boolean(true)) => true,
+                    Some(other) => return Err(format!("sort: descending must be Truth, got {}", other.type_name())),
+                };
+                let mut sorted = items.clone();
+                let mut failed = None;
+                sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or_else(|| {
+                    failed.get_or_insert_with(|| format!("sort: cannot order {} and {}", a.type_name(), b.type_name()));
+                    std::cmp::Ordering::Equal
+                }));
+                if let Some(err) = failed {
+                    return Err(err);
+                }
+                if descending {
+                    sorted.reverse();
+                }
+                Ok(Value::List(sorted))
+            },
+            "join" => {
+                // Items that are not text are shown as they would print
+                expect_args(2)?;
+                let Value::List(items) = &args[0] else {
+                    return Err(format!("join expects a List, got {}", args[0].type_name()));
+                };
+                let Value::String(separator) = &args[1] else {
+                    return Err(format!("join expects a text separator, got {}", args[1].type_name()));
+                };
+                let parts: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                Ok(Value::String(parts.join(separator)))
+            },
+            "upper" | "lower" => {
+                expect_args(1)?;
+                match &args[0] {
+                    Value::String(s) if name == "upper" => Ok(Value::String(s.to_uppercase())),
+                    Value::String(s) => Ok(Value::String(s.to_lowercase())),
+                    other => Err(format!("{} expects text, got {}", name, other.type_name())),
+                }
+            },
+            "first" | "last" => {
+                expect_args(1)?;
+                let Value::List(items) = &args[0] else {
+                    return Err(format!("{} expects a List, got {}", name, args[0].type_name()));
+                };
+                let item = if name == "first" { items.first() } else { items.last() };
+                item.cloned().ok_or_else(|| format!("{}: the list is empty", name))
+            },
+            "split" => {
+                // Without a separator, split on runs of whitespace
+                if !(1..=2).contains(&args.len()) {
+                    return Err(format!("split expects 1 or 2 arguments, got {}", args.len()));
+                }
+                let Value::String(text) = &args[0] else {
+                    return Err(format!("split expects text, got {}", args[0].type_name()));
+                };
+                let parts: Vec<&str> = match args.get(1) {
+                    None => text.split_whitespace().collect(),
+                    Some(Value::String(separator)) if separator.is_empty() => {
+                        return Err("split: separator cannot be empty".to_string());
+                    },
+                    Some(Value::String(separator)) => text.split(separator.as_str()).collect(),
+                    Some(other) => return Err(format!("split expects a text separator, got {}", other.type_name())),
+                };
+                Ok(Value::List(parts.into_iter().map(|part| Value::String(part.to_string())).collect()))
+            },
+            "format" => {
+                let Some((Value::String(template), values)) = args.split_first() else {
+                    return Err("format expects a text template first".to_string());
+                };
+                let pieces: Vec<&str> = template.split("{}").collect();
+                if pieces.len() - 1 != values.len() {
+                    return Err(format!("format: {} placeholder(s) but {} value(s)", pieces.len() - 1, values.len()));
+                }
+                // Each value shows the same way ConvertToString would show it
+                let mut result = pieces[0].to_string();
+                for (value, piece) in values.iter().zip(&pieces[1..]) {
+                    result.push_str(&value.to_string());
+                    result.push_str(piece);
+                }
+                Ok(Value::String(result))
+            },
+            "pretty" => {
+                expect_args(1)?;
+                Ok(Value::String(args[0].pretty()))
+            },
+            "doc" => {
+                expect_args(1)?;
+                let Value::String(target) = &args[0] else {
+                    return Err(format!("doc expects the name as text, got {}", args[0].type_name()));
+                };
+                Ok(self.doc(target)?.map_or(Value::Null, |doc| Value::String(doc.to_string())))
+            },
+            "typeof" => {
+                expect_args(1)?;
+                Ok(Value::String(args[0].type_name().to_string()))
+            },
+            "to_json" => {
+                expect_args(1)?;
+                json::to_json(&args[0]).map(Value::String)
+            },
+            "from_json" => {
+                expect_args(1)?;
+                match &args[0] {
+                    Value::String(text) => json::from_json(text),
+                    other => Err(format!("from_json expects text, got {}", other)),
+                }
+            },
+            "random" => {
+                expect_args(0)?;
+                Ok(Value::Decimal(self.next_random()))
+            },
+            "random_int" => {
+                expect_args(2)?;
+                let (lo, hi) = (number(&args[0])?.ceil(), number(&args[1])?.floor());
+                if lo > hi {
+                    return Err(format!("random_int: empty range {} to {}", lo, hi));
+                }
+                // Both bounds are inclusive
+                Ok(Value::Number(lo + (self.next_random() * (hi - lo + 1.0)).floor()))
+            },
+            "seed" => {
+                expect_args(1)?;
+                self.rng_state = Self::mix_seed(number(&args[0])? as i64 as u64);
+                Ok(Value::Nothing)
+            },
+            _ => Err(format!("Unknown function: {}", name)),
+        }
+    }
+
+    // splitmix64, so nearby seeds start far apart. xorshift never leaves the all-zero
+    // state, so that one output is replaced
+    fn mix_seed(seed: u64) -> u64 {
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        match z ^ (z >> 31) {
+            0 => 0x9E37_79B9_7F4A_7C15,
+            state => state,
+        }
+    }
+
+    // xorshift64*, scaled to a number in [0, 1)
+    fn next_random(&mut self) -> f64 {
+        let mut x = self.rng_state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng_state = x;
+        (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Two exact whole numbers must give an exact whole number: from 2^53 on an f64
+    // can no longer hold every integer, so such a result is an error rather than
+    // a silently rounded one. Anything with a Decimal in it gives a Decimal. Dividing by
+    // zero is left alone here
+    fn binary_op<F>(&self, a: Value, b: Value, op: F) -> Result<Value, String>
+    where
+        F: Fn(f64, f64) -> f64,
+    {
+        const MAX_EXACT_WHOLE: f64 = 9_007_199_254_740_991.0;  // 2^53 - 1
+        let too_large = |n: f64| n.is_infinite() || (n.fract() == 0.0 && n.abs() > MAX_EXACT_WHOLE);
+
+        match (a, b) {
+            (Value::Number(x), Value::Number(y)) => {
+                let result = op(x, y);
+                if y != 0.0 && (too_large(x) || too_large(y) || too_large(result)) {
+                    return Err(format!("Whole number overflow: {} and {} give a result too large to be exact", x, y));
+                }
+                // 2 ** -1 has left the whole numbers
+                Ok(if result.fract() == 0.0 { Value::Number(result) } else { Value::Decimal(result) })
+            },
+            (a, b) => match (a.as_number(), b.as_number()) {
+                (Some(x), Some(y)) => Ok(Value::Decimal(op(x, y))),
+                _ => Err("Invalid operands for arithmetic operation".to_string()),
+            },
+        }
+    }
+
+    // What each arithmetic opcode does to two numbers
+    fn arithmetic(op: &OpCode) -> fn(f64, f64) -> f64 {
+        match op {
+            OpCode::Add => |x, y| x + y,
+            OpCode::Subtract => |x, y| x - y,
+            OpCode::Multiply => |x, y| x * y,
+            OpCode::Divide => |x, y| x / y,
+            OpCode::Modulo => |x, y| x % y,
+            OpCode::Power => f64::powf,
+            OpCode::FloorDivide => |x, y| (x / y).floor(),
+            other => unreachable!("{:?} is not arithmetic", other),
+        }
+    }
+
+    fn concat_values(&self, a: Value, b: Value) -> Result<Value, String> {
+        match (a, b) {
+            (Value::String(s1), Value::String(s2)) => Ok(Value::String(s1 + &s2)),
+            _ => Err("Can only concatenate strings".to_string()),
+        }
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs a script and gives back a variable as show would print it
+    fn shown_as(source: &str, name: &str) -> String {
+        let mut runtime = Runtime::new();
+        runtime.process_input(source).unwrap();
+        runtime.variables[name].to_string()
+    }
+
+    #[test]
+    fn casts_convert_values() {
+        assert_eq!(shown_as("x is 3.7 as Whole", "x"), "3");
+        assert_eq!(shown_as("x is \"2.5\" as Number", "x"), "2.5");
+        assert_eq!(shown_as("x is 5 as Text", "x"), "5");
+    }
+
+    #[test]
+    fn impossible_cast_is_an_error() {
+        let err = Runtime::new().process_input("x is \"abc\" as Number").unwrap_err().to_string();
+        assert!(err.contains("Cannot cast \"abc\" to Number"), "{}", err);
+    }
+
+    #[test]
+    fn show_runs_a_computed_value() {
+        assert!(Runtime::new().process_input("x is 4\nshow x + 1").is_ok());
+    }
+
+    #[test]
+    fn declared_type_persists_across_inputs() {
+        let mut runtime = Runtime::new();
+        runtime.process_input("x as Whole is 1").unwrap();
+        let err = runtime.process_input("x is \"s\"").unwrap_err().to_string();
+        assert!(err.contains("Type mismatch"), "{}", err);
+    }
+
+    #[test]
+    fn expression_statements_are_type_checked() {
+        let err = Runtime::new().process_input("\"a\" - 1").unwrap_err().to_string();
+        assert!(err.contains("Invalid operand types"), "{}", err);
+    }
+
+    #[test]
+    fn negation() {
+        assert_eq!(shown_as("x is - -3", "x"), "3");
+        assert_eq!(shown_as("x is -2.5", "x"), "-2.5");
+        assert_eq!(shown_as("a is 1 + 2\nx is -a", "x"), "-3");
+    }
+
+    #[test]
+    fn not_flips_a_truth() {
+        assert_eq!(shown_as("x is not true", "x"), "false");
+        assert_eq!(shown_as("a is 1 > 2\nx is not a", "x"), "true");
+        assert_eq!(shown_as("x is not not false", "x"), "false");
+    }
+
+    #[test]
+    fn whole_division_follows_the_declared_type() {
+        assert_eq!(shown_as("x as Whole is 7 / 2", "x"), "3");
+        assert_eq!(shown_as("x as Decimal is 7 / 2", "x"), "3.5");
+        assert_eq!(shown_as("x is -7 / 2", "x"), "-4");
+    }
+
+    #[test]
+    fn later_input_sees_earlier_variables() {
+        let mut runtime = Runtime::new();
+        runtime.process_input("x is 41").unwrap();
+        runtime.process_input("y is x + 1").unwrap();
+        assert_eq!(runtime.variables["y"].to_string(), "42");
+    }
+
+    #[test]
+    fn untyped_initializers_fit_typed_declarations() {
+        assert_eq!(shown_as("a is 7\nb is 2\nc as Whole is a / b", "c"), "3");
+    }
+
+    #[test]
+    fn length_of_text_lists_and_mappings() {
+        assert_eq!(shown_as("n is length(\"h\u{e9}llo\")", "n"), "5");
+        assert_eq!(shown_as("xs is [1, 2, 3]\nn is length(xs)", "n"), "3");
+        assert_eq!(shown_as("m is {a: 1, b: 2}\nn is length(m)", "n"), "2");
+    }
+
+    #[test]
+    fn to_json_serializes_a_list() {
+        let mut runtime = Runtime::new();
+        runtime.variables.insert("xs".to_string(), Value::List(vec![Value::Number(1.0), Value::String("a".to_string())]));
+        runtime.process_input("text is to_json(xs)").unwrap();
+        assert_eq!(runtime.variables["text"].to_string(), "[1,\"a\"]");
+    }
+
+    #[test]
+    fn from_json_reads_text() {
+        assert_eq!(shown_as("n is length(from_json(\"[1, 2, 3]\"))", "n"), "3");
+        let err = Runtime::new().process_input("x is from_json(\"[1\")").unwrap_err().to_string();
+        assert!(err.starts_with("from_json:"), "{}", err);
+    }
+
+    #[test]
+    fn typeof_names_each_kind_of_value() {
+        let cases = [
+            ("3", "Whole"), ("2.5", "Decimal"), ("\"hi\"", "Text"), ("true", "Truth"),
+            ("[1, 2]", "List"), ("Promise[Whole]", "Promise"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(shown_as(&format!("t is typeof({})", value), "t"), expected, "{}", value);
+        }
+
+        let mut runtime = Runtime::new();
+        runtime.variables.insert("nothing".to_string(), Value::Null);
+        runtime.variables.insert("m".to_string(), Value::Mapping(Vec::new()));
+        runtime.process_input("a is typeof(nothing)\nb is typeof(m)\nObject Dog:\nc is typeof(new Dog)").unwrap();
+        assert_eq!(runtime.variables["a"].to_string(), "Nothing");
+        assert_eq!(runtime.variables["b"].to_string(), "Mapping");
+        assert_eq!(runtime.variables["c"].to_string(), "Dog");
+    }
+
+    #[test]
+    fn format_fills_placeholders_in_order() {
+        assert_eq!(shown_as("a is 2\nb is 3\ns is format(\"{} + {} = {}\", a, b, a + b)", "s"), "2 + 3 = 5");
+        assert_eq!(shown_as("s is format(\"plain\")", "s"), "plain");
+        assert_eq!(shown_as("n is 4\ns is format(\"{n}: {}\", 5)", "s"), "4: 5");
+        let err = Runtime::new().process_input("s is format(\"{} and {}\", 1)").unwrap_err().to_string();
+        assert_eq!(err, "format: 2 placeholder(s) but 1 value(s)");
+        assert!(Runtime::new().process_input("s is format(1)").is_err());
+    }
+
+    #[test]
+    fn range_counts_up_to_its_end() {
+        assert_eq!(shown_as("xs is range(1, 5)", "xs"), "[1, 2, 3, 4]");
+        assert_eq!(shown_as("xs is range(0, 10, 2)", "xs"), "[0, 2, 4, 6, 8]");
+        assert_eq!(shown_as("xs is range(3, 0, -1)", "xs"), "[3, 2, 1]");
+        assert_eq!(shown_as("xs is range(5, 1)", "xs"), "[]");
+        let err = Runtime::new().process_input("xs is range(0, 10, 0)").unwrap_err().to_string();
+        assert_eq!(err, "range: step cannot be 0");
+        let err = Runtime::new().process_input("xs is range(\"a\", 3)").unwrap_err().to_string();
+        assert_eq!(err, "range expects a number, got a");
+        // Ranges too long to build, or that would never reach their end, are refused
+        for source in ["range(0, 1e17)", "range(0, 1, 1e-300)", "range(0, 5 * 10 ** 7)"] {
+            let err = Runtime::new().process_input(&format!("xs is {}", source)).unwrap_err().to_string();
+            assert!(err.contains("too many items"), "{}: {}", source, err);
+        }
+        let err = Runtime::new().process_input("xs is range(0, from_json(\"1e400\"))").unwrap_err().to_string();
+        assert_eq!(err, "range: bounds and step must be finite");
+        assert_eq!(shown_as("xs is range(0, 1, 0.25)", "xs"), "[0.0, 0.25, 0.5, 0.75]");
+    }
+
+    #[test]
+    fn contains_checks_lists_mappings_and_text() {
+        let mut runtime = Runtime::new();
+        runtime.variables.insert("m".to_string(), Value::Mapping(vec![("key".to_string(), Value::Number(1.0))]));
+        runtime.process_input("xs is [1, 2, 3]
+a is xs contains 3
+b is xs contains 4
+c is m contains \"key\"
+d is m contains \"other\"
+e is \"hello\" contains \"ell\"").unwrap();
+        let results: Vec<String> = ["a", "b", "c", "d", "e"].iter().map(|name| runtime.variables[*name].to_string()).collect();
+        assert_eq!(results, ["true", "false", "true", "false", "true"]);
+        assert!(Runtime::new().process_input("a is 5 contains 1").is_err());
+    }
+
+    #[test]
+    fn join_makes_one_text() {
+        assert_eq!(shown_as("s is join([\"a\", \"b\", \"c\"], \", \")", "s"), "a, b, c");
+        assert_eq!(shown_as("s is join([], \"-\")", "s"), "");
+        assert_eq!(shown_as("s is join([\"n\", 2], \"=\")", "s"), "n=2");
+    }
+
+    #[test]
+    fn split_makes_a_list() {
+        assert_eq!(shown_as("xs is split(\"a,b,,c\", \",\")", "xs"), "[a, b, , c]");
+        assert_eq!(shown_as("xs is split(\"  one two\tthree \")", "xs"), "[one, two, three]");
+        let err = Runtime::new().process_input("xs is split(\"abc\", \"\")").unwrap_err().to_string();
+        assert_eq!(err, "split: separator cannot be empty");
+    }
+
+    #[test]
+    fn sort_orders_numbers_and_text() {
+        assert_eq!(shown_as("xs is sort([3, 1.5, 2])", "xs"), "[1.5, 2, 3]");
+        assert_eq!(shown_as("xs is sort([\"pear\", \"apple\", \"fig\"], true)", "xs"), "[pear, fig, apple]");
+        let err = Runtime::new().process_input("xs is sort([1, \"a\"])").unwrap_err().to_string();
+        assert_eq!(err, "sort: cannot order Text and Whole");
+    }
+
+    #[test]
+    fn length_of_a_number_is_an_error() {
+        let err = Runtime::new().process_input("n is length(5)").unwrap_err().to_string();
+        assert_eq!(err, "length: cannot take the length of 5");
+    }
+
+    #[test]
+    fn math_functions() {
+        let cases = [("sqrt(16)", "4.0"), ("abs(-3)", "3"), ("abs(-2.5)", "2.5"), ("floor(2.7)", "2"),
+                     ("ceil(2.1)", "3"), ("round(2.5)", "3"), ("min(3, 1, 2)", "1"), ("max(3, 1, 2)", "3"),
+                     ("min(1.5, 2)", "1.5")];
+        for (expression, expected) in cases {
+            assert_eq!(shown_as(&format!("x is {}", expression), "x"), expected, "{}", expression);
+        }
+    }
+
+    #[test]
+    fn math_errors_name_the_function() {
+        let err = Runtime::new().process_input("x is sqrt(\"a\")").unwrap_err().to_string();
+        assert!(err.contains("sqrt"), "{}", err);
+    }
+
+    // The values a script leaves in a and b, as they would be shown
+    fn pair(source: &str) -> (String, String) {
+        let mut runtime = Runtime::new();
+        runtime.process_input(source).unwrap();
+        (runtime.variables["a"].to_string(), runtime.variables["b"].to_string())
+    }
+
+    #[test]
+    fn seeded_random_int_is_deterministic() {
+        let source = "seed(42)\na is random_int(1, 6)\nb is random_int(1, 6)";
+        let (a, b) = pair(source);
+        assert_eq!((a.clone(), b.clone()), pair(source));
+        for roll in [a, b] {
+            assert!(["1", "2", "3", "4", "5", "6"].contains(&roll.as_str()), "{}", roll);
+        }
+    }
+
+    #[test]
+    fn neighbouring_seeds_give_different_sequences() {
+        let rolls = |seed: i64| pair(&format!("seed({})\na is random()\nb is random()", seed));
+        assert_ne!(rolls(2), rolls(3));
+        assert_ne!(rolls(4), rolls(5));
+    }
+
+    #[test]
+    fn random_is_below_one() {
+        let (a, b) = pair("seed(7)\na is random()\nb is random()");
+        for value in [a, b] {
+            let value: f64 = value.parse().unwrap();
+            assert!((0.0..1.0).contains(&value), "{}", value);
+        }
+    }
+
+    fn run_with_input(source: &str, input: &'static str) -> Result<Runtime, NairError> {
+        let mut runtime = Runtime::new().with_input(Box::new(io::Cursor::new(input)));
+        runtime.process_input(source)?;
+        Ok(runtime)
+    }
+
+    // A writer whose contents stay readable after the runtime takes it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn show_writes_to_the_given_output() {
+        let buffer = SharedBuffer::default();
+        let mut runtime = Runtime::new().with_output(Box::new(buffer.clone()));
+        runtime.process_input("x is 2\nshow \"x is\", x\nshow x + 1").unwrap();
+        assert_eq!(String::from_utf8(buffer.0.borrow().clone()).unwrap(), "x is 2\n3\n");
+    }
+
+    #[test]
+    fn show_prints_and_leaves_the_stack_empty() {
+        let buffer = SharedBuffer::default();
+        let mut runtime = Runtime::new().with_output(Box::new(buffer.clone()));
+        let code = runtime.compile("show \"hi\"").unwrap();
+        assert_eq!(runtime.run(code, &[]).unwrap(), []);
+        assert_eq!(String::from_utf8(buffer.0.borrow().clone()).unwrap(), "hi\n");
+    }
+
+    #[test]
+    fn expression_statements_leave_the_stack_empty() {
+        let mut runtime = Runtime::new();
+        let code = runtime.compile("x is 2\n1 + 2\n\"text\"\nx * 3\n[1, 2]\nlength(\"abc\")").unwrap();
+        assert_eq!(runtime.run(code, &[]).unwrap(), []);
+    }
+
+    #[test]
+    fn call_statements_run() {
+        let buffer = SharedBuffer::default();
+        let mut runtime = Runtime::new().with_output(Box::new(buffer.clone()));
+        let code = runtime.compile("Task greet requires name:\n    show \"hello\", name\n    output 1\ngreet(\"Ada\")\ngreet(\"Bo\")\nlength(\"abc\")").unwrap();
+        assert_eq!(runtime.run(code, &[]).unwrap(), []);
+        assert_eq!(String::from_utf8(buffer.0.borrow().clone()).unwrap(), "hello Ada\nhello Bo\n");
+    }
+
+    #[test]
+    fn read_number_reads_injected_input() {
+        let runtime = run_with_input("n is read_number()", "42\n").unwrap();
+        assert_eq!(runtime.variables["n"].to_string(), "42");
+        let runtime = run_with_input("line is read_line()", "hello there\n").unwrap();
+        assert_eq!(runtime.variables["line"].to_string(), "hello there");
+        assert!(run_with_input("n is read_number()", "many\n").is_err());
+    }
+
+    #[test]
+    fn write_file_then_read_it_back() {
+        let path = std::env::temp_dir().join(format!("nair-runtime-{}.txt", std::process::id()));
+        let source = format!("write_file(\"{0}\", \"saved text\")\ntext is read_file(\"{0}\")", path.display());
+        assert_eq!(shown_as(&source, "text"), "saved text");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reading_a_missing_file_is_an_error() {
+        let err = Runtime::new().process_input("text is read_file(\"/nonexistent/nair/file.txt\")").unwrap_err().to_string();
+        assert!(err.contains("No such file"), "{}", err);
+    }
+
+    // A fresh directory per test, so files from other runs don't interfere
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nair-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn using_a_file_registers_its_tasks() {
+        let dir = scratch_dir("using");
+        std::fs::write(dir.join("math.nair"), "Task double requires n as Whole:\n    returns n * 2\nshow \"not run\"").unwrap();
+        std::fs::write(dir.join("main.nair"), "using \"math.nair\"\nresult is double(21)").unwrap();
+
+        let mut runtime = Runtime::new();
+        runtime.run_file(dir.join("main.nair").to_str().unwrap()).unwrap();
+        assert_eq!(runtime.variables["result"].to_string(), "42");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn variadic_task_collects_its_arguments() {
+        let source = "Task sum requires each numbers as List:\n    returns numbers\nxs is sum(1, 2, 3)";
+        let mut runtime = Runtime::new();
+        runtime.process_input(source).unwrap();
+        let Value::List(numbers) = &runtime.variables["xs"] else {
+            panic!("expected a list, got {}", runtime.variables["xs"]);
+        };
+        let total: f64 = numbers.iter().map(|n| match n { Value::Number(n) => *n, _ => 0.0 }).sum();
+        assert_eq!(total, 6.0);
+
+        let source = "Task count requires label, each rest:\n    returns length(rest)\nn is count(\"none\")";
+        assert_eq!(shown_as(source, "n"), "0");
+        let err = Runtime::new().process_input("Task count requires label, each rest:\n    returns label\nn is count()")
+            .unwrap_err().to_string();
+        assert_eq!(err, "count expects at least 1 argument(s), got 0");
+    }
+
+    #[test]
+    fn circular_imports_are_an_error() {
+        let dir = scratch_dir("circular");
+        std::fs::write(dir.join("a.nair"), "using \"b.nair\"").unwrap();
+        std::fs::write(dir.join("b.nair"), "using \"a.nair\"").unwrap();
+
+        let err = Runtime::new().run_file(dir.join("a.nair").to_str().unwrap()).unwrap_err().to_string();
+        assert!(err.contains("Circular import"), "{}", err);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_errors_name_the_failing_line() {
+        let dir = scratch_dir("lines");
+        let script = dir.join("script.nair");
+        std::fs::write(&script, "x is 1\nwhen x is 1:\n    y is 2\n    z is x / 0\nshow x").unwrap();
+        let err = Runtime::new().run_file(script.to_str().unwrap()).unwrap_err().to_string();
+        assert_eq!(err, "Division by zero (line 4)");
+
+        // A failure inside a Task is reported at the line in its body
+        std::fs::write(&script, "Task bad:\n    returns \"abc\" as Number\nx is 1\ny is bad()").unwrap();
+        let err = Runtime::new().run_file(script.to_str().unwrap()).unwrap_err().to_string();
+        assert!(err.ends_with("(line 2)"), "{}", err);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn or_when_picks_the_first_true_branch() {
+        let source = "n is 5\nwhen n > 10:\n    size is \"big\"\nor when n > 3:\n    size is \"medium\"\nor:\n    size is \"small\"";
+        assert_eq!(shown_as(source, "size"), "medium");
+    }
+
+    #[test]
+    fn whole_overflow_is_detected() {
+        let err = Runtime::new().process_input("big is 9007199254740991\nx is big + big").unwrap_err().to_string();
+        assert_eq!(err, "Whole number overflow: 9007199254740991 and 9007199254740991 give a result too large to be exact");
+        // Results that round down to 2^53 itself are caught as well
+        assert!(Runtime::new().process_input("x is 9007199254740991 + 2").is_err());
+        assert!(Runtime::new().process_input("x is 9007199254740991 + 1").is_err());
+        assert!(Runtime::new().process_input("x is 9007199254740992 + 1").is_err());
+        assert!(Runtime::new().process_input("x is 3037000500 * 3037000500").is_err());
+        // Still fine within range, and decimals are never checked
+        assert_eq!(shown_as("x is 9007199254740991 - 1", "x"), "9007199254740990");
+        assert_eq!(shown_as("x is 1e300 * 10.5", "x"), format!("{}.0", 1e300 * 10.5));
+    }
+
+    #[test]
+    fn increase_and_decrease_update_a_variable() {
+        assert_eq!(shown_as("count is 1\ncount increase by 1", "count"), "2");
+        assert_eq!(shown_as("count as Whole is 10\ncount decrease by 2 * 2", "count"), "6");
+        assert_eq!(shown_as("count is 1\ncount becomes count * 5", "count"), "5");
+        let err = Runtime::new().process_input("missing increase by 1").unwrap_err().to_string();
+        assert_eq!(err, "Undefined variable: missing");
+        let err = Runtime::new().process_input("missing becomes 1").unwrap_err().to_string();
+        assert_eq!(err, "Undefined variable: missing");
+    }
+
+    #[test]
+    fn each_in_builds_a_new_list() {
+        assert_eq!(shown_as("nums is [1, 2, 3]\ndoubled is each x in nums becomes x * 2", "doubled"), "[2, 4, 6]");
+        assert_eq!(shown_as("big is each x in [1, 5, 2, 8] becomes x when x > 2", "big"), "[5, 8]");
+        assert_eq!(shown_as("none is each x in [] becomes x", "none"), "[]");
+    }
+
+    #[test]
+    fn each_in_names_only_last_as_long_as_the_map() {
+        let mut runtime = Runtime::new();
+        runtime.process_input("x as Text is \"a\"\nys is each x in [1, 2] becomes x * 2\nzs is each z in ys becomes z").unwrap();
+        assert_eq!(runtime.variables["ys"].to_string(), "[2, 4]");
+        assert_eq!(runtime.variables["x"].to_string(), "a");
+        assert!(!runtime.variables.contains_key("z"));
+    }
+
+    #[test]
+    fn break_stops_at_a_threshold() {
+        let source = "n is 0\nloop while 100 > n:\n    n is n + 1\n    when n is 5:\n        break";
+        assert_eq!(shown_as(source, "n"), "5");
+    }
+
+    #[test]
+    fn fixed_values_stay_fixed_across_inputs() {
+        let mut runtime = Runtime::new();
+        runtime.process_input("fixed limit is 10").unwrap();
+        runtime.process_input("double is limit * 2").unwrap();
+        assert_eq!(runtime.variables["double"].to_string(), "20");
+        let err = runtime.process_input("limit is 11").unwrap_err().to_string();
+        assert_eq!(err, "Cannot reassign constant limit");
+        assert_eq!(runtime.variables["limit"].to_string(), "10");
+    }
+
+    #[test]
+    fn otherwise_falls_back_on_null() {
+        assert_eq!(shown_as("name as Text\ngreeting is name otherwise \"anonymous\"", "greeting"), "anonymous");
+        assert_eq!(shown_as("name is \"Ada\"\ngreeting is name otherwise \"anonymous\"", "greeting"), "Ada");
+    }
+
+    #[test]
+    fn otherwise_skips_the_fallback_when_not_null() {
+        let boom = "Task boom:\n    output sort([1, \"evaluated\"])\n";
+        assert_eq!(shown_as(&format!("{}name is \"Ada\" otherwise boom()", boom), "name"), "Ada");
+        let mut runtime = Runtime::new();
+        let err = runtime.process_input(&format!("{}missing as Text\nname is missing otherwise boom()", boom)).unwrap_err();
+        assert!(err.to_string().contains("sort"), "{}", err);
+    }
+
+    #[test]
+    fn using_binds_then_releases() {
+        let source = "open is 1\nseen is \"\"\nObject Handle:\n    Task release:\n        open is open - 1\n\
+                      using new Handle as h:\n    seen is typeof(h)";
+        let mut runtime = Runtime::new();
+        runtime.process_input(source).unwrap();
+        assert_eq!(runtime.variables["seen"].to_string(), "Handle");
+        assert_eq!(runtime.variables["open"].to_string(), "0");
+        assert!(!runtime.variables.contains_key("h"));
+    }
+
+    #[test]
+    fn using_gives_back_the_name_and_releases_on_error() {
+        let mut runtime = Runtime::new();
+        runtime.process_input("r is 1\nusing 5 as r:\n    seen is r").unwrap();
+        assert_eq!(runtime.variables["r"].to_string(), "1");
+        assert_eq!(runtime.variables["seen"].to_string(), "5");
+
+        let source = "open is 1\nObject Handle:\n    Task release:\n        open is open - 1\n\
+                      using new Handle as h:\n    x is 1 / 0";
+        let mut runtime = Runtime::new();
+        let err = runtime.process_input(source).unwrap_err().to_string();
+        assert_eq!(err, "Division by zero");
+        assert_eq!(runtime.variables["open"].to_string(), "0");
+        assert!(!runtime.variables.contains_key("h"));
+    }
+
+    #[test]
+    fn with_calls_a_task() {
+        let source = "Task add requires a, b:\n    output a + b\nsum is add with 2, 3";
+        assert_eq!(shown_as(source, "sum"), "5");
+    }
+
+    #[test]
+    fn every_arithmetic_op() {
+        let ops = [
+            (OpCode::Add, 9.0),
+            (OpCode::Subtract, 5.0),
+            (OpCode::Multiply, 14.0),
+            (OpCode::Divide, 3.5),
+            (OpCode::Modulo, 1.0),
+            (OpCode::Power, 49.0),
+            (OpCode::FloorDivide, 3.0),
+        ];
+        for (op, expected) in ops {
+            let code = vec![OpCode::Push(Value::Number(7.0)), OpCode::Push(Value::Number(2.0)), op.clone()];
+            assert_eq!(Runtime::new().run(code, &[]).unwrap(), [Value::Number(expected)], "{:?}", op);
+        }
+        let code = vec![OpCode::Push(Value::String("a".to_string())), OpCode::Push(Value::Number(1.0)), OpCode::Add];
+        assert_eq!(Runtime::new().run(code, &[]).unwrap_err(), "Cannot add Text and Whole");
+    }
+
+    #[test]
+    fn decimals_stay_decimal_without_a_fraction() {
+        let source = "a is 4.0 as Decimal\nb as Decimal is 4\nc is 4.0\nd is 4\ne is from_json(\"[4.0, 4]\")\n\
+                      ta is typeof(a)\ntb is typeof(b)\ntc is typeof(c)\ntd is typeof(d)\nte is typeof(e at 0)\ntf is typeof(e at 1)";
+        let mut runtime = Runtime::new();
+        runtime.process_input(source).unwrap();
+        let types: Vec<String> = ["ta", "tb", "tc", "td", "te", "tf"].iter().map(|name| runtime.variables[*name].to_string()).collect();
+        assert_eq!(types, ["Decimal", "Decimal", "Decimal", "Whole", "Decimal", "Whole"]);
+        assert_eq!(runtime.variables["c"], runtime.variables["d"]);
+
+        let store = OpCode::StoreVar { name: "x".to_string(), declared_type: Some("Whole".to_string()) };
+        let err = Runtime::new().run(vec![OpCode::Push(Value::Decimal(4.0)), store], &[]).unwrap_err();
+        assert_eq!(err, "Type mismatch: cannot assign Decimal to variable of type Whole");
+        assert_eq!(shown_as("x is 2.5 * 2\nt is typeof(x)", "t"), "Decimal");
+        assert_eq!(shown_as("x is 2.5 * 2", "x"), "5.0");
+    }
+
+    #[test]
+    fn each_loop_counts_its_position() {
+        let source = "total is 0\nloop each n in [10, 20, 30]:\n    total is total + index * n";
+        assert_eq!(shown_as(source, "total"), "80");
+        let source = "total is 0\nloop each n at i in [10, 20, 30, 40]:\n    when i is 3:\n        break\n    total is total + i * n";
+        assert_eq!(shown_as(source, "total"), "80");
+    }
+
+    #[test]
+    fn each_loop_names_only_last_as_long_as_the_loop() {
+        let mut runtime = Runtime::new();
+        runtime.process_input("index is \"keep\"\ntotal is 0\nloop each n in [1, 2]:\n    total is total + n * index").unwrap();
+        assert_eq!(runtime.variables["index"].to_string(), "keep");
+        assert_eq!(runtime.variables["total"].to_string(), "2");
+        assert!(!runtime.variables.contains_key("n"));
+
+        // A typed outer name keeps its value and its type
+        let mut runtime = Runtime::new();
+        runtime.process_input("index as Text is \"keep\"\nloop each n in [1, 2]:\n    when index is 1:\n        break").unwrap();
+        assert_eq!(runtime.variables["index"].to_string(), "keep");
+        assert_eq!(runtime.variable_types["index"], "Text");
+    }
+
+    #[test]
+    fn method_calls_chain() {
+        let source = "Object Inner:\n    build defaults n:\n        me.n is n\n    Task twice:\n        output me.n * 2\n\
+                      Object Outer:\n    build defaults inner:\n        me.inner is inner\n\
+                      o is new Outer with new Inner with 21\nx is o.inner.twice()";
+        assert_eq!(shown_as(source, "x"), "42");
+        assert_eq!(shown_as("xs is [\"ada\", \"bob\"]\ny is xs.first().upper()", "y"), "ADA");
+        assert_eq!(shown_as("xs is [3, 1, 2]\ny is xs.sort().last()", "y"), "3");
+        let err = Runtime::new().process_input("xs is []\ny is xs.first()").unwrap_err();
+        assert_eq!(err.to_string(), "first: the list is empty");
+    }
+
+    #[test]
+    fn dividing_by_zero_fails() {
+        for op in [OpCode::Divide, OpCode::Modulo, OpCode::FloorDivide] {
+            let code = vec![OpCode::Push(Value::Number(5.0)), OpCode::Push(Value::Number(0.0)), op.clone()];
+            assert_eq!(Runtime::new().run(code, &[]).unwrap_err(), "Division by zero", "{:?}", op);
+        }
+        let err = Runtime::new().process_input("x is 5 / 0").unwrap_err();
+        assert_eq!(err.to_string(), "Division by zero");
+        let err = Runtime::new().process_input("x is 5 % 0").unwrap_err();
+        assert_eq!(err.to_string(), "Division by zero");
+        let err = Runtime::new().process_input("x is 5.5 / 0.0").unwrap_err();
+        assert_eq!(err.to_string(), "Division by zero");
+    }
+
+    #[test]
+    fn spelled_out_comparisons() {
+        let source = "x is 5\na is x is not 5\nb is x is not 4\nc is x is greater than 3\nd is x is less than 3\ne is 2 is less than x";
+        let mut runtime = Runtime::new();
+        runtime.process_input(source).unwrap();
+        let shown: Vec<String> = ["a", "b", "c", "d", "e"].iter().map(|name| runtime.variables[*name].to_string()).collect();
+        assert_eq!(shown, ["false", "true", "true", "false", "true"]);
+    }
+
+    #[test]
+    fn record_fields_can_be_read() {
+        let source = "p is {name: \"Ada\", age: 36}\nolder is p.age + 1\nname is p.name";
+        assert_eq!(shown_as(source, "older"), "37");
+        assert_eq!(shown_as(source, "name"), "Ada");
+        assert_eq!(shown_as(source, "p"), "{name: Ada, age: 36}");
+    }
+
+    #[test]
+    fn budgets_stop_runaway_scripts() {
+        let mut runtime = Runtime::new().with_instruction_budget(1000);
+        let err = runtime.process_input("n is 0\nloop:\n    n becomes n + 1").unwrap_err();
+        assert_eq!(err.to_string(), "Instruction budget exceeded");
+        // Task bodies count too
+        let err = Runtime::new().with_instruction_budget(1000)
+            .process_input("Task spin:\n    loop:\n        continue\nspin()").unwrap_err();
+        assert_eq!(err.to_string(), "Instruction budget exceeded");
+        assert!(Runtime::new().with_instruction_budget(1000).process_input("x is 1 + 2").is_ok());
+
+        let mut runtime = Runtime::new().with_output(Box::new(io::sink())).with_output_budget(10);
+        runtime.process_input("show \"12345\"").unwrap();
+        let err = runtime.process_input("show \"12345\"").unwrap_err();
+        assert_eq!(err.to_string(), "Output budget exceeded");
+    }
+
+    #[test]
+    fn field_of_reads_like_a_dot() {
+        let source = "Object Dog:\n    build defaults name:\n        me.name is name\nd is new Dog with \"Rex\"\n\
+                      p is {home: {city: \"London\"}}\na is name of d\nb is city of home of p";
+        assert_eq!(shown_as(source, "a"), "Rex");
+        assert_eq!(shown_as(source, "b"), "London");
+    }
+
+    #[test]
+    fn truth_variables_take_either_name() {
+        assert_eq!(shown_as("flag as Truth is true", "flag"), "true");
+        assert_eq!(shown_as("flag as Logic is false", "flag"), "false");
+        assert!(Runtime::new().process_input("flag as Truth is 1").is_err());
+    }
+
+    #[test]
+    fn literal_words_evaluate() {
+        let source = "done is true\nlater is false\nmissing is null\nfilled is missing otherwise 3";
+        assert_eq!(shown_as(source, "done"), "true");
+        assert_eq!(shown_as(source, "later"), "false");
+        assert_eq!(shown_as(source, "missing"), "null");
+        assert_eq!(shown_as(source, "filled"), "3");
+    }
+
+    #[test]
+    fn lexer_errors_reach_the_user() {
+        let err = Runtime::new().process_input("show \"unfinished").unwrap_err();
+        assert!(err.to_string().starts_with("Unterminated string"), "{}", err);
+    }
+
+    #[test]
+    fn modulo_and_power_symbols() {
+        assert_eq!(shown_as("x is 10 % 3", "x"), "1");
+        assert_eq!(shown_as("x is 2 ** 3", "x"), "8");
+        assert_eq!(shown_as("x is 2 ** 3 ** 2", "x"), "512");
+        assert_eq!(shown_as("x is -2 ** 2", "x"), "-4");
+        assert_eq!(shown_as("x is 2 ** -1", "x"), "0.5");
+        assert_eq!(shown_as("x is 1 + 7 % 4 * 2", "x"), "7");
+    }
+
+    #[test]
+    fn symbolic_comparisons() {
+        let source = "x is 5\na is x < 6\nb is x <= 4\nc is x >= 5\nd is x == 5\ne is x != 5";
+        let mut runtime = Runtime::new();
+        runtime.process_input(source).unwrap();
+        let shown: Vec<String> = ["a", "b", "c", "d", "e"].iter().map(|name| runtime.variables[*name].to_string()).collect();
+        assert_eq!(shown, ["true", "false", "true", "true", "false"]);
+    }
+
+    #[test]
+    fn bare_loop_runs_until_break() {
+        let source = "n is 0\nloop:\n    n is n + 1\n    when n is 3:\n        break\nafter is n";
+        assert_eq!(shown_as(source, "after"), "3");
+    }
+
+    #[test]
+    fn continue_skips_even_numbers() {
+        let source = "n as Whole is 0\nodd is 0\nloop while 5 > n:\n    n is n + 1\n    when n / 2 * 2 is n:\n        continue\n    odd is odd + n";
+        assert_eq!(shown_as(source, "odd"), "9");
+    }
+
+    #[test]
+    fn long_loop_leaves_the_stack_empty() {
+        let mut runtime = Runtime::new();
+        let code = runtime.compile("n is 0\nloop while 1000 > n:\n    n is n + 1").unwrap();
+        assert!(runtime.execute_bytecode(code).unwrap().is_empty());
+        assert_eq!(runtime.variables["n"].to_string(), "1000");
+    }
+
+    #[test]
+    fn non_truth_condition_is_a_runtime_error() {
+        // Carried over from an earlier input, x is untyped to the analyzer
+        let mut runtime = Runtime::new();
+        runtime.process_input("x is 8").unwrap();
+        let err = runtime.process_input("when x:\n    show x").unwrap_err().to_string();
+        assert!(err.contains("Condition must be Truth"), "{}", err);
+    }
+
+    #[test]
+    fn subclass_calls_an_inherited_method() {
+        let source = "Object Animal:
+    Task greet:
+        returns \"hello\"
+    Task kind:
+        returns \"animal\"
+Object Dog inherits Animal:
+    Task kind:
+        returns \"dog\"
+Object Puppy inherits Dog:
+greeting is new Puppy.greet()
+kind is new Puppy.kind()";
+        let mut runtime = Runtime::new();
+        runtime.process_input(source).unwrap();
+        assert_eq!(runtime.variables["greeting"].to_string(), "hello");
+        assert_eq!(runtime.variables["kind"].to_string(), "dog");
+    }
+
+    #[test]
+    fn build_base_runs_the_inherited_constructor() {
+        // Puppy's base is Dog, so Dog's own `build base` reaches Animal
+        let source = "Object Animal:
+    build defaults name as Text:
+        my name is name
+        my sound is \"...\"
+Object Dog inherits Animal:
+    build defaults name as Text:
+        build base with name
+        my sound is \"woof\"
+Object Puppy inherits Dog:
+    build:
+        build base with \"Pup\"
+        my small is true
+p is new Puppy
+shown is \"{p.name} {p.sound} {p.small}\"";
+        assert_eq!(shown_as(source, "shown"), "Pup woof true");
+        let source = "Object A:\n    build:\n        my x is 1\nObject B inherits A:\n    build:\n        build base with 2\nb is new B";
+        let err = Runtime::new().process_input(source).unwrap_err().to_string();
+        assert_eq!(err, "Constructor for A expects 0 arguments, got 1");
+    }
+
+    #[test]
+    fn parameters_shadow_globals() {
+        let mut runtime = Runtime::new();
+        runtime.process_input("n is 100\nTask bump requires n:\n    n is n + 1\n    output n\nb is bump(1)").unwrap();
+        assert_eq!(runtime.variables["b"].to_string(), "2");
+        assert_eq!(runtime.variables["n"].to_string(), "100");
+    }
+
+    #[test]
+    fn emitted_values_are_kept_in_order() {
+        let mut runtime = Runtime::new();
+        runtime.process_input("Emit \"started\"\nEmit 2 + 3").unwrap();
+        let events: Vec<String> = runtime.events().iter().map(Value::to_string).collect();
+        assert_eq!(events, ["started", "5"]);
+    }
+
+    #[test]
+    fn output_returns_from_a_task() {
+        let source = "Object Dice:
+    Task sides:
+        output 6
+d is new Dice
+total is d.sides() + 1";
+        assert_eq!(shown_as(source, "total"), "7");
+    }
+
+    #[test]
+    fn method_updates_a_field_through_me() {
+        let source = "Object Counter:
+    build:
+        me.count is 0
+    Task increment:
+        me.count is me.count + 1
+c is new Counter
+c.increment()
+c.increment()
+n is c.count";
+        assert_eq!(shown_as(source, "n"), "2");
+    }
+
+    #[test]
+    fn my_reads_and_sets_a_field() {
+        let source = "Object Counter:
+    build defaults count:
+        my count is count
+    Task next:
+        my count is my count + 1
+        output my count
+c is new Counter with 4
+n is c.next()
+stored is c.count";
+        assert_eq!(shown_as(source, "n"), "5");
+        assert_eq!(shown_as(source, "stored"), "5");
+    }
+
+    #[test]
+    fn instances_show_their_class_and_fields() {
+        let source = "Object Point:\n    build defaults x, y:\n        me.x is x\n        me.y is y\np is new Point with 1, 2";
+        assert_eq!(shown_as(source, "p"), "Point {x: 1, y: 2}");
+        assert_eq!(shown_as("Object Empty:\ne is new Empty", "e"), "Empty {}");
+    }
+
+    #[test]
+    fn interpolation_reads_instance_fields() {
+        let source = "Object User:\n    build defaults name:\n        me.name is name\nuser is new User with \"Ada\"\nn is 2\ngreeting is \"Hi {user.name}, {n * { n + 1 }}!\"";
+        assert_eq!(shown_as(source, "greeting"), "Hi Ada, 6!");
+    }
 
-fn main() -> Result<(), String> {
-    let mut runtime = Runtime::new();
-    runtime.run_repl()
+    fn construct(declaration: &str, new: &str) -> Result<(), NairError> {
+        Runtime::new().process_input(&format!("{}\n{}", declaration, new))
+    }
+
+    #[test]
+    fn constructor_takes_its_declared_arguments() {
+        let point = "Object Point:\n    build defaults x, y:\n        me.x is x\n        me.y is y";
+        assert!(construct(point, "p is new Point with 1, 2").is_ok());
+
+        let err = construct(point, "p is new Point with 1").unwrap_err().to_string();
+        assert_eq!(err, "Constructor for Point expects 2 arguments, got 1");
+        let err = construct(point, "p is new Point with 1, 2, 3").unwrap_err().to_string();
+        assert_eq!(err, "Constructor for Point expects 2 arguments, got 3");
+    }
+
+    #[test]
+    fn constructor_without_parameters_takes_no_arguments() {
+        let origin = "Object Origin:\n    build:\n        me.x is 0";
+        assert!(construct(origin, "o is new Origin").is_ok());
+        let err = construct(origin, "o is new Origin with 5").unwrap_err().to_string();
+        assert_eq!(err, "Constructor for Origin expects 0 arguments, got 1");
+    }
+
+    #[test]
+    fn literals_evaluate_to_their_values() {
+        assert_eq!(shown_as("x is true", "x"), "true");
+        assert_eq!(shown_as("x is [1, 2 + 1]", "x"), "[1, 3]");
+        assert_eq!(shown_as("x is Promise[Whole]", "x"), "[promise Whole]");
+    }
+
+    #[test]
+    fn text_index_gives_one_character() {
+        assert_eq!(shown_as("text is \"hello\"\nc is text[1]", "c"), "e");
+    }
+
+    #[test]
+    fn pretty_puts_each_element_on_its_own_line() {
+        let mut runtime = Runtime::new();
+        runtime.variables.insert("m".to_string(), Value::Mapping(vec![
+            ("name".to_string(), Value::String("Ada".to_string())),
+            ("scores".to_string(), Value::List(vec![Value::Number(1.0), Value::Number(2.5)])),
+            ("tags".to_string(), Value::List(Vec::new())),
+        ]));
+        runtime.process_input("s is pretty(m)\nplain is pretty(3)").unwrap();
+        assert_eq!(runtime.variables["s"].to_string(), "{\n    name: Ada,\n    scores: [\n        1,\n        2.5\n    ],\n    tags: []\n}");
+        assert_eq!(runtime.variables["plain"].to_string(), "3");
+    }
+
+    #[test]
+    fn empty_tasks_and_objects_run() {
+        let source = "Task noop:\nObject Empty:\nresult is noop()\ne is new Empty";
+        let mut runtime = Runtime::new();
+        runtime.process_input(source).unwrap();
+        assert_eq!(runtime.variables["result"], Value::Nothing);
+        assert_eq!(runtime.variables["e"].to_string(), "Empty {}");
+    }
+
+    #[test]
+    fn tasks_without_output_give_nothing() {
+        let source = "Task noop:\n    x is 1\nr is noop()\nt is typeof(r)\nempty as Nothing\nmissing as Text";
+        assert_eq!(shown_as(source, "r"), "nothing");
+        assert_eq!(shown_as(source, "t"), "Nothing");
+        assert_eq!(shown_as(source, "empty"), "nothing");
+        // null is a missing value, which is not the same thing
+        assert_eq!(shown_as(source, "missing"), "null");
+        assert_eq!(shown_as(&format!("{}\nsame is r is missing", source), "same"), "false");
+        assert_eq!(shown_as(&format!("{}\nfilled is r otherwise 3", source), "filled"), "3");
+    }
+
+    #[test]
+    fn nothing_holds_no_real_value() {
+        let mut runtime = Runtime::new();
+        runtime.process_input("empty as Nothing\nTask noop:\n    x is 1").unwrap();
+        let err = runtime.process_input("empty becomes 5").unwrap_err();
+        assert_eq!(err.to_string(), "Type mismatch: cannot assign Whole to variable of type Nothing");
+        // and the VM keeps what a Task gave back out of typed variables
+        let code = vec![
+            OpCode::Call("noop".to_string(), 0),
+            OpCode::StoreVar { name: "n".to_string(), declared_type: Some("Whole".to_string()) },
+        ];
+        assert_eq!(runtime.run(code, &[]).unwrap_err(), "Type mismatch: cannot assign Nothing to variable of type Whole");
+        let err = runtime.process_input("Task loud returns Nothing:\n    output 5").unwrap_err();
+        assert!(err.to_string().contains("expected Nothing, got Whole"), "{}", err);
+    }
+
+    #[test]
+    fn doc_gives_back_the_about_text() {
+        let mut runtime = Runtime::new();
+        runtime.process_input("Task greet:\n    about \"Says hello\"\n    show \"hello\"\nTask quiet:\n    show 1").unwrap();
+        runtime.process_input("a is doc(\"greet\")\nb is doc(\"quiet\")").unwrap();
+        assert_eq!(runtime.variables["a"].to_string(), "Says hello");
+        assert_eq!(runtime.variables["b"], Value::Null);
+        let err = runtime.process_input("c is doc(\"missing\")").unwrap_err().to_string();
+        assert_eq!(err, "doc: no Task or Object named missing");
+    }
+
+    #[test]
+    fn assert_fails_only_when_false() {
+        assert_eq!(shown_as("x is 2\nassert x > 1\nassert x is 2, \"x should be 2\"\ny is 1", "y"), "1");
+        let err = Runtime::new().process_input("x is 2\nassert x > 5").unwrap_err().to_string();
+        assert_eq!(err, "Assertion failed");
+        let err = Runtime::new().process_input("x is 2\nassert x is 3, \"x is {x}, not 3\"").unwrap_err().to_string();
+        assert_eq!(err, "x is 2, not 3");
+        assert!(Runtime::new().process_input("assert 1").is_err());
+    }
+
+    #[test]
+    fn raise_stops_with_its_message() {
+        let err = Runtime::new().process_input("x is 1\nwhen x is 1:\n    raise \"boom {x}\" as Error\nx is 2").unwrap_err().to_string();
+        assert_eq!(err, "boom 1");
+    }
+
+    #[test]
+    fn casts_reach_into_lists_and_mappings() {
+        assert_eq!(shown_as("xs is [1, 2, 3] as List[Text]\nfirst is xs[0] + \"!\"", "first"), "1!");
+        assert_eq!(shown_as("xs is split(\"4 5\") as List[Whole]\ntotal is xs[0] + xs[1]", "total"), "9");
+        let mut runtime = Runtime::new();
+        runtime.variables.insert("m".to_string(), Value::Mapping(vec![("a".to_string(), Value::String("2.5".to_string()))]));
+        runtime.process_input("n is m as Mapping of Decimal").unwrap();
+        assert_eq!(runtime.variables["n"], Value::Mapping(vec![("a".to_string(), Value::Number(2.5))]));
+
+        let err = Runtime::new().process_input("xs is [\"1\", \"two\"] as List[Whole]").unwrap_err().to_string();
+        assert_eq!(err, "Cannot cast \"two\" to Whole");
+    }
+
+    #[test]
+    fn at_gives_a_list_element() {
+        assert_eq!(shown_as("xs is [10, 20, 30]\nx is xs at 1", "x"), "20");
+        assert_eq!(shown_as("xs is [10, 20, 30]\nx is item at 2 of xs", "x"), "30");
+        assert_eq!(shown_as("xs is [10, 20, 30]\nx is xs[0]", "x"), "10");
+        let err = Runtime::new().process_input("xs is [1]\nx is xs at 3").unwrap_err().to_string();
+        assert_eq!(err, "Index 3 is out of range for a list of length 1");
+    }
+
+    #[test]
+    fn text_range_leaves_out_its_end() {
+        assert_eq!(shown_as("text is \"hello\"\ns is text[1..3]", "s"), "el");
+        assert_eq!(shown_as("text is \"hello\"\ns is text[0..5]", "s"), "hello");
+    }
+
+    #[test]
+    fn text_access_past_the_end_is_an_error() {
+        let err = Runtime::new().process_input("text is \"hello\"\nc is text[5]").unwrap_err().to_string();
+        assert_eq!(err, "Index 5 is out of range for text of length 5");
+        let err = Runtime::new().process_input("text is \"hello\"\ns is text[3..9]").unwrap_err().to_string();
+        assert_eq!(err, "Range 3..9 is out of range for text of length 5");
+    }
+
+    #[test]
+    fn plus_joins_text() {
+        assert_eq!(shown_as("s is \"a\" + \"b\"", "s"), "ab");
+    }
+
+    #[test]
+    fn plus_between_text_and_a_number_is_an_error() {
+        // Untyped to the analyzer once carried over, so the VM has to catch it
+        let mut runtime = Runtime::new();
+        runtime.process_input("s is \"a\"").unwrap();
+        let err = runtime.process_input("t is s + 1").unwrap_err().to_string();
+        assert_eq!(err, "Cannot add Text and Whole");
+    }
 }
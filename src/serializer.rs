@@ -0,0 +1,239 @@
+use crate::generator::{ObjectDef, OpCode, TaskDef, Value};
+use crate::json::{from_json, to_json};
+use crate::runtime::Program;
+
+// Bumped whenever the layout below changes, so stale files are refused
+const VERSION: f64 = 2.0;
+
+// Instructions that carry nothing are written by name alone
+const PLAIN: [OpCode; 28] = [
+    OpCode::Pop, OpCode::Duplicate, OpCode::AppendItem, OpCode::FinishMap, OpCode::ItemIndex,
+    OpCode::Add, OpCode::Subtract, OpCode::Multiply, OpCode::Divide, OpCode::Modulo, OpCode::Power,
+    OpCode::FloorDivide, OpCode::Negate, OpCode::Not,
+    OpCode::Equal, OpCode::NotEqual, OpCode::Greater, OpCode::GreaterEqual, OpCode::Less, OpCode::LessEqual,
+    OpCode::Contains, OpCode::Return, OpCode::Raise,
+    OpCode::Index, OpCode::Slice, OpCode::Concat, OpCode::ConvertToString, OpCode::Emit,
+];
+
+// A compiled program as JSON: each instruction is a list of its name and
+// operands, and Tasks and Objects are mappings of their parts
+pub fn encode(program: &Program) -> Result<String, String> {
+    to_json(&Value::Mapping(vec![
+        ("version".to_string(), Value::Number(VERSION)),
+        ("constants".to_string(), Value::List(program.constants.clone())),
+        ("lines".to_string(), Value::List(program.lines.iter().map(|&line| whole(line)).collect())),
+        ("code".to_string(), encode_code(&program.code)),
+    ]))
+}
+
+pub fn decode(text: &str) -> Result<Program, String> {
+    let program = from_json(text)?;
+    if field(&program, "version")? != &Value::Number(VERSION) {
+        return Err("the file was compiled by a different version of nair".to_string());
+    }
+    let constants = list(field(&program, "constants")?)?.to_vec();
+    let lines = list(field(&program, "lines")?)?.iter().map(number).collect::<Result<_, _>>()?;
+    let code = decode_code(field(&program, "code")?)?;
+    Ok(Program { code, lines, constants })
+}
+
+fn whole(n: usize) -> Value {
+    Value::Number(n as f64)
+}
+
+fn text(s: &str) -> Value {
+    Value::String(s.to_string())
+}
+
+fn optional_text(s: &Option<String>) -> Value {
+    s.as_deref().map_or(Value::Null, text)
+}
+
+fn encode_code(code: &[OpCode]) -> Value {
+    Value::List(code.iter().map(encode_op).collect())
+}
+
+fn encode_op(op: &OpCode) -> Value {
+    let name = format!("{:?}", op);
+    let name = text(name.split('(').next().unwrap_or_default().split(' ').next().unwrap_or_default());
+    let operands = match op {
+        // JSON has no word for nothing, so that Push is written without a value
+        OpCode::Push(Value::Nothing) => Vec::new(),
+        OpCode::Push(value) => vec![value.clone()],
+        OpCode::LoadConst(n) | OpCode::BuildList(n) | OpCode::NextItem(n) | OpCode::Jump(n)
+        | OpCode::JumpIfFalse(n) | OpCode::JumpIfNotNull(n) | OpCode::Interpolate(n) | OpCode::Show(n) => vec![whole(*n)],
+        OpCode::LoadVar(s) | OpCode::GetProperty(s) | OpCode::SetProperty(s) | OpCode::Cast(s)
+        | OpCode::Bind(s) | OpCode::Borrow(s) | OpCode::Unbind(s) => {
+            vec![text(s)]
+        },
+        OpCode::StoreVar { name, declared_type } => vec![text(name), optional_text(declared_type)],
+        OpCode::BuildRecord(names) => vec![Value::List(names.iter().map(|name| text(name)).collect())],
+        OpCode::Call(s, n) | OpCode::CallMethod(s, n) | OpCode::NewObject(s, n) | OpCode::BuildBase(s, n) => {
+            vec![text(s), whole(*n)]
+        },
+        OpCode::DefineTask(task) => vec![encode_task(task)],
+        OpCode::DefineObject(object) => vec![Value::Mapping(vec![
+            ("name".to_string(), text(&object.name)),
+            ("base".to_string(), optional_text(&object.base)),
+            ("constructor".to_string(), object.constructor.as_ref().map_or(Value::Null, encode_task)),
+            ("methods".to_string(), Value::List(object.methods.iter().map(encode_task).collect())),
+            ("doc".to_string(), optional_text(&object.doc)),
+        ])],
+        _ => Vec::new(),
+    };
+    Value::List(std::iter::once(name).chain(operands).collect())
+}
+
+fn encode_task(task: &TaskDef) -> Value {
+    Value::Mapping(vec![
+        ("name".to_string(), text(&task.name)),
+        ("params".to_string(), Value::List(task.params.iter().map(|p| text(p)).collect())),
+        ("variadic".to_string(), Value::Boolean(task.variadic)),
+        ("code".to_string(), encode_code(&task.code)),
+        ("lines".to_string(), Value::List(task.lines.iter().map(|&line| whole(line)).collect())),
+        ("doc".to_string(), optional_text(&task.doc)),
+    ])
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Result<&'a Value, String> {
+    match value {
+        Value::Mapping(entries) => entries.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+            .ok_or_else(|| format!("missing '{}'", name)),
+        other => Err(format!("expected a mapping, got {}", other)),
+    }
+}
+
+fn list(value: &Value) -> Result<&[Value], String> {
+    match value {
+        Value::List(items) => Ok(items),
+        other => Err(format!("expected a list, got {}", other)),
+    }
+}
+
+fn number(value: &Value) -> Result<usize, String> {
+    match value {
+        Value::Number(n) if *n >= 0.0 => Ok(*n as usize),
+        other => Err(format!("expected a count, got {}", other)),
+    }
+}
+
+fn string(value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        other => Err(format!("expected text, got {}", other)),
+    }
+}
+
+fn optional_string(value: &Value) -> Result<Option<String>, String> {
+    match value {
+        Value::Null => Ok(None),
+        other => string(other).map(Some),
+    }
+}
+
+fn decode_code(value: &Value) -> Result<Vec<OpCode>, String> {
+    list(value)?.iter().map(decode_op).collect()
+}
+
+fn decode_op(value: &Value) -> Result<OpCode, String> {
+    let [name, operands @ ..] = list(value)? else {
+        return Err("expected an instruction, got an empty list".to_string());
+    };
+    let name = string(name)?;
+    let operand = |i: usize| operands.get(i).ok_or_else(|| format!("{} is missing an operand", name));
+    Ok(match name.as_str() {
+        "Push" => OpCode::Push(operands.first().cloned().unwrap_or(Value::Nothing)),
+        "LoadConst" => OpCode::LoadConst(number(operand(0)?)?),
+        "BuildList" => OpCode::BuildList(number(operand(0)?)?),
+        "NextItem" => OpCode::NextItem(number(operand(0)?)?),
+        "Jump" => OpCode::Jump(number(operand(0)?)?),
+        "JumpIfFalse" => OpCode::JumpIfFalse(number(operand(0)?)?),
+        "JumpIfNotNull" => OpCode::JumpIfNotNull(number(operand(0)?)?),
+        "Interpolate" => OpCode::Interpolate(number(operand(0)?)?),
+        "Show" => OpCode::Show(number(operand(0)?)?),
+        "LoadVar" => OpCode::LoadVar(string(operand(0)?)?),
+        "GetProperty" => OpCode::GetProperty(string(operand(0)?)?),
+        "SetProperty" => OpCode::SetProperty(string(operand(0)?)?),
+        "Bind" => OpCode::Bind(string(operand(0)?)?),
+        "Borrow" => OpCode::Borrow(string(operand(0)?)?),
+        "Unbind" => OpCode::Unbind(string(operand(0)?)?),
+        "Cast" => OpCode::Cast(string(operand(0)?)?),
+        "StoreVar" => OpCode::StoreVar { name: string(operand(0)?)?, declared_type: optional_string(operand(1)?)? },
+        "BuildRecord" => OpCode::BuildRecord(list(operand(0)?)?.iter().map(string).collect::<Result<_, _>>()?),
+        "Call" => OpCode::Call(string(operand(0)?)?, number(operand(1)?)?),
+        "CallMethod" => OpCode::CallMethod(string(operand(0)?)?, number(operand(1)?)?),
+        "NewObject" => OpCode::NewObject(string(operand(0)?)?, number(operand(1)?)?),
+        "BuildBase" => OpCode::BuildBase(string(operand(0)?)?, number(operand(1)?)?),
+        "DefineTask" => OpCode::DefineTask(decode_task(operand(0)?)?),
+        "DefineObject" => {
+            let object = operand(0)?;
+            let constructor = match field(object, "constructor")? {
+                Value::Null => None,
+                task => Some(decode_task(task)?),
+            };
+            OpCode::DefineObject(ObjectDef {
+                name: string(field(object, "name")?)?,
+                base: optional_string(field(object, "base")?)?,
+                constructor,
+                methods: list(field(object, "methods")?)?.iter().map(decode_task).collect::<Result<_, _>>()?,
+                doc: optional_string(field(object, "doc")?)?,
+            })
+        },
+        _ => PLAIN.iter()
+            .find(|op| format!("{:?}", op) == name)
+            .cloned()
+            .ok_or_else(|| format!("unknown instruction {}", name))?,
+    })
+}
+
+fn decode_task(value: &Value) -> Result<TaskDef, String> {
+    Ok(TaskDef {
+        name: string(field(value, "name")?)?,
+        params: list(field(value, "params")?)?.iter().map(string).collect::<Result<_, _>>()?,
+        variadic: matches!(field(value, "variadic")?, Value::Boolean(true)),
+        code: decode_code(field(value, "code")?)?,
+        lines: list(field(value, "lines")?)?.iter().map(number).collect::<Result<_, _>>()?,
+        doc: optional_string(field(value, "doc")?)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(code: Vec<OpCode>) -> Vec<OpCode> {
+        let program = Program { code, lines: Vec::new(), constants: Vec::new() };
+        decode(&encode(&program).unwrap()).unwrap().code
+    }
+
+    #[test]
+    fn every_plain_instruction_reads_back() {
+        let code = round_trip(PLAIN.to_vec());
+        assert_eq!(format!("{:?}", code), format!("{:?}", PLAIN));
+    }
+
+    #[test]
+    fn operands_read_back() {
+        let code = vec![
+            OpCode::Push(Value::Decimal(4.0)),
+            OpCode::Push(Value::Nothing),
+            OpCode::Push(Value::Null),
+            OpCode::StoreVar { name: "x".to_string(), declared_type: Some("Decimal".to_string()) },
+            OpCode::StoreVar { name: "y".to_string(), declared_type: None },
+            OpCode::CallMethod("upper".to_string(), 0),
+            OpCode::BuildBase("Animal".to_string(), 2),
+            OpCode::BuildRecord(vec!["name".to_string(), "age".to_string()]),
+            OpCode::JumpIfNotNull(7),
+        ];
+        assert_eq!(format!("{:?}", round_trip(code.clone())), format!("{:?}", code));
+    }
+
+    #[test]
+    fn promises_cannot_be_saved() {
+        let program = Program { code: vec![OpCode::Push(Value::Promise("Whole".to_string()))], lines: Vec::new(), constants: Vec::new() };
+        assert!(encode(&program).is_err());
+        assert_eq!(decode("[]").err().unwrap(), "expected a mapping, got []");
+    }
+}
@@ -1,3 +1,5 @@
+use crate::error::NairError;
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
@@ -6,7 +8,9 @@ pub struct Token {
     pub column: usize,
 }
 
+// Some tokens are declared ahead of the scanning that produces them
 #[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::upper_case_acronyms, dead_code)]
 pub enum TokenType {
     // Keywords
     As,
@@ -15,6 +19,7 @@ pub enum TokenType {
     To,
     When,
     Or,
+    Otherwise,
     Do,
     Fail,
     Always,
@@ -27,16 +32,21 @@ pub enum TokenType {
     Using,
     Loop,
     While,
+    Break,
+    Continue,
     Emit,
     Match,
     Output,
     Raise,
+    Assert,
     Show,
     Await,
     At,
     And,
+    Not,
     Each,
     Becomes,
+    Fixed,
     My,
     About,
     Me,
@@ -70,6 +80,7 @@ pub enum TokenType {
     Colon,
     Comma,
     Dot,
+    DotDot,  // the `..` in `text[1..3]`
     OpenBracket,
     CloseBracket,
     OpenParen,
@@ -96,12 +107,13 @@ pub enum TokenType {
 
     EOF,
     NewLine,
+    Indent,  // a line starts further in than the one before, opening a block
+    Dedent,  // a line starts further out, closing one block per token
 
     Includes,  // Add this new token
+    Contains,  // `xs contains x`: membership in a list, key in a mapping, or text in text
     LeftBrace,
     RightBrace,
-    Quote,
-    StringPart(String),
 }
 
 pub struct Tokenizer {
@@ -110,6 +122,7 @@ pub struct Tokenizer {
     start: usize,
     line: usize,
     column: usize,
+    pub case_insensitive_keywords: bool,  // `emit`, `EMIT` and `Emit` all mean Emit; type names still need their case
 }
 
 impl Tokenizer {
@@ -120,17 +133,39 @@ impl Tokenizer {
             start: 0,
             line: 1,
             column: 1,
+            case_insensitive_keywords: false,
         }
     }
 
-    pub fn tokenize(&mut self) -> Result<Vec<Token>, String> {
+    pub fn tokenize(&mut self) -> Result<Vec<Token>, NairError> {
         let mut tokens = Vec::new();
+        let mut indents = Vec::new();
+        let mut line_start = true;
 
         while !self.is_at_end() {
-            self.start = self.current;
-            if let Ok(token) = self.scan_token() {
-                tokens.push(token);
+            if line_start {
+                line_start = false;
+                self.indentation(&mut indents, &mut tokens).map_err(|msg| NairError::Lex {
+                    msg,
+                    line: self.line,
+                    col: self.column,
+                })?;
+                continue;
             }
+
+            self.start = self.current;
+            let token = self.scan_token().map_err(|msg| NairError::Lex {
+                msg,
+                line: self.line,
+                col: self.column,
+            })?;
+            line_start = token.token_type == TokenType::NewLine;
+            tokens.push(token);
+        }
+
+        // Close the blocks still open at the end of the input
+        for _ in indents.iter().skip(1) {
+            tokens.push(self.layout_token(TokenType::Dedent));
         }
 
         tokens.push(Token {
@@ -143,6 +178,48 @@ impl Tokenizer {
         Ok(tokens)
     }
 
+    // Reads a line's leading whitespace, one column per space or tab, and emits an
+    // Indent or Dedents against the enclosing lines. The first line sets the base level
+    fn indentation(&mut self, indents: &mut Vec<usize>, tokens: &mut Vec<Token>) -> Result<(), String> {
+        let mut width = 0;
+        while matches!(self.peek(), ' ' | '\t') {
+            self.advance();
+            width += 1;
+        }
+
+        // Blank lines neither open nor close a block
+        if self.is_at_end() || matches!(self.peek(), '\n' | '\r') {
+            return Ok(());
+        }
+
+        let Some(&current) = indents.last() else {
+            indents.push(width);
+            return Ok(());
+        };
+        if width > current {
+            indents.push(width);
+            tokens.push(self.layout_token(TokenType::Indent));
+            return Ok(());
+        }
+        while indents.last().is_some_and(|&level| width < level) {
+            indents.pop();
+            tokens.push(self.layout_token(TokenType::Dedent));
+        }
+        if indents.last() != Some(&width) {
+            return Err(format!("Inconsistent indentation on line {}", self.line));
+        }
+        Ok(())
+    }
+
+    fn layout_token(&self, token_type: TokenType) -> Token {
+        Token {
+            token_type,
+            literal: String::new(),
+            line: self.line,
+            column: self.column,
+        }
+    }
+
     // Helper methods
     fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
@@ -171,6 +248,8 @@ impl Tokenizer {
         }
     }
 
+    // Newlines end statements, so they are left for scan_token to turn into
+    // NewLine tokens, which also moves on to the next line
     fn skip_whitespace(&mut self) {
         while !self.is_at_end() {
             match self.peek() {
@@ -191,33 +270,6 @@ impl Tokenizer {
         }
     }
 
-    fn number_token(&mut self) -> Result<Token, String> {
-        
-        while self.peek().is_ascii_digit() {
-            self.advance();
-        }
-
-        // Look for a decimal part
-        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
-            self.advance(); // Consume the "."
-
-            while self.peek().is_ascii_digit() {
-                self.advance();
-            }
-        }
-
-        let number_str: String = self.source[self.start..self.current].iter().collect();
-        match number_str.parse::<f64>() {
-            Ok(number) => Ok(Token {
-                token_type: TokenType::Number(number),
-                literal: number_str,
-                line: self.line,
-                column: self.column,
-            }),
-            Err(_) => Err("Invalid number".to_string()),
-        }
-    }
-
     fn scan_token(&mut self) -> Result<Token, String> {
         self.skip_whitespace();
         self.start = self.current;
@@ -228,6 +280,12 @@ impl Tokenizer {
 
         let c = self.advance();
         match c {
+            '\n' => {
+                let token = self.create_token(TokenType::NewLine);
+                self.line += 1;
+                self.column = 1;
+                Ok(token)
+            },
             '"' => self.string(),
             '{' => Ok(self.create_token(TokenType::LeftBrace)),
             '}' => Ok(self.create_token(TokenType::RightBrace)),
@@ -237,12 +295,39 @@ impl Tokenizer {
             ']' => Ok(self.create_token(TokenType::CloseBracket)),
             ':' => Ok(self.create_token(TokenType::Colon)),
             ',' => Ok(self.create_token(TokenType::Comma)),
+            '.' if self.peek() == '.' => {
+                self.advance();
+                Ok(self.create_token(TokenType::DotDot))
+            },
             '.' => Ok(self.create_token(TokenType::Dot)),
             '+' => Ok(self.create_token(TokenType::Plus)),
             '-' => Ok(self.create_token(TokenType::Minus)),
+            '*' if self.peek() == '*' => {
+                self.advance();
+                Ok(self.create_token(TokenType::Power))
+            },
             '*' => Ok(self.create_token(TokenType::Multiply)),
             '/' => Ok(self.create_token(TokenType::Divide)),
+            '%' => Ok(self.create_token(TokenType::Modulo)),
+            '>' if self.peek() == '=' => {
+                self.advance();
+                Ok(self.create_token(TokenType::GreaterThanOrEqual))
+            },
             '>' => Ok(self.create_token(TokenType::GreaterThan)),
+            '<' if self.peek() == '=' => {
+                self.advance();
+                Ok(self.create_token(TokenType::LessThanOrEqual))
+            },
+            '<' => Ok(self.create_token(TokenType::LessThan)),
+            // `=` and `!` only come in pairs; `is` and `is not` are the words for them
+            '=' if self.peek() == '=' => {
+                self.advance();
+                Ok(self.create_token(TokenType::Equals))
+            },
+            '!' if self.peek() == '=' => {
+                self.advance();
+                Ok(self.create_token(TokenType::NotEquals))
+            },
             '0'..='9' => self.number(),
             _ => {
                 if c.is_alphabetic() || c == '_' {
@@ -256,20 +341,10 @@ impl Tokenizer {
     }
 
     fn string(&mut self) -> Result<Token, String> {
+        // Braces stay in the text; the parser picks out interpolations
         let mut string = String::new();
         
         while !self.is_at_end() && self.peek() != '"' {
-            if self.peek() == '{' {
-                if !string.is_empty() {
-                    return Ok(Token {
-                        token_type: TokenType::StringPart(string.clone()),
-                        literal: string,
-                        line: self.line,
-                        column: self.column,
-                    });
-                }
-                return Ok(self.create_token(TokenType::LeftBrace));
-            }
             string.push(self.advance());
         }
 
@@ -288,13 +363,51 @@ impl Tokenizer {
         })
     }
 
-    fn identifier_token(&mut self) -> Result<Token, String> {
+    fn read_identifier(&mut self) -> String {
+        let start = self.start;
         while !self.is_at_end() && (self.peek().is_alphanumeric() || self.peek() == '_') {
             self.advance();
         }
+        self.source[start..self.current].iter().collect()
+    }
+
+    fn create_identifier_token(&self, text: String) -> Token {
+        let token_type = Self::keyword(&text)
+            .or_else(|| self.keyword_ignoring_case(&text))
+            .unwrap_or_else(|| TokenType::Identifier(text.clone()));
+
+        Token {
+            token_type,
+            literal: text,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    // Keywords are spelled either all lowercase or capitalized, so those are the
+    // two spellings to try; a type name only counts when written exactly
+    fn keyword_ignoring_case(&self, text: &str) -> Option<TokenType> {
+        if !self.case_insensitive_keywords {
+            return None;
+        }
+        let lower = text.to_lowercase();
+        let mut chars = lower.chars();
+        let capitalized = chars.next().map(|c| c.to_uppercase().chain(chars).collect::<String>())?;
+        [lower, capitalized].iter()
+            .filter_map(|spelling| Self::keyword(spelling))
+            .find(|token_type| !Self::is_type_name(token_type))
+    }
 
-        let text: String = self.source[self.start..self.current].iter().collect();
-        let token_type = match text.as_str() {
+    fn is_type_name(token_type: &TokenType) -> bool {
+        matches!(token_type,
+            TokenType::TypeWhole | TokenType::TypeDecimal | TokenType::TypeText | TokenType::TypeLogic
+            | TokenType::TypeNothing | TokenType::TypeList | TokenType::TypeMapping | TokenType::TypePromise
+            | TokenType::TypeAny | TokenType::TypeNumber | TokenType::TypeError)
+    }
+
+    // The one keyword table; anything not in it is an identifier
+    fn keyword(text: &str) -> Option<TokenType> {
+        let token_type = match text {
             // Keywords
             "as" => TokenType::As,
             "is" => TokenType::Is,
@@ -302,10 +415,13 @@ impl Tokenizer {
             "to" => TokenType::To,
             "when" => TokenType::When,
             "or" => TokenType::Or,
+            "otherwise" => TokenType::Otherwise,
             "do" => TokenType::Do,
             "fail" => TokenType::Fail,
             "always" => TokenType::Always,
             "inherits" => TokenType::Extends,
+            "includes" => TokenType::Includes,
+            "contains" => TokenType::Contains,
             "returns" => TokenType::Returns,
             "requires" => TokenType::Requires,
             "returning" => TokenType::Returning,
@@ -314,16 +430,21 @@ impl Tokenizer {
             "using" => TokenType::Using,
             "loop" => TokenType::Loop,
             "while" => TokenType::While,
+            "break" => TokenType::Break,
+            "continue" => TokenType::Continue,
             "Emit" => TokenType::Emit,
             "match" => TokenType::Match,
             "output" => TokenType::Output,
             "raise" => TokenType::Raise,
+            "assert" => TokenType::Assert,
             "show" => TokenType::Show,
             "await" => TokenType::Await,
             "at" => TokenType::At,
             "and" => TokenType::And,
+            "not" => TokenType::Not,
             "each" => TokenType::Each,
             "becomes" => TokenType::Becomes,
+            "fixed" => TokenType::Fixed,
             "my" => TokenType::My,
             "about" => TokenType::About,
             "me" => TokenType::Me,
@@ -338,7 +459,7 @@ impl Tokenizer {
             "Whole" => TokenType::TypeWhole,
             "Decimal" => TokenType::TypeDecimal,
             "Text" => TokenType::TypeText,
-            "Logic" => TokenType::TypeLogic,
+            "Truth" | "Logic" => TokenType::TypeLogic,
             "Nothing" => TokenType::TypeNothing,
             "List" => TokenType::TypeList,
             "Mapping" => TokenType::TypeMapping,
@@ -347,118 +468,61 @@ impl Tokenizer {
             "Number" => TokenType::TypeNumber,
             "Error" => TokenType::TypeError,
 
-            // Boolean literals
+            // Literals
             "true" => TokenType::Boolean(true),
             "false" => TokenType::Boolean(false),
             "null" => TokenType::Null,
 
-            // Default to identifier
-            _ => TokenType::Identifier(text.clone()),
+            _ => return None,
         };
-
-        Ok(Token {
-            token_type,
-            literal: text,
-            line: self.line,
-            column: self.column,
-        })
+        Some(token_type)
     }
 
-    fn identifier_type(&self, text: String) -> Result<Token, String> {
-        println!("Processing identifier: {}", text);
-        let token_type = match text.as_str() {
-            "Mapping" => {
-                println!("Found Mapping keyword");
-                TokenType::TypeMapping
-            },
-            "Text" => {
-                println!("Found Text keyword");
-                TokenType::TypeText
-            },
-            "of" => TokenType::Of,
-            "to" => TokenType::To,
-            "includes" => TokenType::Includes,
-            _ => {
-                println!("Unknown identifier: {}", text);
-                TokenType::Identifier(text.clone())
-            },
-        };
+    fn number(&mut self) -> Result<Token, String> {
+        // Hexadecimal (0xFF) and binary (0b1010) integers
+        if self.source[self.start] == '0' && matches!(self.peek(), 'x' | 'X' | 'b' | 'B') {
+            let radix = if matches!(self.advance(), 'x' | 'X') { 16 } else { 2 };
+            while self.peek().is_ascii_alphanumeric() {
+                self.advance();
+            }
 
-        Ok(Token {
-            token_type,
-            literal: text,
-            line: self.line,
-            column: self.column,
-        })
-    }
+            let number_str: String = self.source[self.start..self.current].iter().collect();
+            return match i64::from_str_radix(&number_str[2..], radix) {
+                Ok(number) => Ok(Token {
+                    token_type: TokenType::Number(number as f64),
+                    literal: number_str,
+                    line: self.line,
+                    column: self.column,
+                }),
+                Err(_) => Err(format!("Invalid base-{} number: {}", radix, number_str)),
+            };
+        }
 
-    fn read_identifier(&mut self) -> String {
-        let start = self.start;
-        while !self.is_at_end() && (self.peek().is_alphanumeric() || self.peek() == '_') {
+        while !self.is_at_end() && self.peek().is_ascii_digit() {
             self.advance();
         }
-        self.source[start..self.current].iter().collect()
-    }
-
-    fn create_identifier_token(&self, text: String) -> Token {
-        let token_type = match text.as_str() {
-            "is" => TokenType::Is,
-            "as" => TokenType::As,
-            "Mapping" => TokenType::TypeMapping,
-            "Text" => TokenType::TypeText,
-            "includes" => TokenType::Includes,
-            "Object" => TokenType::Object,
-            "Task" => TokenType::Task,
-            "build" => TokenType::Build,
-            "defaults" => TokenType::Defaults,
-            "of" => TokenType::Of,
-            "to" => TokenType::To,
-            // "includes" => TokenType::Includes,
-            "show" => TokenType::Show,
-            "raise" => TokenType::Raise,
-            "await" => TokenType::Await,
-            "at" => TokenType::At,
-            "and" => TokenType::And,
-            "each" => TokenType::Each,
-            "becomes" => TokenType::Becomes,
-            "my" => TokenType::My,
-            "about" => TokenType::About,
-            "me" => TokenType::Me,
-            "loop" => TokenType::Loop,
-            "while" => TokenType::While,
-            "Emit" => TokenType::Emit,
-            "match" => TokenType::Match,
-            "output" => TokenType::Output,
-            "returns" => TokenType::Returns,
-            "requires" => TokenType::Requires,
-            "returning" => TokenType::Returning,
-            "new" => TokenType::New,
-            "with" => TokenType::With,
-            "using" => TokenType::Using,
-            _ => TokenType::Identifier(text.clone()),
-        };
 
-        Token {
-            token_type,
-            literal: text,
-            line: self.line,
-            column: self.column,
-        }
-    }
+        // Look for a decimal point; `1..3` is a range, not 1.0 followed by .3
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
+            self.advance();  // Consume the dot
 
-    fn number(&mut self) -> Result<Token, String> {
-        let mut is_decimal = false;
-        
-        while !self.is_at_end() && self.peek().is_digit(10) {
-            self.advance();
+            while !self.is_at_end() && self.peek().is_ascii_digit() {
+                self.advance();
+            }
         }
 
-        // Look for a decimal point
-        if !self.is_at_end() && self.peek() == '.' {
-            is_decimal = true;
-            self.advance();  // Consume the dot
+        // Look for an exponent, e.g. 1e10 or 2.5e-3
+        if matches!(self.peek(), 'e' | 'E') {
+            self.advance();  // Consume the 'e'
+            if matches!(self.peek(), '+' | '-') {
+                self.advance();
+            }
 
-            while !self.is_at_end() && self.peek().is_digit(10) {
+            if !self.peek().is_ascii_digit() {
+                let number_str: String = self.source[self.start..self.current].iter().collect();
+                return Err(format!("Missing exponent digits in number: {}", number_str));
+            }
+            while self.peek().is_ascii_digit() {
                 self.advance();
             }
         }
@@ -486,4 +550,169 @@ impl std::fmt::Display for Token {
             self.column
         )
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn types(source: &str) -> Vec<TokenType> {
+        Tokenizer::new(source).tokenize().unwrap().into_iter().map(|token| token.token_type).collect()
+    }
+
+    #[test]
+    fn numbers_take_an_exponent() {
+        assert_eq!(types("1e10"), [TokenType::Number(1e10), TokenType::EOF]);
+        assert_eq!(types("2.5e-3"), [TokenType::Number(2.5e-3), TokenType::EOF]);
+        assert_eq!(types("42 4.5"), [TokenType::Number(42.0), TokenType::Number(4.5), TokenType::EOF]);
+    }
+
+    #[test]
+    fn exponent_needs_digits() {
+        assert_eq!(Tokenizer::new("5e").scan_token().unwrap_err(), "Missing exponent digits in number: 5e");
+    }
+
+    #[test]
+    fn hex_and_binary_literals() {
+        assert_eq!(types("0xFF"), [TokenType::Number(255.0), TokenType::EOF]);
+        assert_eq!(types("0b1010"), [TokenType::Number(10.0), TokenType::EOF]);
+        assert_eq!(Tokenizer::new("0xG").scan_token().unwrap_err(), "Invalid base-16 number: 0xG");
+    }
+
+    #[test]
+    fn text_keeps_its_braces() {
+        assert_eq!(types("\"{} and {name}\" x"), [
+            TokenType::String("{} and {name}".to_string()), TokenType::Identifier("x".to_string()), TokenType::EOF,
+        ]);
+    }
+
+    #[test]
+    fn keywords_lex_as_keywords() {
+        assert_eq!(types("Whole when inherits"), [TokenType::TypeWhole, TokenType::When, TokenType::Extends, TokenType::EOF]);
+        assert_eq!(types("do fail always"), [TokenType::Do, TokenType::Fail, TokenType::Always, TokenType::EOF]);
+        assert_eq!(types("whole"), [TokenType::Identifier("whole".to_string()), TokenType::EOF]);
+    }
+
+    #[test]
+    fn type_names_lex_as_types() {
+        let source = "Whole Decimal Text Truth Logic Nothing List Mapping Promise Any Number Error";
+        assert_eq!(types(source), [
+            TokenType::TypeWhole, TokenType::TypeDecimal, TokenType::TypeText, TokenType::TypeLogic, TokenType::TypeLogic,
+            TokenType::TypeNothing, TokenType::TypeList, TokenType::TypeMapping, TokenType::TypePromise, TokenType::TypeAny,
+            TokenType::TypeNumber, TokenType::TypeError, TokenType::EOF,
+        ]);
+    }
+
+    #[test]
+    fn literal_words() {
+        assert_eq!(types("when done is true:"), [
+            TokenType::When, TokenType::Identifier("done".to_string()), TokenType::Is, TokenType::Boolean(true),
+            TokenType::Colon, TokenType::EOF,
+        ]);
+        assert_eq!(types("false"), [TokenType::Boolean(false), TokenType::EOF]);
+        assert_eq!(types("null"), [TokenType::Null, TokenType::EOF]);
+    }
+
+    #[test]
+    fn lines_are_counted_across_newlines() {
+        let tokens = Tokenizer::new("x is 1\r\n\nwhen x:\n    show x\nshow 2").tokenize().unwrap();
+        let lines: Vec<(TokenType, usize)> = tokens.into_iter().map(|token| (token.token_type, token.line)).collect();
+        let x = || TokenType::Identifier("x".to_string());
+        assert_eq!(lines, [
+            (x(), 1), (TokenType::Is, 1), (TokenType::Number(1.0), 1), (TokenType::NewLine, 1),
+            (TokenType::NewLine, 2),
+            (TokenType::When, 3), (x(), 3), (TokenType::Colon, 3), (TokenType::NewLine, 3),
+            (TokenType::Indent, 4), (TokenType::Show, 4), (x(), 4), (TokenType::NewLine, 4),
+            (TokenType::Dedent, 5), (TokenType::Show, 5), (TokenType::Number(2.0), 5), (TokenType::EOF, 5),
+        ]);
+    }
+
+    #[test]
+    fn comparison_symbols() {
+        let x = || TokenType::Identifier("x".to_string());
+        let one = TokenType::Number(1.0);
+        for (source, operator) in [
+            ("x<1", TokenType::LessThan),
+            ("x >= 1", TokenType::GreaterThanOrEqual),
+            ("x<=1", TokenType::LessThanOrEqual),
+            ("x == 1", TokenType::Equals),
+            ("x!=1", TokenType::NotEquals),
+            ("x > 1", TokenType::GreaterThan),
+        ] {
+            assert_eq!(types(source), [x(), operator, one.clone(), TokenType::EOF], "{}", source);
+        }
+        assert_eq!(types("% ** * == != < <= >="), [
+            TokenType::Modulo,
+            TokenType::Power,
+            TokenType::Multiply,
+            TokenType::Equals,
+            TokenType::NotEquals,
+            TokenType::LessThan,
+            TokenType::LessThanOrEqual,
+            TokenType::GreaterThanOrEqual,
+            TokenType::EOF,
+        ]);
+        let mut tokenizer = Tokenizer::new("> =");
+        assert_eq!(tokenizer.scan_token().unwrap().token_type, TokenType::GreaterThan);
+        assert_eq!(tokenizer.scan_token().unwrap_err(), "Unexpected character: =");
+    }
+
+    #[test]
+    fn range_between_numbers() {
+        assert_eq!(types("1..3"), [TokenType::Number(1.0), TokenType::DotDot, TokenType::Number(3.0), TokenType::EOF]);
+    }
+
+    #[test]
+    fn newlines_are_tokens() {
+        let tokens = Tokenizer::new("x\ny").tokenize().unwrap();
+        let types: Vec<_> = tokens.iter().map(|token| token.token_type.clone()).collect();
+        assert_eq!(types, [TokenType::Identifier("x".to_string()), TokenType::NewLine,
+                           TokenType::Identifier("y".to_string()), TokenType::EOF]);
+        assert_eq!(tokens[2].line, 2);
+    }
+
+    #[test]
+    fn indentation_opens_and_closes_blocks() {
+        assert_eq!(types("a:\n    b\n\n        c\nd"), [
+            TokenType::Identifier("a".to_string()), TokenType::Colon, TokenType::NewLine,
+            TokenType::Indent, TokenType::Identifier("b".to_string()), TokenType::NewLine, TokenType::NewLine,
+            TokenType::Indent, TokenType::Identifier("c".to_string()), TokenType::NewLine,
+            TokenType::Dedent, TokenType::Dedent, TokenType::Identifier("d".to_string()), TokenType::EOF,
+        ]);
+    }
+
+    #[test]
+    fn keyword_case_is_optional_when_asked_for() {
+        assert_eq!(types("emit EMIT Emit"), [
+            TokenType::Identifier("emit".to_string()), TokenType::Identifier("EMIT".to_string()),
+            TokenType::Emit, TokenType::EOF,
+        ]);
+
+        let mut tokenizer = Tokenizer::new("emit EMIT Emit SHOW task text");
+        tokenizer.case_insensitive_keywords = true;
+        let types: Vec<_> = tokenizer.tokenize().unwrap().into_iter().map(|token| token.token_type).collect();
+        assert_eq!(types, [
+            TokenType::Emit, TokenType::Emit, TokenType::Emit, TokenType::Show, TokenType::Task,
+            TokenType::Identifier("text".to_string()), TokenType::EOF,
+        ]);
+    }
+
+    #[test]
+    fn lex_errors_carry_their_position() {
+        match Tokenizer::new("a:\n    b\n  c").tokenize() {
+            Err(NairError::Lex { msg, line, .. }) => {
+                assert!(msg.contains("Inconsistent indentation"));
+                assert_eq!(line, 3);
+            }
+            other => panic!("expected a lex error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn scan_errors_stop_lexing() {
+        let err = Tokenizer::new("a is 1\nb is \"open").tokenize().unwrap_err();
+        assert!(matches!(&err, NairError::Lex { msg, line: 2, .. } if msg == "Unterminated string"), "{:?}", err);
+        let err = Tokenizer::new("a is 1 @ 2").tokenize().unwrap_err();
+        assert_eq!(err.to_string(), "Unexpected character: @ (line 1, column 9)");
+    }
+}
@@ -0,0 +1,77 @@
+use std::process::Command;
+
+// Runs `nair` with the given flags on a small script and returns what it printed
+fn inspect(flags: &[&str]) -> String {
+    let path = std::env::temp_dir().join(format!("nair-inspect-{}-{}.nr", std::process::id(), flags.join("")));
+    std::fs::write(&path, "x is 5\nshow x\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_nair"))
+        .args(flags)
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn tokens_only() {
+    let output = inspect(&["--tokens"]);
+    assert!(output.starts_with("Tokens:\n"), "{}", output);
+    assert!(output.contains("Identifier"), "{}", output);
+    assert!(!output.contains("AST:") && !output.contains("Bytecode:"), "{}", output);
+    // nothing was run
+    assert!(!output.contains("\n5\n"), "{}", output);
+}
+
+#[test]
+fn ast_only() {
+    let output = inspect(&["--ast"]);
+    assert!(output.starts_with("AST:\n"), "{}", output);
+    assert!(output.contains("Show"), "{}", output);
+    assert!(!output.contains("Tokens:") && !output.contains("Bytecode:"), "{}", output);
+}
+
+#[test]
+fn bytecode_only() {
+    let output = inspect(&["--bytecode"]);
+    assert!(output.starts_with("Bytecode:\n"), "{}", output);
+    assert!(output.contains("StoreVar"), "{}", output);
+    assert!(!output.contains("\n5\n"), "{}", output);
+}
+
+#[test]
+fn combined_flags_print_each_stage_in_order() {
+    let output = inspect(&["--ast", "--tokens"]);
+    let tokens = output.find("Tokens:").unwrap();
+    let ast = output.find("AST:").unwrap();
+    assert!(tokens < ast, "{}", output);
+    assert!(!output.contains("Bytecode:"), "{}", output);
+}
+
+#[test]
+fn fmt_prints_canonical_source() {
+    let output = inspect(&["fmt"]);
+    assert_eq!(output, "x is 5\nshow x\n");
+}
+
+#[test]
+fn compiled_file_runs_like_the_source() {
+    let dir = std::env::temp_dir();
+    let source = dir.join(format!("nair-compile-{}.nr", std::process::id()));
+    let nairc = dir.join(format!("nair-compile-{}.nairc", std::process::id()));
+    std::fs::write(&source, "Task twice requires n:\n    output n * 2\nshow twice(21), \"done\"\n").unwrap();
+    let nair = |args: &[&std::path::Path]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_nair")).args(args).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    nair(&["compile".as_ref(), &source, "-o".as_ref(), &nairc]);
+    let compiled = nair(&["run".as_ref(), &nairc]);
+    let direct = nair(&[&source]);
+    std::fs::remove_file(&source).unwrap();
+    std::fs::remove_file(&nairc).unwrap();
+    // only the file name in the first line differs
+    assert_eq!(compiled.lines().skip(1).collect::<Vec<_>>(), ["42 done"]);
+    assert_eq!(direct.lines().skip(1).collect::<Vec<_>>(), ["42 done"]);
+}
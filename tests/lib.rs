@@ -0,0 +1,53 @@
+use nair::{NairError, Value};
+
+#[test]
+fn eval_gives_back_what_was_shown() {
+    let values = nair::eval("x is 2\nshow x + 1").unwrap();
+    assert_eq!(values, vec![Value::Number(3.0)]);
+}
+
+#[test]
+fn shown_and_emitted_values_keep_their_order() {
+    let values = nair::eval("show \"a\", 1\nEmit true\nshow \"b\"").unwrap();
+    assert_eq!(values, vec![
+        Value::String("a".to_string()),
+        Value::Number(1.0),
+        Value::Boolean(true),
+        Value::String("b".to_string()),
+    ]);
+}
+
+#[test]
+fn compile_checks_without_running() {
+    assert!(matches!(nair::compile("show y"), Err(NairError::Type(_))));
+    let program = nair::compile("show 1 + 1").unwrap();
+    assert_eq!(program.run().unwrap(), vec![Value::Number(2.0)]);
+    assert_eq!(program.run().unwrap(), vec![Value::Number(2.0)]);
+}
+
+#[test]
+fn a_saved_program_runs_like_its_source() {
+    let source = "Object Dog:\n    build defaults name:\n        me.name is name\n    Task speak returns Text:\n        output \"{me.name} says woof\"\n\
+                  Task add requires a, each rest:\n    output a + length(rest)\n\
+                  d is new Dog with \"Rex\"\nshow d.speak(), add(1, 2, 3), 2.0, [1, \"two\"]\nloop each n in [3, 4]:\n    when n > 3:\n        Emit n * index\n";
+    let nairc = nair::compile(source).unwrap().to_nairc().unwrap();
+    let loaded = nair::Program::from_nairc(&nairc).unwrap();
+    assert_eq!(loaded.run().unwrap(), nair::eval(source).unwrap());
+    // saving again gives the same file
+    assert_eq!(loaded.to_nairc().unwrap(), nairc);
+}
+
+#[test]
+fn a_damaged_program_is_refused() {
+    assert!(matches!(nair::Program::from_nairc("{\"version\":1}"), Err(NairError::Runtime(_))));
+    let nairc = nair::compile("show 1").unwrap().to_nairc().unwrap();
+    let older = nairc.replacen("\"version\":2", "\"version\":1", 1);
+    assert!(nair::Program::from_nairc(&older).is_err());
+}
+
+#[test]
+fn an_endless_loop_runs_out_of_budget() {
+    let program = nair::compile("n is 0\nloop:\n    n becomes n + 1").unwrap();
+    let err = program.run_on(nair::Runtime::new().with_instruction_budget(10_000)).unwrap_err();
+    assert!(matches!(&err, NairError::Runtime(msg) if msg == "Instruction budget exceeded"), "{:?}", err);
+}
@@ -0,0 +1,107 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Feeds lines to an interactive session and returns everything it printed, errors last
+fn repl(input: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_nair"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    String::from_utf8_lossy(&output.stdout).into_owned() + &String::from_utf8_lossy(&output.stderr)
+}
+
+#[test]
+fn dump_prints_bytecode_without_running_it() {
+    let output = repl(".dump x is\n.dump x is 5\n.vars\n.exit\n");
+    assert!(output.contains("StoreVar"), "{}", output);
+    // the broken line is reported, and neither line ran
+    assert!(output.contains("Expected"), "{}", output);
+    assert!(!output.contains("x as Whole is 5"), "{}", output);
+    assert!(output.contains("Goodbye!"), "{}", output);
+}
+
+#[test]
+fn multi_line_block() {
+    let output = repl("x is 8\nwhen x is 8:\n    show x\n\n.exit\n");
+    assert!(output.contains("... "), "{}", output);
+    assert!(output.contains("... 8\n"), "{}", output);
+}
+
+#[test]
+fn multi_line_task_definition() {
+    let output = repl("Task double requires n as Whole returns Whole:\n    twice is n * 2\n    output twice\n\nshow double(21)\n.exit\n");
+    assert!(output.contains("> ... ... ... > 42\n"), "{}", output);
+}
+
+#[test]
+fn history_lists_entries_in_order() {
+    let output = repl("x is 1\nshow x + 1\n.history\n.exit\n");
+    assert!(output.contains("   1  x is 1\n   2  show x + 1\n"), "{}", output);
+    assert!(!output.contains(".history\n"), "{}", output);
+}
+
+#[test]
+fn bang_reruns_an_entry() {
+    let output = repl("x is 1\nshow x + 1\n!2\n.exit\n");
+    assert_eq!(output.matches("2\n").count(), 2, "{}", output);
+}
+
+#[test]
+fn reset_clears_variables() {
+    let output = repl("x is 1\n.vars\n.reset\nshow x\n.exit\n");
+    assert!(output.contains("x as Whole is 1"), "{}", output);
+    assert!(output.contains("Undefined variable: x"), "{}", output);
+}
+
+#[test]
+fn time_reports_the_elapsed_time() {
+    let output = repl(".time show 1\n.time 2 + 3\n.exit\n");
+    assert!(output.contains("> 1\nTook "), "{}", output);
+    assert!(output.contains("> 5\nTook "), "{}", output);
+    let millis: f64 = output.split("Took ").nth(1).unwrap().split(' ').next().unwrap().parse().unwrap();
+    assert!(millis >= 0.0, "{}", output);
+}
+
+#[test]
+fn time_reports_errors_and_carries_on() {
+    let output = repl(".time show nope\nshow 1\n.exit\n");
+    assert!(output.contains("Undefined variable: nope"), "{}", output);
+    assert!(output.contains("> 1\n"), "{}", output);
+    assert!(output.contains("Goodbye!"), "{}", output);
+}
+
+#[test]
+fn show_separates_values_with_spaces() {
+    let output = repl("show \"a\", 1, true\nshow \"alone\"\n.exit\n");
+    assert!(output.contains("> a 1 true\n"), "{}", output);
+    assert!(output.contains("> alone\n"), "{}", output);
+}
+
+#[test]
+fn doc_shows_a_task_description() {
+    let output = repl("Task greet:\n    about \"Says hello\"\n    show 1\n\n.doc greet\n.doc nothing_here\n.exit\n");
+    assert!(output.contains("> Says hello\n"), "{}", output);
+    assert!(output.contains("no Task or Object named nothing_here"), "{}", output);
+}
+
+#[test]
+fn step_shows_the_stack_after_each_instruction() {
+    let output = repl(".step 1 + 2\n\n\n\n.exit\n");
+    let grown = output.find("stack: [1, 2]\n").unwrap();
+    let collapsed = output.find("stack: [3]\n").unwrap();
+    assert!(output.find("stack: [1]\n").unwrap() < grown, "{}", output);
+    assert!(grown < collapsed, "{}", output);
+    assert!(output.contains("Add\n"), "{}", output);
+}
+
+#[test]
+fn step_reports_errors_and_carries_on() {
+    let output = repl(".step show (\n.step show 1 / 0\n\n\n\nshow \"still here\"\n.exit\n");
+    assert!(output.contains("Division by zero"), "{}", output);
+    assert!(output.contains("> still here\n"), "{}", output);
+    assert!(output.contains("Goodbye!"), "{}", output);
+}